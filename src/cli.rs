@@ -5,29 +5,37 @@ use std::{fmt::Debug, io};
 
 // Reference the modules for the subcommands
 mod center_opts;
+mod checkpoint_opts;
 mod clear_kinetics_opts;
+mod cpg_pileup_opts;
 mod ddda_to_m6a_opts;
 mod decorator_opts;
 mod extract_opts;
 mod fire_opts;
 mod footprint_opts;
+mod nrl_opts;
 mod nucleosome_opts;
 mod pileup_opts;
 mod predict_opts;
+mod qc_merge_opts;
 mod qc_opts;
 mod strip_basemods_opts;
 
 // include the subcommand modules as top level functions and structs in the cli module
 pub use center_opts::*;
+pub use checkpoint_opts::*;
 pub use clear_kinetics_opts::*;
+pub use cpg_pileup_opts::*;
 pub use ddda_to_m6a_opts::*;
 pub use decorator_opts::*;
 pub use extract_opts::*;
 pub use fire_opts::*;
 pub use footprint_opts::*;
+pub use nrl_opts::*;
 pub use nucleosome_opts::*;
 pub use pileup_opts::*;
 pub use predict_opts::*;
+pub use qc_merge_opts::*;
 pub use qc_opts::*;
 pub use strip_basemods_opts::*;
 
@@ -119,10 +127,16 @@ pub enum Commands {
     Footprint(FootprintOptions),
     /// Collect QC metrics from a fiberseq bam file
     Qc(QcOpts),
+    /// Merge the QC metrics of `ft qc` runs on parts of the data into one report
+    QcMerge(QcMergeOpts),
+    /// Estimate the nucleosome repeat length and regularity of each fiber, and optionally of regions in a bed file
+    Nrl(NrlOptions),
     /// Make decorated bed files for fiberseq data
     TrackDecorators(DecoratorOptions),
     /// Make a pileup track of Fiber-seq features from a FIRE bam
     Pileup(PileupOptions),
+    /// Make a pileup of the 5mC methylation of each CpG in the reference
+    CpgPileup(CpgPileupOptions),
     /// Remove HiFi kinetics tags from the input bam file
     ClearKinetics(ClearKineticsOptions),
     /// Strip out select base modifications
@@ -0,0 +1,26 @@
+use clap::Args;
+use std::fmt::Debug;
+
+pub static CHECKPOINT_INTERVAL: &str = "60";
+
+#[derive(Args, Debug, Clone)]
+pub struct CheckpointOptions {
+    /// Periodically record progress in a checkpoint file next to the output bam (<OUT>.ckpt).
+    /// If the checkpoint file already exists the interrupted run is resumed instead of starting over.
+    ///
+    /// Requires the output to be a file and the same input and options as the interrupted run.
+    #[clap(long, help_heading = "Checkpoint-Options")]
+    pub checkpoint: bool,
+    /// Seconds between checkpoints
+    #[clap(long, default_value = CHECKPOINT_INTERVAL, help_heading = "Checkpoint-Options")]
+    pub checkpoint_interval: u64,
+}
+
+impl std::default::Default for CheckpointOptions {
+    fn default() -> Self {
+        Self {
+            checkpoint: false,
+            checkpoint_interval: CHECKPOINT_INTERVAL.parse().unwrap(),
+        }
+    }
+}
@@ -0,0 +1,34 @@
+use crate::utils::input_bam::InputBam;
+use clap::Args;
+use std::fmt::Debug;
+
+pub static CPG_MIN_COVERAGE: &str = "4";
+pub static CPG_MOD_ML: &str = "128";
+
+#[derive(Args, Debug)]
+pub struct CpgPileupOptions {
+    #[clap(flatten)]
+    pub input: InputBam,
+    /// Reference fasta the bam is aligned to, used to find the CpG sites
+    #[clap(short, long)]
+    pub reference: String,
+    /// Output bed file with the methylation of each CpG site
+    #[clap(short, long, default_value = "-")]
+    pub out: String,
+    /// Region string to make a pileup of. e.g. chr1:1-1000 or chr1:1-1,000
+    /// If not provided will make a pileup of the whole genome
+    #[clap(default_value = None)]
+    pub rgn: Option<String>,
+    /// Combine the calls on the forward and reverse strand of each CpG into one record
+    #[clap(long)]
+    pub combine_strands: bool,
+    /// Add records with the methylation of each haplotype (HP tag)
+    #[clap(long)]
+    pub haps: bool,
+    /// Minimum number of fibers with a call at a CpG to report it
+    #[clap(long, default_value = CPG_MIN_COVERAGE)]
+    pub min_coverage: usize,
+    /// Calls with an ML value at or above this are counted as modified, otherwise as unmodified
+    #[clap(long, default_value = CPG_MOD_ML)]
+    pub mod_ml: u8,
+}
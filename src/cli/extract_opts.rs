@@ -1,3 +1,4 @@
+use crate::utils::basemods::parse_mod_code;
 use crate::utils::input_bam::InputBam;
 use clap::Args;
 use std::fmt::Debug;
@@ -23,6 +24,11 @@ pub struct ExtractOptions {
     /// Output path for 5mC (CpG, primrose) bed12
     #[clap(short, long)]
     pub cpg: Option<String>,
+    /// Output path for a bed12 of another base modification, given as CODE=PATH, e.g. 5hmC=5hmc.bed.gz.
+    /// CODE can be any modification code in the MM tag (e.g. h), ChEBI id (e.g. 21839), or known name (e.g. 5hmC, 4mC).
+    /// Can be used multiple times.
+    #[clap(long, value_parser = parse_mod_path, value_name = "CODE=PATH")]
+    pub basemod: Vec<(String, String)>,
     /// Output path for methylation sensitive patch (msp) bed12
     #[clap(long)]
     pub msp: Option<String>,
@@ -39,3 +45,11 @@ pub struct ExtractOptions {
     #[clap(short, long, help_heading = "All-Format-Options")]
     pub simplify: bool,
 }
+
+/// parse CODE=PATH pairs for `--basemod`
+fn parse_mod_path(s: &str) -> Result<(String, String), String> {
+    let (code, path) = s
+        .split_once('=')
+        .ok_or_else(|| format!("{s} is not of the form CODE=PATH"))?;
+    Ok((parse_mod_code(code)?, path.to_string()))
+}
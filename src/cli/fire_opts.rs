@@ -1,7 +1,14 @@
+use super::checkpoint_opts::CheckpointOptions;
 use crate::utils::input_bam::InputBam;
 use clap::Args;
 use std::fmt::Debug;
 
+pub static WIDTH_BIN: &str = "40";
+pub static BIN_NUM: &str = "9";
+pub static BEST_WINDOW_SIZE: &str = "100";
+pub static MIN_MSP_LENGTH_FOR_FIRE: &str = "85";
+pub static ONT_MIN_MSP_LENGTH_FOR_FIRE: &str = "100";
+
 #[derive(Args, Debug)]
 pub struct FireOptions {
     #[clap(flatten)]
@@ -9,8 +16,11 @@ pub struct FireOptions {
     /// Output file (BAM by default, table of MSP features if `--feats-to-text` is used, and bed9 + if `--extract`` is used)
     #[clap(default_value = "-")]
     pub out: String,
+    #[clap(flatten)]
+    pub checkpoint: CheckpointOptions,
     /// Use a ONT heuristic adjustment for FIRE calling.
     /// This adjusts the observed number of m6A counts by adding pseudo counts to account for the single stranded nature of ONT data.
+    /// Also checks that the m6A calls are single strand (e.g. Dorado `A+a.` or `A+a?` MM tags) and uses ONT defaults for other FIRE options.
     #[clap(long, env)]
     pub ont: bool,
     /// Use a human model for FIRE calling
@@ -37,8 +47,15 @@ pub struct FireOptions {
     /// Skip reads without an average MSP size greater than `N`
     #[clap(long, default_value = "0", env)]
     pub min_ave_msp_size: i64,
+    /// Add the estimated m6A labelling efficiency of each read to the `le` tag (float).
+    /// This is the m6A rate of the A/T bases in MSPs corrected for the background rate in nucleosomes.
+    #[clap(long)]
+    pub labelling_tag: bool,
+    /// Skip reads with an estimated m6A labelling efficiency less than `X`. Reads without MSPs or nucleosomes are kept.
+    #[clap(long)]
+    pub min_labelling_efficiency: Option<f64>,
     /// Width of bin for feature collection
-    #[clap(short, long, default_value = "40", env, 
+    #[clap(short, long, default_value = WIDTH_BIN, env,
         default_value_ifs([
             ("human", "true", "40"),
             ("yeast", "true", "100")
@@ -46,17 +63,21 @@ pub struct FireOptions {
     )]
     pub width_bin: i64,
     /// Number of bins to collect
-    #[clap(short, long, default_value = "9", env)]
+    #[clap(short, long, default_value = BIN_NUM, env)]
     pub bin_num: i64,
     /// Calculate stats for the highest X bp window within each MSP
     /// Should be a fair amount higher than the expected linker length.
-    #[clap(long, default_value = "100", env)]
+    #[clap(long, default_value = BEST_WINDOW_SIZE, env)]
     pub best_window_size: i64,
     /// Use 5mC data in FIREs
     #[clap(short, long, hide = true)]
     pub use_5mc: bool,
     /// Minium length of msp to call a FIRE
-    #[clap(long, default_value = "85", env)]
+    #[clap(long, default_value = MIN_MSP_LENGTH_FOR_FIRE, env,
+        default_value_ifs([
+            ("ont", "true", ONT_MIN_MSP_LENGTH_FOR_FIRE),
+        ])
+    )]
     pub min_msp_length_for_positive_fire_call: i64,
     /// Optional path to a model json file.
     /// If not provided ft will use the default model (recommended).
@@ -72,6 +93,7 @@ impl Default for FireOptions {
         Self {
             input: Default::default(),
             out: "-".to_string(),
+            checkpoint: CheckpointOptions::default(),
             ont: false,
             human: false,
             yeast: false,
@@ -81,11 +103,13 @@ impl Default for FireOptions {
             skip_no_m6a: false,
             min_msp: 0,
             min_ave_msp_size: 0,
-            width_bin: 40,
-            bin_num: 9,
-            best_window_size: 100,
+            labelling_tag: false,
+            min_labelling_efficiency: None,
+            width_bin: WIDTH_BIN.parse().unwrap(),
+            bin_num: BIN_NUM.parse().unwrap(),
+            best_window_size: BEST_WINDOW_SIZE.parse().unwrap(),
             use_5mc: false,
-            min_msp_length_for_positive_fire_call: 85,
+            min_msp_length_for_positive_fire_call: MIN_MSP_LENGTH_FOR_FIRE.parse().unwrap(),
             model: None,
             fdr_table: None,
         }
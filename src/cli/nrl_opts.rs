@@ -0,0 +1,48 @@
+use crate::utils::input_bam::InputBam;
+use clap::Args;
+use std::fmt::Debug;
+
+pub static MIN_NRL: &str = "140";
+pub static MAX_NRL: &str = "250";
+pub static MAX_NUC_SPACING: &str = "400";
+
+#[derive(Args, Debug, Clone)]
+pub struct NrlParameters {
+    /// Smallest nucleosome repeat length to consider when finding the m6A auto-correlation peak
+    #[clap(long, default_value = MIN_NRL)]
+    pub min_nrl: usize,
+    /// Largest nucleosome repeat length to consider when finding the m6A auto-correlation peak
+    #[clap(long, default_value = MAX_NRL)]
+    pub max_nrl: usize,
+    /// Distances between adjacent nucleosomes larger than this are treated as a break in the
+    /// nucleosome array (e.g. a regulatory element) and not used as a repeat length
+    #[clap(long, default_value = MAX_NUC_SPACING)]
+    pub max_nuc_spacing: i64,
+}
+
+impl std::default::Default for NrlParameters {
+    fn default() -> Self {
+        Self {
+            min_nrl: MIN_NRL.parse().unwrap(),
+            max_nrl: MAX_NRL.parse().unwrap(),
+            max_nuc_spacing: MAX_NUC_SPACING.parse().unwrap(),
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct NrlOptions {
+    #[clap(flatten)]
+    pub input: InputBam,
+    /// Output bed file with the nucleosome repeat length and regularity of each fiber
+    #[clap(default_value = "-")]
+    pub out: String,
+    #[clap(flatten)]
+    pub nrl: NrlParameters,
+    /// Bed file of regions to aggregate the nucleosome repeat length over. Requires an indexed bam.
+    #[clap(short, long, requires = "region_out")]
+    pub bed: Option<String>,
+    /// Output bed file with the nucleosome repeat length and regularity of each region in --bed
+    #[clap(short, long, requires = "bed")]
+    pub region_out: Option<String>,
+}
@@ -7,14 +7,30 @@ pub static COMBO_NUC_LEN: &str = "100";
 pub static MIN_DIST_ADDED: &str = "25";
 pub static DIST_FROM_END: &str = "45";
 pub static ALLOWED_SKIPS: &str = "-1";
+pub static NUC_CALLER: &str = "heuristic";
+// ONT m6A calls are made on only one strand so about half as many A/T bases are assessed,
+// which makes m6A free stretches in linkers longer than with PacBio data. These are the PacBio
+// defaults plus 10 bp, about two extra gaps between assessed As (~4 bp apart on one strand
+// rather than ~2 bp on both). They are not fit to a benchmark, so set -n and -c explicitly
+// when values calibrated on your data are available.
+pub static ONT_NUC_LEN: &str = "85";
+pub static ONT_COMBO_NUC_LEN: &str = "110";
 
 #[derive(Args, Debug, Clone)]
 pub struct NucleosomeParameters {
     /// Minium nucleosome length
-    #[clap(short, long, default_value = NUC_LEN)]
+    #[clap(short, long, default_value = NUC_LEN,
+        default_value_ifs([
+            ("ont", "true", ONT_NUC_LEN),
+        ])
+    )]
     pub nucleosome_length: i64,
     /// Minium nucleosome length when combining over a single m6A
-    #[clap(short, long, default_value = COMBO_NUC_LEN)]
+    #[clap(short, long, default_value = COMBO_NUC_LEN,
+        default_value_ifs([
+            ("ont", "true", ONT_COMBO_NUC_LEN),
+        ])
+    )]
     pub combined_nucleosome_length: i64,
     /// Minium distance needed to add to an already existing nuc by crossing an m6a
     #[clap(long, default_value = MIN_DIST_ADDED)]
@@ -25,6 +41,10 @@ pub struct NucleosomeParameters {
     /// Most m6A events we can skip over to get to the nucleosome length when using D-segment algorithm. 2 is often a good value, negative values disable D-segment for the simple caller.
     #[clap(short, long, default_value = ALLOWED_SKIPS, hide = true)]
     pub allowed_m6a_skips: i64,
+    /// Nucleosome caller to use. The hmm caller fits a nucleosome/linker HMM to each fiber,
+    /// uses --nucleosome-length as the minimum nucleosome length, and adds a per nucleosome quality (nq tag).
+    #[clap(long, default_value = NUC_CALLER, value_parser(["heuristic", "hmm"]))]
+    pub nucleosome_caller: String,
 }
 
 impl std::default::Default for NucleosomeParameters {
@@ -35,6 +55,7 @@ impl std::default::Default for NucleosomeParameters {
             min_distance_added: MIN_DIST_ADDED.parse().unwrap(),
             distance_from_end: DIST_FROM_END.parse().unwrap(),
             allowed_m6a_skips: ALLOWED_SKIPS.parse().unwrap(),
+            nucleosome_caller: NUC_CALLER.to_string(),
         }
     }
 }
@@ -48,4 +69,36 @@ pub struct AddNucleosomeOptions {
     pub out: String,
     #[clap(flatten)]
     pub nuc: NucleosomeParameters,
+    /// Input is ONT data with single strand m6A calls (e.g. Dorado `A+a.` or `A+a?` MM tags).
+    /// The nucleosome lengths default to ONT values instead of PacBio values.
+    #[clap(long, env)]
+    pub ont: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[clap(flatten)]
+        opts: AddNucleosomeOptions,
+    }
+
+    #[test]
+    fn test_ont_defaults() {
+        let nuc = |args: &[&str]| Cli::parse_from(args).opts.nuc;
+        let pacbio = nuc(&["ft", "in.bam"]);
+        assert_eq!(pacbio.nucleosome_length, NUC_LEN.parse::<i64>().unwrap());
+        let ont = nuc(&["ft", "in.bam", "--ont"]);
+        assert_eq!(ont.nucleosome_length, ONT_NUC_LEN.parse::<i64>().unwrap());
+        assert_eq!(
+            ont.combined_nucleosome_length,
+            ONT_COMBO_NUC_LEN.parse::<i64>().unwrap()
+        );
+        // an explicit value is kept even if it equals the PacBio default
+        let ont = nuc(&["ft", "in.bam", "--ont", "-n", NUC_LEN]);
+        assert_eq!(ont.nucleosome_length, NUC_LEN.parse::<i64>().unwrap());
+    }
 }
@@ -1,7 +1,14 @@
+use crate::utils::basemods::parse_mod_code;
 use crate::utils::input_bam::InputBam;
 use clap::Args;
 use std::fmt::Debug;
 
+pub static MAX_PEAK_FDR: &str = "0.05";
+pub static PEAK_MERGE_DIST: &str = "0";
+pub static SHUFFLE_SEED: &str = "42";
+pub static MAX_DIFF_FDR: &str = "0.05";
+pub static MIN_DIFF_COVERAGE: &str = "4";
+
 #[derive(Args, Debug)]
 pub struct PileupOptions {
     #[clap(flatten)]
@@ -10,6 +17,10 @@ pub struct PileupOptions {
     /// If not provided will make a pileup of the whole genome
     #[clap(default_value = None)]
     pub rgn: Option<String>,
+    /// Bed file of regions to make a pileup of, e.g. a set of enhancers.
+    /// Overlapping regions are merged and the regions are processed in parallel.
+    #[clap(long, conflicts_with = "rgn")]
+    pub bed: Option<String>,
     /// Output file
     #[clap(short, long, default_value = "-")]
     pub out: String,
@@ -19,9 +30,24 @@ pub struct PileupOptions {
     /// include 5mC calls
     #[clap(short, long)]
     pub cpg: bool,
+    /// include calls of another base modification, any modification code in the MM tag (e.g. h),
+    /// ChEBI id (e.g. 21839), or known name (e.g. 5hmC, 4mC). Can be used multiple times.
+    #[clap(long, value_parser = parse_mod_code)]
+    pub basemod: Vec<String>,
     /// For each column add two new columns with the hap1 and hap2 specific data.
     #[clap(long)]
     pub haps: bool,
+    /// Add columns for each group of fibers, defined by the value of a string tag (e.g. RG).
+    /// The group name is used as a prefix of the column names.
+    #[clap(long, conflicts_with = "group_tsv", help_heading = "Group-Options")]
+    pub group_by: Option<String>,
+    /// Add columns for each group of fibers, defined by a tab separated file of read names and groups
+    #[clap(long, help_heading = "Group-Options")]
+    pub group_tsv: Option<String>,
+    /// Comma separated list of the groups to include, in order. Defaults to the read groups in the bam header
+    /// for --group-by RG or the groups in --group-tsv, and is required for --group-by with any other tag.
+    #[clap(long, value_delimiter = ',', help_heading = "Group-Options")]
+    pub groups: Vec<String>,
     /// Keep zero coverage regions
     #[clap(short, long)]
     pub keep_zeros: bool,
@@ -37,8 +63,21 @@ pub struct PileupOptions {
     ///
     /// The bed file should have the following format:
     /// #chrom shuffled_start shuffled_end read_name original_start
-    #[clap(long)]
+    #[clap(long, conflicts_with = "auto_shuffle")]
     pub shuffle: Option<String>,
+    /// Shuffle the fiber-seq data by randomly placing each fiber within its chromosome
+    /// instead of reading the shuffled positions from a --shuffle bed file
+    #[clap(long, help_heading = "Shuffle-Options")]
+    pub auto_shuffle: bool,
+    /// Bed file of regions that shuffled fibers should not overlap (e.g. gaps in the assembly)
+    #[clap(long, requires = "auto_shuffle", help_heading = "Shuffle-Options")]
+    pub shuffle_exclude: Option<String>,
+    /// Seed for the random placement of shuffled fibers
+    #[clap(long, default_value = SHUFFLE_SEED, help_heading = "Shuffle-Options")]
+    pub shuffle_seed: u64,
+    /// Write the shuffled positions of the fibers to this bed file, which can be reused with --shuffle
+    #[clap(long, requires = "auto_shuffle", help_heading = "Shuffle-Options")]
+    pub shuffle_out: Option<String>,
     /// Output a rolling max of the score column over X bases
     #[clap(long)]
     pub rolling_max: Option<usize>,
@@ -48,4 +87,50 @@ pub struct PileupOptions {
     /// No NUC columns
     #[clap(long)]
     pub no_nuc: bool,
+    /// Add columns with the number of fibers with an A/T assessed for m6A at each base (at_coverage)
+    /// and the fraction of them with an m6A call (m6a_fraction)
+    #[clap(long)]
+    pub m6a_fraction: bool,
+    /// Also split the m6A fraction by the base of the read in the orientation of the reference,
+    /// A (a_coverage, m6a_fraction_a) or T (t_coverage, m6a_fraction_t)
+    #[clap(long, requires = "m6a_fraction")]
+    pub m6a_fraction_by_strand: bool,
+    /// Call FIRE peaks and write them to this file in narrowPeak format. The FDR of each score threshold
+    /// is estimated by comparing the observed scores to the scores of the shuffled track (--shuffle or --auto-shuffle).
+    #[clap(long, help_heading = "Peak-Options")]
+    pub peaks: Option<String>,
+    /// Maximum FDR of the score threshold used to call peaks
+    #[clap(long, default_value = MAX_PEAK_FDR, help_heading = "Peak-Options")]
+    pub max_peak_fdr: f64,
+    /// Merge peaks that are within this many bases of each other
+    #[clap(long, default_value = PEAK_MERGE_DIST, help_heading = "Peak-Options")]
+    pub peak_merge_dist: i64,
+    /// Test for differential FIRE accessibility between two groups of fibers and write the
+    /// significant elements to this bed file. Each stretch of bases with the same coverage and FIRE coverage
+    /// is tested with a Fisher's exact test and the p-values are corrected with Benjamini-Hochberg.
+    #[clap(long, help_heading = "Diff-Options")]
+    pub diff: Option<String>,
+    /// The two groups to compare, e.g. two read groups from --group-by RG. Defaults to H1,H2 with --haps.
+    #[clap(long, value_delimiter = ',', help_heading = "Diff-Options")]
+    pub diff_groups: Vec<String>,
+    /// Maximum FDR of differential elements
+    #[clap(long, default_value = MAX_DIFF_FDR, help_heading = "Diff-Options")]
+    pub max_diff_fdr: f64,
+    /// Minimum coverage in both groups to test a base
+    #[clap(long, default_value = MIN_DIFF_COVERAGE, help_heading = "Diff-Options")]
+    pub min_diff_coverage: i32,
+    /// Write each column of the pileup to its own bedGraph file, {prefix}.{column}.bedGraph
+    #[clap(long, help_heading = "Track-Options")]
+    pub bedgraph: Option<String>,
+    /// Write each column of the pileup to its own bigWig file, {prefix}.{column}.bw.
+    /// Chromosome sizes are taken from the bam header.
+    #[clap(long, help_heading = "Track-Options")]
+    pub bigwig: Option<String>,
+}
+
+impl PileupOptions {
+    /// whether a shuffled track is being made
+    pub fn shuffling(&self) -> bool {
+        self.shuffle.is_some() || self.auto_shuffle
+    }
 }
@@ -1,3 +1,5 @@
+use super::checkpoint_opts::CheckpointOptions;
+use super::fire_opts::*;
 use super::nucleosome_opts::NucleosomeParameters;
 use crate::utils::input_bam::InputBam;
 use clap::Args;
@@ -12,20 +14,83 @@ pub struct PredictM6AOptions {
     pub out: String,
     #[clap(flatten)]
     pub nuc: NucleosomeParameters,
+    #[clap(flatten)]
+    pub checkpoint: CheckpointOptions,
+    /// Use the m6A model for this PacBio chemistry instead of inferring it from the BINDINGKIT in the bam header
+    #[clap(long, value_parser(["2.0", "2.2", "3.2", "revio"]))]
+    pub chemistry: Option<String>,
+    /// YAML or JSON file mapping additional BINDINGKIT values to a chemistry, e.g. {"103-426-500": "revio"}.
+    /// Entries override the built-in binding kits.
+    #[clap(long, env = "FT_CHEMISTRY_REGISTRY")]
+    pub chemistry_registry: Option<String>,
+    /// Do not call FIREs (aq tag) after predicting m6A, nucleosomes, and MSPs
+    #[clap(long, help_heading = "FIRE-Options")]
+    pub skip_fire: bool,
+    /// Optional path to a FIRE model json file, see `ft fire --model`
+    #[clap(
+        long,
+        env = "FIRE_MODEL",
+        requires = "fire_fdr_table",
+        help_heading = "FIRE-Options"
+    )]
+    pub fire_model: Option<String>,
+    /// Optional path to a FDR table for the FIRE model, see `ft fire --fdr-table`
+    #[clap(long, env = "FDR_TABLE", help_heading = "FIRE-Options")]
+    pub fire_fdr_table: Option<String>,
+    /// Width of bin for FIRE feature collection, see `ft fire --width-bin`
+    #[clap(long, default_value = WIDTH_BIN, env = "WIDTH_BIN", help_heading = "FIRE-Options")]
+    pub fire_width_bin: i64,
+    /// Number of bins to collect for FIRE features, see `ft fire --bin-num`
+    #[clap(long, default_value = BIN_NUM, env = "BIN_NUM", help_heading = "FIRE-Options")]
+    pub fire_bin_num: i64,
+    /// Size of the best and worst m6A windows within each MSP, see `ft fire --best-window-size`
+    #[clap(long, default_value = BEST_WINDOW_SIZE, env = "BEST_WINDOW_SIZE", help_heading = "FIRE-Options")]
+    pub fire_best_window_size: i64,
+    /// Minium length of msp to call a FIRE, see `ft fire --min-msp-length-for-positive-fire-call`
+    #[clap(long, default_value = MIN_MSP_LENGTH_FOR_FIRE, env = "MIN_MSP_LENGTH_FOR_POSITIVE_FIRE_CALL", help_heading = "FIRE-Options")]
+    pub fire_min_msp_length: i64,
+    /// Add the estimated m6A labelling efficiency of each read to the `le` tag, see `ft fire --labelling-tag`
+    #[clap(long, conflicts_with = "skip_fire", help_heading = "FIRE-Options")]
+    pub labelling_tag: bool,
+    /// Skip reads with an estimated m6A labelling efficiency less than `X`, see `ft fire --min-labelling-efficiency`
+    #[clap(long, conflicts_with = "skip_fire", help_heading = "FIRE-Options")]
+    pub min_labelling_efficiency: Option<f64>,
     /// Keep hifi kinetics data
     #[clap(short, long)]
     pub keep: bool,
+    /// Add the raw m6A model output for every A and T in the read to a float array tag (mp:B:f).
+    /// Values are in the order of the A/T bases in the original read sequence and are not filtered or converted with the precision table, so the calls can be recalibrated later without re-running the model.
+    #[clap(long)]
+    pub raw_probabilities: bool,
     /// Force a different minimum ML score
     #[clap(long, help_heading = "Developer-Options")]
     pub force_min_ml_score: Option<u8>,
     /// Keep all m6A calls regardless of how low the ML value is
     #[clap(long, help_heading = "Developer-Options")]
     pub all_calls: bool,
+    /// Path to a custom m6A model to use instead of the built-in model for the PacBio chemistry.
+    ///
+    /// The model must be a Burn binary record (.bin) with the same architecture and WINDOW x LAYERS input as the built-in models, e.g. the record made by burn-import from a compatible ONNX file.
+    #[clap(
+        long,
+        env = "FT_MODEL",
+        requires = "precision_table",
+        help_heading = "Developer-Options"
+    )]
+    pub model: Option<String>,
+    /// Path to a precision table JSON to use in place of the built-in table for the PacBio chemistry. Required with `--model`.
+    #[clap(long, env = "FT_JSON", help_heading = "Developer-Options")]
+    pub precision_table: Option<String>,
     /// Number of reads to include in batch prediction
     ///
     /// Increasing improves GPU performance at the cost of memory.
     #[clap(short, long, default_value = "1", help_heading = "Developer-Options")]
     pub batch_size: usize,
+    /// Number of chunks of reads that can wait between the reading, prediction, and writing steps
+    ///
+    /// Increasing lets reading and writing run further ahead of prediction at the cost of memory.
+    #[clap(long, default_value = "2", help_heading = "Developer-Options")]
+    pub queue_size: usize,
     /// Skip the actual prediction step to allow for testing the speed of other parts of the code
     #[clap(long, help_heading = "Developer-Options", hide = true)]
     pub fake: bool,
@@ -37,11 +102,44 @@ impl std::default::Default for PredictM6AOptions {
             input: InputBam::default(),
             out: "-".to_string(),
             nuc: NucleosomeParameters::default(),
+            checkpoint: CheckpointOptions::default(),
+            chemistry: None,
+            chemistry_registry: None,
+            skip_fire: false,
+            fire_model: None,
+            fire_fdr_table: None,
+            fire_width_bin: WIDTH_BIN.parse().unwrap(),
+            fire_bin_num: BIN_NUM.parse().unwrap(),
+            fire_best_window_size: BEST_WINDOW_SIZE.parse().unwrap(),
+            fire_min_msp_length: MIN_MSP_LENGTH_FOR_FIRE.parse().unwrap(),
+            labelling_tag: false,
+            min_labelling_efficiency: None,
             keep: false,
+            raw_probabilities: false,
             force_min_ml_score: None,
             all_calls: false,
+            model: None,
+            precision_table: None,
             batch_size: 1,
+            queue_size: 2,
             fake: false,
         }
     }
 }
+
+impl PredictM6AOptions {
+    /// FIRE options equivalent to running `ft fire` with the same FIRE settings
+    pub fn fire_options(&self) -> FireOptions {
+        FireOptions {
+            model: self.fire_model.clone(),
+            fdr_table: self.fire_fdr_table.clone(),
+            width_bin: self.fire_width_bin,
+            bin_num: self.fire_bin_num,
+            best_window_size: self.fire_best_window_size,
+            min_msp_length_for_positive_fire_call: self.fire_min_msp_length,
+            labelling_tag: self.labelling_tag,
+            min_labelling_efficiency: self.min_labelling_efficiency,
+            ..Default::default()
+        }
+    }
+}
@@ -0,0 +1,15 @@
+use clap::Args;
+use std::fmt::Debug;
+
+#[derive(Args, Debug)]
+pub struct QcMergeOpts {
+    /// Outputs of `ft qc` to merge, e.g. from runs on each chromosome or SMRT cell
+    #[clap(required = true)]
+    pub inputs: Vec<String>,
+    /// Output text file with the merged QC metrics, in the same format as `ft qc`
+    #[clap(short, long, default_value = "-")]
+    pub out: String,
+    /// Also write a JSON report of the merged QC metrics, see `ft qc --json`
+    #[clap(long)]
+    pub json: Option<String>,
+}
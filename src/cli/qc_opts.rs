@@ -2,6 +2,9 @@ use crate::utils::input_bam::InputBam;
 use clap::Args;
 use std::fmt::Debug;
 
+pub static ACF_BOOTSTRAPS: &str = "100";
+pub static ACF_CONFIDENCE: &str = "0.95";
+
 #[derive(Args, Debug)]
 pub struct QcOpts {
     #[clap(flatten)]
@@ -9,6 +12,11 @@ pub struct QcOpts {
     /// Output text file with QC metrics. The format is a tab-separated file with the following columns: "statistic\tvalue\tcount" where "statistic" is the name of the metric, "value" is the value of the metric, and "count" is the number of times the metric was observed.
     #[clap(default_value = "-")]
     pub out: String,
+    /// Also write a JSON report with the histograms and summary statistics (e.g. fiber length N50,
+    /// median nucleosome length, fraction phased, MSP and FIRE fractions, and the m6A ACF peak).
+    /// The report has a versioned schema ("schema_version") for aggregating many samples.
+    #[clap(long)]
+    pub json: Option<String>,
     /// Calculate the auto-correlation function of the m6A marks in the fiber-seq data.
     #[clap(long)]
     pub acf: bool,
@@ -24,12 +32,49 @@ pub struct QcOpts {
     /// After sampling the first "acf-max-reads" randomly sample one of every "acf-sample-rate" reads and replace one of the previous reads at random.
     #[clap(long, default_value = "100")]
     pub acf_sample_rate: f32,
+    /// Number of bootstrap resamples of the ACF reads used for the confidence interval of each lag
+    #[clap(long, default_value = ACF_BOOTSTRAPS)]
+    pub acf_bootstraps: usize,
+    /// Confidence level of the bootstrap confidence intervals of the ACF
+    #[clap(long, default_value = ACF_CONFIDENCE, value_parser = parse_confidence)]
+    pub acf_confidence: f64,
+    /// Also calculate the ACF of the reads of each read group (RG) or haplotype (HP)
+    #[clap(long, value_parser = ["RG", "HP"], requires = "acf")]
+    pub acf_group_by: Option<String>,
+    /// Output text file with the ACF of the m6A calls and the nucleosome starts, for all reads ("all") and for each group of --acf-group-by ("RG=<read group>" or "HP=<haplotype>").
+    /// The columns are: "feature\tgroup\tfibers\tlag\tacf\tlower\tupper" where "lower" and "upper" are the bootstrap confidence interval.
+    /// The ACFs are also added to the --json report under "acf_series".
+    #[clap(long, requires = "acf")]
+    pub acf_out: Option<String>,
     /// In the output include a measure of the number of m6A events per MSPs of a given size.
     /// The output format is: "m6a_per_msp_size\t{m6A count},{MSP size},{is a FIRE}\t{count}"
     /// e.g. "m6a_per_msp_size\t35,100,false\t100"
     #[clap(short, long)]
     pub m6a_per_msp: bool,
+    /// Fibers with an estimated m6A labelling efficiency below this are counted as under-labelled ("fraction_under_labelled") in the --json report.
+    /// The efficiency of each fiber is the m6A rate of the A/T bases in its MSPs corrected for the background rate in its nucleosomes.
+    #[clap(long)]
+    pub min_labelling_efficiency: Option<f64>,
+    /// Bed file of labelled regions (e.g. promoters, gene bodies, heterochromatin) with the label in the 4th column.
+    /// The QC metrics are also collected for the fibers overlapping the regions of each label.
+    /// MSPs, FIREs, and nucleosomes are only counted for a label if their midpoint is within one of its regions.
+    #[clap(long, requires = "bed_out", help_heading = "Strata-Options")]
+    pub bed: Option<String>,
+    /// Output text file with the QC metrics of each label in --bed, with the columns: "label\tstatistic\tvalue\tcount".
+    /// The metrics are also added to the --json report under "strata".
+    #[clap(long, requires = "bed", help_heading = "Strata-Options")]
+    pub bed_out: Option<String>,
     /// Only process the first "n" reads in the input bam file.
     #[clap(long)]
     pub n_reads: Option<usize>,
 }
+
+/// parse a confidence level for `--acf-confidence`, which must be between 0 and 1
+fn parse_confidence(s: &str) -> Result<f64, String> {
+    let confidence = s.parse::<f64>().map_err(|e| format!("{s}: {e}"))?;
+    if confidence > 0.0 && confidence < 1.0 {
+        Ok(confidence)
+    } else {
+        Err(format!("{s} is not between 0 and 1, e.g. 0.95"))
+    }
+}
@@ -1,3 +1,4 @@
+use crate::utils::basemods::parse_mod_code;
 use crate::utils::input_bam::InputBam;
 use clap::Args;
 use std::fmt::Debug;
@@ -9,8 +10,9 @@ pub struct StripBasemodsOptions {
     /// Output bam file
     #[clap(default_value = "-")]
     pub out: String,
-    #[clap(short, long, value_parser(["m6A","6mA", "5mC","CpG"]))]
-    /// base modification to strip out of the bam file
+    /// base modification to strip out of the bam file.
+    /// Any modification code in the MM tag (e.g. a, m, h), ChEBI id (e.g. 21839), or known name (e.g. m6A, 5mC, CpG, 5hmC, 4mC)
+    #[clap(short, long, value_parser = parse_mod_code)]
     pub basemod: Option<String>,
     /// filter out m6A modifications with less than this ML value
     #[clap(long, default_value = "0")]
@@ -18,6 +20,10 @@ pub struct StripBasemodsOptions {
     /// filter out 5mC modifications with less than this ML value
     #[clap(long, default_value = "0")]
     pub ml_5mc: u8,
+    /// filter out modifications of a type with less than an ML value, given as CODE=ML, e.g. 5hmC=200.
+    /// Can be used multiple times.
+    #[clap(long, value_parser = parse_mod_ml)]
+    pub ml_mod: Vec<(String, u8)>,
     /// Drop forward strand of base modifications
     #[clap(long)]
     pub drop_forward: bool,
@@ -25,3 +31,14 @@ pub struct StripBasemodsOptions {
     #[clap(long)]
     pub drop_reverse: bool,
 }
+
+/// parse CODE=ML pairs for `--ml-mod`
+fn parse_mod_ml(s: &str) -> Result<(String, u8), String> {
+    let (code, ml) = s
+        .split_once('=')
+        .ok_or_else(|| format!("{s} is not of the form CODE=ML"))?;
+    let ml = ml
+        .parse::<u8>()
+        .map_err(|e| format!("invalid ML value in {s}: {e}"))?;
+    Ok((parse_mod_code(code)?, ml))
+}
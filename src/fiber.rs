@@ -41,7 +41,11 @@ impl FiberseqData {
         let msp_starts = get_u32_tag(&record, b"as");
         let nuc_length = get_u32_tag(&record, b"nl");
         let msp_length = get_u32_tag(&record, b"al");
-        let nuc = Ranges::new(&record, nuc_starts, None, Some(nuc_length));
+        let mut nuc = Ranges::new(&record, nuc_starts, None, Some(nuc_length));
+        let nuc_qual = get_u8_tag(&record, b"nq");
+        if !nuc_qual.is_empty() {
+            nuc.set_qual(nuc_qual);
+        }
         let mut msp = Ranges::new(&record, msp_starts, None, Some(msp_length));
         let msp_qual = get_u8_tag(&record, b"aq");
         if !msp_qual.is_empty() {
@@ -147,6 +151,29 @@ impl FiberseqData {
         }
     }
 
+    /// Whether each base of the read, in the orientation of the aligned sequence, was assessed for m6A.
+    /// Only the A bases of the sequenced strand are assessed if the fiber has single strand m6A calls (e.g. ONT),
+    /// and bases left out of an explicit mode MM tag are not assessed. Fibers whose MM tag has no m6A code have no assessed bases,
+    /// while fibers with an m6A code but no calls (e.g. `A+a.;`) were assessed and found no m6A.
+    pub fn m6a_assessed(&self) -> Vec<bool> {
+        let seq = self.record.seq().as_bytes();
+        if !self.base_mods.base_mods.iter().any(|bm| bm.is_m6a()) {
+            return vec![false; seq.len()];
+        }
+        let single_strand = self.base_mods.is_single_strand_m6a();
+        let sequenced_bp = if self.record.is_reverse() { b'T' } else { b'A' };
+        let mut assessed: Vec<bool> = seq
+            .iter()
+            .map(|&bp| (bp == b'A' || bp == b'T') && (!single_strand || bp == sequenced_bp))
+            .collect();
+        for pos in self.base_mods.unassessed_m6a() {
+            if let Some(a) = assessed.get_mut(pos as usize) {
+                *a = false;
+            }
+        }
+        assessed
+    }
+
     //
     //  CENTERING FUNCTIONS
     //
@@ -295,6 +322,18 @@ impl FiberseqData {
         self.to_bed12(reference, starts, &lengths, CPG_COLOR)
     }
 
+    /// bed12 of the calls for any modification given as a MM code, ChEBI id, or name
+    pub fn write_basemod(&self, code: &str, reference: bool) -> String {
+        let ranges = self.base_mods.get_mod(code);
+        let starts = if reference {
+            &ranges.reference_starts
+        } else {
+            &ranges.starts
+        };
+        let lengths = vec![Some(1); starts.len()];
+        self.to_bed12(reference, starts, &lengths, BASEMOD_COLOR)
+    }
+
     pub fn to_bed12(
         &self,
         reference: bool,
@@ -451,7 +490,8 @@ impl FiberseqData {
             .as_bytes()
             .iter()
             .filter(|&x| *x == b'A' || *x == b'T')
-            .count() as i64;
+            .count() as i64
+            - self.base_mods.unassessed_m6a().len() as i64;
 
         // get the info
         let m6a_count = self.m6a.starts.len();
@@ -34,6 +34,7 @@ const PROGRESS_STYLE: &str =
 pub const NUC_COLOR: &str = "169,169,169";
 pub const M6A_COLOR: &str = "128,0,128";
 pub const CPG_COLOR: &str = "139,69,19";
+pub const BASEMOD_COLOR: &str = "0,100,0";
 pub const LINKER_COLOR: &str = "147,112,219";
 pub const FIRE_COLORS: [(f32, &str); 9] = [
     (1.0, "139,0,0"),
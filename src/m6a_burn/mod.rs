@@ -1,7 +1,9 @@
 use super::subcommands::predict_m6a::PredictOptions;
 use super::subcommands::predict_m6a::{LAYERS, WINDOW};
 use crate::utils::bio_io::PbChem;
+use anyhow::Result;
 use burn::module::Module;
+use burn::record::{BinFileRecorder, FullPrecisionSettings, Recorder};
 use burn::tensor::backend::Backend;
 use burn::tensor::{Shape, Tensor};
 
@@ -33,6 +35,8 @@ where
     pub two_two: Option<two_two::Model<B>>,
     pub three_two: Option<three_two::Model<B>>,
     pub revio: Option<revio::Model<B>>,
+    /// user supplied weights, all of the built-in models share the same architecture
+    pub custom: Option<revio::Model<B>>,
     pub device: BurnDevice,
 }
 
@@ -40,7 +44,7 @@ impl<B> BurnModels<B>
 where
     B: Backend<Device = BurnDevice>,
 {
-    pub fn new(polymerase: &PbChem) -> Self {
+    fn get_device() -> BurnDevice {
         #[cfg(not(feature = "tch"))]
         let device = B::Device::default();
         #[cfg(feature = "tch")]
@@ -48,6 +52,11 @@ where
 
         // log info about the device used
         log::info!("Using {:?} for Burn device.", device);
+        device
+    }
+
+    pub fn new(polymerase: &PbChem) -> Self {
+        let device = Self::get_device();
 
         match polymerase {
             PbChem::Two => {
@@ -57,6 +66,7 @@ where
                     two_two: None,
                     three_two: None,
                     revio: None,
+                    custom: None,
                     device,
                 }
             }
@@ -67,6 +77,7 @@ where
                     two_two: Some(two_two),
                     three_two: None,
                     revio: None,
+                    custom: None,
                     device,
                 }
             }
@@ -77,6 +88,7 @@ where
                     two_two: None,
                     three_two: Some(three_two),
                     revio: None,
+                    custom: None,
                     device,
                 }
             }
@@ -87,12 +99,39 @@ where
                     two_two: None,
                     three_two: None,
                     revio: Some(revio),
+                    custom: None,
                     device,
                 }
             }
         }
     }
 
+    /// Load user supplied model weights from a Burn binary record (`.bin`).
+    /// The record must have the same architecture as the built-in models,
+    /// e.g. the `.bin` file written by `burn-import` when converting a compatible ONNX model.
+    pub fn from_record_file(path: &str) -> Result<Self> {
+        if path.ends_with(".onnx") {
+            return Err(anyhow::anyhow!(
+                "ONNX models cannot be loaded at runtime ({}). Convert the model to a Burn binary record (.bin) with burn-import first.",
+                path
+            ));
+        }
+        let device = Self::get_device();
+        let record = BinFileRecorder::<FullPrecisionSettings>::new()
+            .load(path.into(), &device)
+            .map_err(|e| anyhow::anyhow!("Unable to load m6A model from {}: {}", path, e))?;
+        let custom = revio::Model::new_with(record).to_device(&device);
+        log::info!("Using custom m6A model: {}", path);
+        Ok(Self {
+            two_zero: None,
+            two_two: None,
+            three_two: None,
+            revio: None,
+            custom: Some(custom),
+            device,
+        })
+    }
+
     #[cfg(feature = "tch")]
     fn get_libtorch_device() -> BurnDevice {
         use burn::backend::libtorch::LibTorchDevice;
@@ -123,11 +162,12 @@ where
         if opts.fake {
             return vec![0.0; count];
         }
-        let forward: Tensor<B, 2, burn::tensor::Float> = match opts.polymerase {
-            PbChem::Two => self.two_zero.as_ref().unwrap().forward(input),
-            PbChem::TwoPointTwo => self.two_two.as_ref().unwrap().forward(input),
-            PbChem::ThreePointTwo => self.three_two.as_ref().unwrap().forward(input),
-            PbChem::Revio => self.revio.as_ref().unwrap().forward(input),
+        let forward: Tensor<B, 2, burn::tensor::Float> = match (&self.custom, &opts.polymerase) {
+            (Some(custom), _) => custom.forward(input),
+            (None, PbChem::Two) => self.two_zero.as_ref().unwrap().forward(input),
+            (None, PbChem::TwoPointTwo) => self.two_two.as_ref().unwrap().forward(input),
+            (None, PbChem::ThreePointTwo) => self.three_two.as_ref().unwrap().forward(input),
+            (None, PbChem::Revio) => self.revio.as_ref().unwrap().forward(input),
         };
         forward
             .into_data()
@@ -156,4 +196,11 @@ mod tests {
         let z: Vec<f32> = output.to_data().value.chunks(2).map(|c| c[0]).collect();
         println!("{:?}", z);
     }
+
+    #[test]
+    #[cfg(not(feature = "tch"))]
+    fn test_custom_model_errors() {
+        assert!(BurnModels::<BurnBackend>::from_record_file("model.onnx").is_err());
+        assert!(BurnModels::<BurnBackend>::from_record_file("does/not/exist.bin").is_err());
+    }
 }
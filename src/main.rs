@@ -72,7 +72,7 @@ pub fn main() -> Result<(), Error> {
                 tch::set_num_threads(1);
                 tch::set_num_interop_threads(1);
             }
-            subcommands::predict_m6a::read_bam_into_fiberdata(predict_m6a_opts);
+            subcommands::predict_m6a::read_bam_into_fiberdata(predict_m6a_opts)?;
         }
         Some(Commands::ClearKinetics(clear_kinetics_opts)) => {
             subcommands::clear_kinetics::clear_kinetics(clear_kinetics_opts);
@@ -89,12 +89,21 @@ pub fn main() -> Result<(), Error> {
         Some(Commands::Qc(qc_opts)) => {
             subcommands::qc::run_qc(qc_opts)?;
         }
+        Some(Commands::QcMerge(qc_merge_opts)) => {
+            subcommands::qc::run_qc_merge(qc_merge_opts)?;
+        }
+        Some(Commands::Nrl(nrl_opts)) => {
+            subcommands::nrl::nrl(nrl_opts)?;
+        }
         Some(Commands::Footprint(footprint_opts)) => {
             subcommands::footprint::start_finding_footprints(footprint_opts)?;
         }
         Some(Commands::Pileup(pileup_opts)) => {
             subcommands::pileup::pileup_track(pileup_opts)?;
         }
+        Some(Commands::CpgPileup(cpg_pileup_opts)) => {
+            subcommands::cpg_pileup::cpg_pileup(cpg_pileup_opts)?;
+        }
         Some(Commands::TrackDecorators(decorator_opts)) => {
             subcommands::decorator::get_decorators_from_bam(decorator_opts)?;
         }
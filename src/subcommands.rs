@@ -4,6 +4,8 @@ pub mod add_nucleosomes;
 pub mod center;
 /// Clear HiFi kinetics tags from a bam file
 pub mod clear_kinetics;
+/// Make a pileup of the methylation of reference CpGs
+pub mod cpg_pileup;
 pub mod ddda_to_m6a;
 /// add decorators
 pub mod decorator;
@@ -12,6 +14,8 @@ pub mod extract;
 /// add fire data
 pub mod fire;
 pub mod footprint;
+/// Estimate nucleosome repeat lengths
+pub mod nrl;
 /// make a fire track from a bam file
 pub mod pileup;
 /// m6A prediction
@@ -9,6 +9,10 @@ use rust_htslib::bam::Record;
 pub fn add_nucleosomes_to_bam(nuc_opts: &mut AddNucleosomeOptions) {
     let mut bam = nuc_opts.input.bam_reader();
     let mut out = nuc_opts.input.bam_writer(&nuc_opts.out);
+    if nuc_opts.ont {
+        log::info!("Using ONT nucleosome parameters: {:?}", nuc_opts.nuc);
+    }
+    let mut not_single_strand = 0;
 
     // read in bam data
     let bam_chunk_iter = BamChunk::new(bam.records(), None);
@@ -16,19 +20,28 @@ pub fn add_nucleosomes_to_bam(nuc_opts: &mut AddNucleosomeOptions) {
     // iterate over chunks
     for mut chunk in bam_chunk_iter {
         // add nuc calls
-        let records: Vec<&mut Record> = chunk
+        let records: Vec<(&mut Record, bool)> = chunk
             .par_iter_mut()
             .map(|record| {
                 let fd = FiberseqData::new(record.clone(), None, &nuc_opts.input.filters);
                 //let m6a = fd.base_mods.forward_m6a();
                 let m6a = fd.m6a.get_forward_starts();
                 add_nucleosomes_to_record(record, &m6a, &nuc_opts.nuc);
-                record
+                (record, fd.base_mods.is_single_strand_m6a())
             })
             .collect();
 
-        records
-            .into_iter()
-            .for_each(|record| out.write(record).unwrap());
+        for (record, single_strand) in records {
+            if nuc_opts.ont && !single_strand {
+                not_single_strand += 1;
+            }
+            out.write(record).unwrap();
+        }
+    }
+    if not_single_strand > 0 {
+        log::warn!(
+            "{} reads had m6A calls on both strands, which is not expected for ONT data. Is this PacBio data?",
+            not_single_strand
+        );
     }
 }
@@ -0,0 +1,301 @@
+use crate::cli::CpgPileupOptions;
+use crate::subcommands::pileup::split_fetch_definition;
+use crate::utils::basemods::{BaseMods, SkipMode};
+use crate::utils::bio_io;
+use crate::*;
+use anyhow::{anyhow, Result};
+use bio::alphabets::dna::complement;
+use rayon::prelude::*;
+use rust_htslib::bam::ext::BamRecordExtensions;
+use rust_htslib::bam::record::Aux;
+use rust_htslib::bam::{FetchDefinition, IndexedReader};
+use rust_htslib::faidx;
+use std::collections::{BTreeMap, HashSet};
+
+static WINDOW_SIZE: usize = 1_000_000;
+static TYPES: [&str; 3] = ["Total", "hap1", "hap2"];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CpgCounts {
+    pub modified: usize,
+    pub unmodified: usize,
+    pub ml_sum: u64,
+}
+
+impl CpgCounts {
+    pub fn add(&mut self, ml: u8, mod_ml: u8) {
+        if ml >= mod_ml {
+            self.modified += 1;
+        } else {
+            self.unmodified += 1;
+        }
+        self.ml_sum += ml as u64;
+    }
+
+    pub fn coverage(&self) -> usize {
+        self.modified + self.unmodified
+    }
+
+    pub fn mod_fraction(&self) -> f64 {
+        self.modified as f64 / self.coverage() as f64
+    }
+
+    pub fn mean_ml(&self) -> f64 {
+        self.ml_sum as f64 / self.coverage() as f64
+    }
+}
+
+/// The start of the reference CpG a call at `pos` is on and the strand of the call.
+/// `seq` is the upper case reference starting at `seq_offset`.
+pub fn cpg_site(seq: &[u8], seq_offset: i64, pos: i64) -> Option<(i64, char)> {
+    let base = |p: i64| {
+        if p < seq_offset {
+            None
+        } else {
+            seq.get((p - seq_offset) as usize).copied()
+        }
+    };
+    match (base(pos - 1), base(pos), base(pos + 1)) {
+        (_, Some(b'C'), Some(b'G')) => Some((pos, '+')),
+        (Some(b'C'), Some(b'G'), _) => Some((pos - 1, '-')),
+        _ => None,
+    }
+}
+
+/// The CpG site, strand, haplotype (0 for none), and ML value of each 5mC call of a record
+/// on a CpG that starts within [start, end).
+///
+/// In implicit mode (`.` or no mode in the MM tag) the bases of the modified type that are not listed were
+/// assessed and are not modified, so the unlisted bases aligned to a reference CpG are added as calls with an ML of 0.
+/// Calls whose reference CpG strand disagrees with the strand the read assessed (e.g. a read C aligned to the G of a CpG) are dropped.
+fn record_calls(
+    record: &bam::Record,
+    seq: &[u8],
+    seq_offset: i64,
+    start: i64,
+    end: i64,
+    opts: &CpgPileupOptions,
+) -> Vec<(i64, char, usize, u8)> {
+    let hp = match record.aux(b"HP") {
+        Ok(Aux::U8(hp)) if hp == 1 || hp == 2 => hp as usize,
+        _ => 0,
+    };
+    let mods = BaseMods::new(record, 0);
+    let read_seq = record.seq().as_bytes();
+    // reference position, ML, and the reference strand the call is on
+    let mut calls: Vec<(i64, u8, char)> = vec![];
+    for bm in mods.base_mods.iter().filter(|bm| bm.is_cpg()) {
+        let call_strand = if record.is_reverse() != (bm.strand == '-') {
+            '-'
+        } else {
+            '+'
+        };
+        let ranges = &bm.ranges;
+        calls.extend(
+            ranges
+                .reference_starts
+                .iter()
+                .zip(ranges.qual.iter())
+                .filter_map(|(pos, &ml)| Some(((*pos)?, ml, call_strand))),
+        );
+        if bm.skip_mode != SkipMode::Implicit {
+            continue;
+        }
+        // the modified base is given on the original strand of the read
+        let read_base = if record.is_reverse() {
+            complement(bm.modified_base)
+        } else {
+            bm.modified_base
+        };
+        let listed: HashSet<i64> = ranges.starts.iter().flatten().copied().collect();
+        for [q, r] in record.aligned_pairs() {
+            // calls on the reverse strand are on the base after the CpG start
+            if r < start || r > end || listed.contains(&q) || read_seq[q as usize] != read_base {
+                continue;
+            }
+            calls.push((r, 0, call_strand));
+        }
+    }
+
+    calls
+        .into_iter()
+        .filter_map(|(pos, ml, call_strand)| {
+            let (site, strand) = cpg_site(seq, seq_offset, pos)?;
+            if site < start || site >= end || strand != call_strand {
+                return None;
+            }
+            let strand = if opts.combine_strands { '.' } else { strand };
+            Some((site, strand, hp, ml))
+        })
+        .collect()
+}
+
+fn write_sites(
+    out: &mut Box<dyn Write>,
+    chrom: &str,
+    sites: &BTreeMap<(i64, char), [CpgCounts; 3]>,
+    opts: &CpgPileupOptions,
+) -> Result<()> {
+    for (&(site, strand), counts) in sites {
+        let (start, end) = match strand {
+            '+' => (site, site + 1),
+            '-' => (site + 1, site + 2),
+            _ => (site, site + 2),
+        };
+        let n_types = if opts.haps { 3 } else { 1 };
+        for (cpg_type, c) in TYPES.iter().zip(counts.iter()).take(n_types) {
+            if c.coverage() == 0 || c.coverage() < opts.min_coverage {
+                continue;
+            }
+            out.write_all(
+                format!(
+                    "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{:.4}\t{:.1}\n",
+                    chrom,
+                    start,
+                    end,
+                    strand,
+                    cpg_type,
+                    c.coverage(),
+                    c.modified,
+                    c.unmodified,
+                    c.mod_fraction(),
+                    c.mean_ml()
+                )
+                .as_bytes(),
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn run_chrom(
+    chrom: &str,
+    rgn: FetchDefinition,
+    bam: &mut IndexedReader,
+    fasta: &faidx::Reader,
+    out: &mut Box<dyn Write>,
+    opts: &CpgPileupOptions,
+) -> Result<()> {
+    let tid = bam
+        .header()
+        .tid(chrom.as_bytes())
+        .ok_or(anyhow!("{} is not in the bam header", chrom))?;
+    let chrom_len = bam.header().target_len(tid).unwrap() as usize;
+    for (start, end) in split_fetch_definition(&rgn, chrom_len, WINDOW_SIZE) {
+        let end = end.min(chrom_len as i64);
+        if start >= end {
+            continue;
+        }
+        // calls on the reverse strand are on the base after the CpG start
+        bam.fetch((chrom, start, end + 1))?;
+        let records: Vec<bam::Record> = opts
+            .input
+            .filters
+            .filter_on_bit_flags(bam.records())
+            .collect();
+        if records.is_empty() {
+            continue;
+        }
+        let seq_offset = (start - 1).max(0);
+        let seq = fasta
+            .fetch_seq(chrom, seq_offset as usize, end as usize + 1)?
+            .to_ascii_uppercase();
+
+        let calls: Vec<(i64, char, usize, u8)> = records
+            .par_iter()
+            .flat_map(|record| record_calls(record, &seq, seq_offset, start, end, opts))
+            .collect();
+        let mut sites: BTreeMap<(i64, char), [CpgCounts; 3]> = BTreeMap::new();
+        for (site, strand, hp, ml) in calls {
+            let counts = sites.entry((site, strand)).or_default();
+            counts[0].add(ml, opts.mod_ml);
+            if hp > 0 {
+                counts[hp].add(ml, opts.mod_ml);
+            }
+        }
+        log::debug!(
+            "{} CpG sites with calls in {}:{}-{}",
+            sites.len(),
+            chrom,
+            start,
+            end
+        );
+        write_sites(out, chrom, &sites, opts)?;
+    }
+    Ok(())
+}
+
+/// Methylation pileup of the CpG sites in the reference.
+///
+/// Every 5mC call, including calls with a low ML value, is placed on the CpG of the reference it aligns to.
+/// Calls on the forward strand align to the C of a CpG and calls on the reverse strand to the G.
+/// Calls that do not align to a reference CpG are skipped. In implicit mode the unlisted bases
+/// at a CpG are counted as unmodified, in explicit mode (`?`) they are not counted.
+pub fn cpg_pileup(opts: &mut CpgPileupOptions) -> Result<()> {
+    if !std::path::Path::new(&opts.reference).exists() {
+        return Err(anyhow!(
+            "Reference fasta {} does not exist.",
+            opts.reference
+        ));
+    }
+    let fasta = faidx::Reader::from_path(&opts.reference)?;
+    let mut bam = opts.input.indexed_bam_reader();
+    let header = opts.input.header_view();
+    let mut out = bio_io::writer(&opts.out)?;
+    out.write_all(
+        b"#chrom\tstart\tend\tstrand\ttype\tcoverage\tmodified\tunmodified\tmod_fraction\tmean_ml\n",
+    )?;
+
+    match &opts.rgn {
+        Some(rgn) => {
+            let (rgn, chrom) = region_parser(rgn);
+            run_chrom(&chrom, rgn, &mut bam, &fasta, &mut out, opts)?;
+        }
+        None => {
+            for chrom in header.target_names() {
+                let rgn = FetchDefinition::String(chrom);
+                run_chrom(
+                    &String::from_utf8_lossy(chrom),
+                    rgn,
+                    &mut bam,
+                    &fasta,
+                    &mut out,
+                    opts,
+                )?;
+            }
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cpg_site() {
+        // reference starting at position 10
+        let seq = b"ACGTTCGA";
+        assert_eq!(cpg_site(seq, 10, 11), Some((11, '+')));
+        assert_eq!(cpg_site(seq, 10, 12), Some((11, '-')));
+        assert_eq!(cpg_site(seq, 10, 15), Some((15, '+')));
+        assert_eq!(cpg_site(seq, 10, 16), Some((15, '-')));
+        assert_eq!(cpg_site(seq, 10, 10), None);
+        assert_eq!(cpg_site(seq, 10, 13), None);
+        // G at the start of the sequence without the preceding base
+        assert_eq!(cpg_site(b"GA", 10, 10), None);
+    }
+
+    #[test]
+    fn test_cpg_counts() {
+        let mut counts = CpgCounts::default();
+        for ml in [250, 200, 10, 127] {
+            counts.add(ml, 128);
+        }
+        assert_eq!(counts.coverage(), 4);
+        assert_eq!(counts.modified, 2);
+        assert_eq!(counts.mod_fraction(), 0.5);
+        assert_eq!(counts.mean_ml(), 146.75);
+    }
+}
@@ -71,7 +71,7 @@ pub fn ddda_to_m6a_record(record: &mut Record, _opts: &DddaToM6aOptions) {
     } else {
         (b'A', '+')
     };
-    let modification_type = 'a';
+    let modification_type = "a";
     let modified_probabilities_forward = vec![255; modified_bases_forward.len()];
 
     let fake_base_mods = basemods::BaseMod::new(
@@ -6,6 +6,7 @@ use rayon::prelude::*;
 pub struct FiberOut {
     pub m6a: Option<Box<dyn Write>>,
     pub cpg: Option<Box<dyn Write>>,
+    pub basemods: Vec<(String, Box<dyn Write>)>,
     pub msp: Option<Box<dyn Write>>,
     pub nuc: Option<Box<dyn Write>>,
     pub all: Option<Box<dyn Write>>,
@@ -19,6 +20,7 @@ impl FiberOut {
     pub fn new(
         m6a: &Option<String>,
         cpg: &Option<String>,
+        basemods: &[(String, String)],
         msp: &Option<String>,
         nuc: &Option<String>,
         all: &Option<String>,
@@ -34,6 +36,10 @@ impl FiberOut {
             Some(cpg) => Some(writer(cpg)?),
             None => None,
         };
+        let basemods = basemods
+            .iter()
+            .map(|(code, path)| Ok((code.clone(), writer(path)?)))
+            .collect::<Result<Vec<_>>>()?;
         let msp = match msp {
             Some(msp) => Some(writer(msp)?),
             None => None,
@@ -50,6 +56,7 @@ impl FiberOut {
         Ok(FiberOut {
             m6a,
             cpg,
+            basemods,
             msp,
             nuc,
             all,
@@ -79,6 +86,15 @@ pub fn process_bam_chunk(fiber_data: Vec<FiberseqData>, out_files: &mut FiberOut
             write_to_file(&line, cpg);
         }
     }
+    for (code, basemod) in out_files.basemods.iter_mut() {
+        let out: Vec<String> = fiber_data
+            .par_iter()
+            .map(|r| r.write_basemod(code, out_files.reference))
+            .collect();
+        for line in out {
+            write_to_file(&line, basemod);
+        }
+    }
     if let Some(msp) = &mut out_files.msp {
         let out: Vec<String> = fiber_data
             .par_iter()
@@ -113,6 +129,7 @@ pub fn extract_contained(extract_opts: &mut ExtractOptions) {
     let mut out_files = FiberOut::new(
         &extract_opts.m6a,
         &extract_opts.cpg,
+        &extract_opts.basemod,
         &extract_opts.msp,
         &extract_opts.nuc,
         &extract_opts.all,
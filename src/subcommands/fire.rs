@@ -2,6 +2,9 @@ use super::decorator::get_fire_color;
 use crate::cli::FireOptions;
 use crate::fiber::FiberseqData;
 use crate::utils::bio_io;
+use crate::utils::bio_io::BamChunk;
+use crate::utils::checkpoint::Checkpoint;
+use crate::utils::labelling::LabellingCounts;
 use crate::*;
 use anyhow;
 use bam::record::{Aux, AuxArray};
@@ -30,6 +33,20 @@ pub fn add_fire_to_rec(
     log::trace!("precisions: {:?}", precisions);
 }
 
+/// Estimate the m6A labelling efficiency of the record and optionally add it to the `le` tag
+pub fn add_labelling_to_rec(rec: &mut FiberseqData, fire_opts: &FireOptions) -> Option<f64> {
+    let efficiency = LabellingCounts::from_fiber(rec).efficiency();
+    if fire_opts.labelling_tag {
+        rec.record.remove_aux(b"le").unwrap_or(());
+        if let Some(efficiency) = efficiency {
+            rec.record
+                .push_aux(b"le", Aux::Float(efficiency as f32))
+                .expect("Cannot add labelling efficiency to bam");
+        }
+    }
+    efficiency
+}
+
 pub fn add_fire_to_bam(fire_opts: &mut FireOptions) -> Result<(), anyhow::Error> {
     let (model, precision_table) = get_model(fire_opts);
     let mut bam = fire_opts.input.bam_reader();
@@ -58,17 +75,43 @@ pub fn add_fire_to_bam(fire_opts: &mut FireOptions) -> Result<(), anyhow::Error>
     }
     // add FIRE prediction to bam file
     else {
+        let mut checkpoint = Checkpoint::new(&fire_opts.out, &fire_opts.checkpoint)?;
         let mut out = fire_opts.input.bam_writer(&fire_opts.out);
-        let fibers = fire_opts.input.fibers(&mut bam);
+        checkpoint.resume(&mut bam, &mut out)?;
+        let header_view = fire_opts.input.header_view();
+        let mut chunks = BamChunk::new(bam.records(), Some(2_000));
+        chunks.set_bit_flag_filter(fire_opts.input.filters.bit_flag);
+        let mut records_read = 0;
         let mut skip_because_no_m6a = 0;
         let mut skip_because_num_msp = 0;
         let mut skip_because_ave_msp_length = 0;
-        for recs in &fibers.chunks(2_000) {
-            let mut recs: Vec<FiberseqData> = recs.collect();
-            recs.par_iter_mut().for_each(|r| {
-                add_fire_to_rec(r, fire_opts, &model, &precision_table);
-            });
-            for rec in recs {
+        let mut skip_because_labelling = 0;
+        let labelling = fire_opts.labelling_tag || fire_opts.min_labelling_efficiency.is_some();
+        while let Some(recs) = chunks.next() {
+            let mut recs = FiberseqData::from_records(recs, &header_view, &fire_opts.input.filters);
+            let efficiencies: Vec<Option<f64>> = recs
+                .par_iter_mut()
+                .map(|r| {
+                    add_fire_to_rec(r, fire_opts, &model, &precision_table);
+                    if labelling {
+                        add_labelling_to_rec(r, fire_opts)
+                    } else {
+                        None
+                    }
+                })
+                .collect();
+            let mut n_written = 0;
+            let mut last_read = None;
+            for (rec, efficiency) in recs.into_iter().zip(efficiencies) {
+                // skip under labelled reads, reads without an estimate are kept
+                if let (Some(min), Some(efficiency)) =
+                    (fire_opts.min_labelling_efficiency, efficiency)
+                {
+                    if efficiency < min {
+                        skip_because_labelling += 1;
+                        continue;
+                    }
+                }
                 let n_msps = rec.msp.starts.len();
                 if fire_opts.skip_no_m6a || fire_opts.min_msp > 0 || fire_opts.min_ave_msp_size > 0
                 {
@@ -90,8 +133,18 @@ pub fn add_fire_to_bam(fire_opts: &mut FireOptions) -> Result<(), anyhow::Error>
                     }
                 }
                 out.write(&rec.record)?;
+                n_written += 1;
+                last_read = Some(rec.record);
             }
+            checkpoint.add_chunk(
+                chunks.records_read - records_read,
+                n_written,
+                last_read.as_ref().map(|r| r.qname()),
+            )?;
+            records_read = chunks.records_read;
         }
+        drop(out);
+        checkpoint.finish()?;
         log::info!(
                 "Skipped {} records because they had an average MSP length less than {}; {} records because they had fewer than {} MSPs; and {} records because they had no m6A sites",
                 skip_because_ave_msp_length,
@@ -100,6 +153,13 @@ pub fn add_fire_to_bam(fire_opts: &mut FireOptions) -> Result<(), anyhow::Error>
                 fire_opts.min_msp,
                 skip_because_no_m6a,
             );
+        if let Some(min) = fire_opts.min_labelling_efficiency {
+            log::info!(
+                "Skipped {} records because they had a labelling efficiency less than {}",
+                skip_because_labelling,
+                min
+            );
+        }
     }
     Ok(())
 }
@@ -0,0 +1,155 @@
+use crate::cli::{NrlOptions, NrlParameters};
+use crate::fiber::FiberseqData;
+use crate::utils::bio_io;
+use crate::utils::nrl::{NrlObservations, NrlSummary};
+use anyhow::Result;
+use itertools::Itertools;
+use rayon::prelude::*;
+use rust_htslib::bam;
+use rust_htslib::bam::ext::BamRecordExtensions;
+use rust_htslib::bam::Read;
+use std::io::{BufRead, Write};
+
+/// NRL observations of a whole fiber in molecular coordinates
+fn fiber_observations(fiber: &FiberseqData, opts: &NrlParameters) -> NrlObservations {
+    let nucs: Vec<(i64, i64)> = fiber
+        .nuc
+        .starts
+        .iter()
+        .zip(fiber.nuc.ends.iter())
+        .filter_map(|(st, en)| Some(((*st)?, (*en)?)))
+        .collect();
+    let m6a: Vec<i64> = fiber.m6a.starts.iter().flatten().copied().collect();
+    let mut obs = NrlObservations::default();
+    obs.add_fiber(&nucs, &m6a, 0, fiber.record.seq_len() as i64, opts);
+    obs
+}
+
+/// Add the part of a fiber that is within a reference region to the observations of the region
+fn add_fiber_in_region(
+    obs: &mut NrlObservations,
+    fiber: &FiberseqData,
+    start: i64,
+    end: i64,
+    opts: &NrlParameters,
+) {
+    let nucs: Vec<(i64, i64)> = fiber
+        .nuc
+        .reference_starts
+        .iter()
+        .zip(fiber.nuc.reference_ends.iter())
+        .filter_map(|(st, en)| Some(((*st)?, (*en)?)))
+        .collect();
+    let m6a: Vec<i64> = fiber
+        .m6a
+        .reference_starts
+        .iter()
+        .flatten()
+        .copied()
+        .collect();
+    let start = start.max(fiber.record.reference_start());
+    let end = end.min(fiber.record.reference_end());
+    obs.add_fiber(&nucs, &m6a, start, end, opts);
+}
+
+fn fiber_line(fiber: &FiberseqData, summary: &NrlSummary) -> String {
+    let (ct, start, end, strand) = if fiber.record.is_unmapped() {
+        (".", 0, 0, '.')
+    } else {
+        (
+            fiber.target_name.as_str(),
+            fiber.record.reference_start(),
+            fiber.record.reference_end(),
+            if fiber.record.is_reverse() { '-' } else { '+' },
+        )
+    };
+    format!(
+        "{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+        ct,
+        start,
+        end,
+        fiber.get_qname(),
+        strand,
+        fiber.record.seq_len(),
+        summary
+    )
+}
+
+fn per_fiber_nrl(opts: &mut NrlOptions) -> Result<()> {
+    let mut bam = opts.input.bam_reader();
+    let mut out = bio_io::writer(&opts.out)?;
+    out.write_all(
+        format!(
+            "#ct\tst\ten\tfiber\tstrand\tfiber_length\t{}\n",
+            NrlSummary::header()
+        )
+        .as_bytes(),
+    )?;
+    for chunk in &opts.input.fibers(&mut bam).chunks(1_000) {
+        let fibers: Vec<FiberseqData> = chunk.collect();
+        let lines: Vec<String> = fibers
+            .par_iter()
+            .map(|fiber| {
+                fiber_line(
+                    fiber,
+                    &fiber_observations(fiber, &opts.nrl).summary(&opts.nrl),
+                )
+            })
+            .collect();
+        for line in lines {
+            out.write_all(line.as_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+fn per_region_nrl(opts: &mut NrlOptions, bed: &str, region_out: &str) -> Result<()> {
+    let mut bam = opts.input.indexed_bam_reader();
+    let header_view = opts.input.header_view();
+    let mut out = bio_io::writer(region_out)?;
+    out.write_all(format!("#ct\tst\ten\t{}\n", NrlSummary::header()).as_bytes())?;
+
+    for line in bio_io::buffer_from(bed)?.lines() {
+        let line = line?;
+        if line.starts_with('#') || line.trim().is_empty() {
+            continue;
+        }
+        let tokens: Vec<&str> = line.split('\t').collect();
+        anyhow::ensure!(
+            tokens.len() >= 3,
+            "BED line has fewer than 3 columns: {}",
+            line
+        );
+        let (ct, start, end) = (
+            tokens[0],
+            tokens[1].parse::<i64>()?,
+            tokens[2].parse::<i64>()?,
+        );
+
+        bam.fetch((ct, start, end))?;
+        let records: Vec<bam::Record> = opts
+            .input
+            .filters
+            .filter_on_bit_flags(bam.records())
+            .collect();
+        let fibers = FiberseqData::from_records(records, &header_view, &opts.input.filters);
+        let mut obs = NrlObservations::default();
+        for fiber in &fibers {
+            add_fiber_in_region(&mut obs, fiber, start, end, &opts.nrl);
+        }
+        let summary = obs.summary(&opts.nrl);
+        out.write_all(format!("{}\t{}\t{}\t{}\n", ct, start, end, summary).as_bytes())?;
+    }
+    Ok(())
+}
+
+pub fn nrl(opts: &mut NrlOptions) -> Result<()> {
+    if opts.nrl.min_nrl >= opts.nrl.max_nrl {
+        anyhow::bail!("--min-nrl must be less than --max-nrl.");
+    }
+    per_fiber_nrl(opts)?;
+    if let (Some(bed), Some(region_out)) = (opts.bed.clone(), opts.region_out.clone()) {
+        per_region_nrl(opts, &bed, &region_out)?;
+    }
+    Ok(())
+}
@@ -1,14 +1,21 @@
 use crate::cli::PileupOptions;
 use crate::fiber::FiberseqData;
 use crate::utils::bamranges;
+use crate::utils::basemods::mod_code_name;
+use crate::utils::bigwig::BigWigWriter;
 use crate::utils::bio_io;
+use crate::utils::stats::{benjamini_hochberg, FisherExact};
 use crate::*;
 use anyhow::{anyhow, Ok};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::io::BufRead;
 //use polars::prelude::*;
 use ordered_float::NotNan;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rayon::prelude::*;
 use rust_htslib::bam::ext::BamRecordExtensions;
+use rust_htslib::bam::record::Aux;
 use rust_htslib::bam::{FetchDefinition, IndexedReader};
 
 /// This module is used to extract the fire calls as well as nucs and msps from a bam file
@@ -18,6 +25,10 @@ use rust_htslib::bam::{FetchDefinition, IndexedReader};
 const MIN_FIRE_COVERAGE: i32 = 4;
 const MIN_FIRE_QUAL: u8 = 229; // floor(255*0.9)
 static WINDOW_SIZE: usize = 1_000_000;
+/// number of random placements to try before giving up on shuffling a fiber outside of excluded regions
+const SHUFFLE_TRIES: usize = 1_000;
+/// shuffled fibers with original starts within this distance are read from the bam with one fetch
+const SHUFFLE_FETCH_GAP: i64 = 100_000;
 
 #[derive(Debug)]
 pub struct FireRow<'a> {
@@ -28,6 +39,9 @@ pub struct FireRow<'a> {
     pub msp_coverage: &'a i32,
     pub cpg_coverage: &'a i32,
     pub m6a_coverage: &'a i32,
+    pub basemod_coverage: Vec<i32>,
+    /// (at_coverage, m6a_fraction), followed by the values for A and T with --m6a-fraction-by-strand
+    pub m6a_fraction: Vec<(i32, f32)>,
     pileup_opts: &'a PileupOptions,
 }
 
@@ -51,6 +65,8 @@ impl PartialEq for FireRow<'_> {
             && self.msp_coverage == other.msp_coverage
             && cpg
             && m6a
+            && self.basemod_coverage == other.basemod_coverage
+            && self.m6a_fraction == other.m6a_fraction
     }
 }
 
@@ -72,18 +88,86 @@ impl std::fmt::Display for FireRow<'_> {
         if self.pileup_opts.cpg {
             rtn += &format!("\t{}", self.cpg_coverage);
         }
+        for cov in self.basemod_coverage.iter() {
+            rtn += &format!("\t{}", cov);
+        }
+        for (cov, frac) in self.m6a_fraction.iter() {
+            rtn += &format!("\t{}\t{}", cov, frac);
+        }
         write!(f, "{}", rtn)
     }
 }
 
+/// The values of one column of a pileup
+pub enum TrackColumn<'a> {
+    Count(&'a [i32]),
+    Score(&'a [f32]),
+}
+
+impl TrackColumn<'_> {
+    pub fn value(&self, i: usize) -> f32 {
+        match self {
+            TrackColumn::Count(x) => x[i] as f32,
+            TrackColumn::Score(x) => x[i],
+        }
+    }
+}
+
+/// read a bed file into sorted and merged regions for each chromosome
+fn read_exclude_regions(path: &str) -> Result<HashMap<String, Vec<(i64, i64)>>> {
+    let mut regions: HashMap<String, Vec<(i64, i64)>> = HashMap::new();
+    for line in bio_io::buffer_from(path)?.lines() {
+        let line = line?;
+        if line.starts_with('#') || line.trim().is_empty() {
+            continue;
+        }
+        let mut parts = line.split('\t');
+        let chrom = parts.next().ok_or(anyhow!("missing chrom"))?;
+        let start = parts
+            .next()
+            .ok_or(anyhow!("missing start"))?
+            .parse::<i64>()?;
+        let end = parts.next().ok_or(anyhow!("missing end"))?.parse::<i64>()?;
+        regions
+            .entry(chrom.to_string())
+            .or_default()
+            .push((start, end));
+    }
+    for rgns in regions.values_mut() {
+        rgns.sort();
+        let mut merged: Vec<(i64, i64)> = vec![];
+        for &(st, en) in rgns.iter() {
+            match merged.last_mut() {
+                Some(last) if st <= last.1 => last.1 = last.1.max(en),
+                _ => merged.push((st, en)),
+            }
+        }
+        *rgns = merged;
+    }
+    Ok(regions)
+}
+
+/// check if [start, end) overlaps any of the sorted and merged regions
+fn overlaps_region(regions: &[(i64, i64)], start: i64, end: i64) -> bool {
+    let idx = regions.partition_point(|(_, en)| *en <= start);
+    idx < regions.len() && regions[idx].0 < end
+}
+
+/// A fiber placed at a random position: (shuffled start, shuffled end, fiber name, original start)
+type ShuffledFiber = (i64, i64, String, i64);
+
 pub struct ShuffledFibers {
     pub shuffled_fiber_starts: HashMap<(String, String, i64), i64>,
+    /// shuffled fibers on each chromosome sorted by their shuffled start
+    by_shuffled_start: HashMap<String, Vec<ShuffledFiber>>,
+    /// longest shuffled fiber on each chromosome, bounds searches of `by_shuffled_start`
+    max_len: HashMap<String, i64>,
 }
 
 impl ShuffledFibers {
     pub fn new(file_path: &str) -> Result<Self> {
         let buffer = bio_io::buffer_from(file_path)?;
-        let mut shuffled_fiber_starts = HashMap::new();
+        let mut shuffled = vec![];
         for line in buffer.lines() {
             let line = line?;
             if line.starts_with('#') {
@@ -96,7 +180,7 @@ impl ShuffledFibers {
                 .next()
                 .ok_or(anyhow!("missing fiber start"))?
                 .parse::<i64>()?;
-            let _end = parts
+            let end = parts
                 .next()
                 .ok_or(anyhow!("missing fiber end"))?
                 .parse::<i64>()?;
@@ -105,15 +189,169 @@ impl ShuffledFibers {
                 .next()
                 .ok_or(anyhow!("missing original start"))?
                 .parse::<i64>()?;
-            shuffled_fiber_starts.insert(
-                (chrom.to_string(), fiber_name.to_string(), original_start),
-                start,
-            );
+            shuffled.push((
+                chrom.to_string(),
+                (start, end, fiber_name.to_string(), original_start),
+            ));
         }
-        log::info!("Read {} shuffled fibers", shuffled_fiber_starts.len());
-        Ok(Self {
+        log::info!("Read {} shuffled fibers", shuffled.len());
+        Ok(Self::from_shuffled(shuffled))
+    }
+
+    fn from_shuffled(shuffled: Vec<(String, ShuffledFiber)>) -> Self {
+        let mut shuffled_fiber_starts = HashMap::new();
+        let mut by_shuffled_start: HashMap<String, Vec<ShuffledFiber>> = HashMap::new();
+        let mut max_len: HashMap<String, i64> = HashMap::new();
+        for (chrom, fiber) in shuffled {
+            let (start, end, name, original_start) = &fiber;
+            shuffled_fiber_starts.insert((chrom.clone(), name.clone(), *original_start), *start);
+            let len = max_len.entry(chrom.clone()).or_default();
+            *len = (*len).max(end - start);
+            by_shuffled_start.entry(chrom).or_default().push(fiber);
+        }
+        for fibers in by_shuffled_start.values_mut() {
+            fibers.sort();
+        }
+        Self {
             shuffled_fiber_starts,
-        })
+            by_shuffled_start,
+            max_len,
+        }
+    }
+
+    /// shuffled fibers that overlap [start, end) in their shuffled position
+    pub fn overlapping(&self, chrom: &str, start: i64, end: i64) -> Vec<&ShuffledFiber> {
+        let (Some(fibers), Some(max_len)) =
+            (self.by_shuffled_start.get(chrom), self.max_len.get(chrom))
+        else {
+            return vec![];
+        };
+        let first = fibers.partition_point(|(st, _, _, _)| *st < start - max_len);
+        fibers[first..]
+            .iter()
+            .take_while(|(st, _, _, _)| *st < end)
+            .filter(|(_, en, _, _)| *en > start)
+            .collect()
+    }
+
+    /// Read the records of the fibers that are shuffled into [start, end) from their original positions
+    pub fn fetch_shuffled_records(
+        &self,
+        bam: &mut IndexedReader,
+        chrom: &str,
+        start: i64,
+        end: i64,
+        pileup_opts: &PileupOptions,
+    ) -> Result<Vec<bam::Record>> {
+        let mut wanted: HashSet<(String, i64)> = HashSet::new();
+        let mut original_starts = vec![];
+        for (_, _, name, original_start) in self.overlapping(chrom, start, end) {
+            wanted.insert((name.clone(), *original_start));
+            original_starts.push(*original_start);
+        }
+        original_starts.sort();
+
+        // fetch nearby original positions together
+        let mut fetches: Vec<(i64, i64)> = vec![];
+        for st in original_starts {
+            match fetches.last_mut() {
+                Some(last) if st - last.1 <= SHUFFLE_FETCH_GAP => last.1 = st + 1,
+                _ => fetches.push((st, st + 1)),
+            }
+        }
+
+        let mut records = vec![];
+        for (fetch_start, fetch_end) in fetches {
+            bam.fetch((chrom, fetch_start, fetch_end))?;
+            for rec in pileup_opts.input.filters.filter_on_bit_flags(bam.records()) {
+                let key = (
+                    String::from_utf8_lossy(rec.qname()).to_string(),
+                    rec.reference_start(),
+                );
+                // a record is only used once even if it is returned by more than one fetch
+                if wanted.remove(&key) {
+                    records.push(rec);
+                }
+            }
+        }
+        Ok(records)
+    }
+
+    /// Randomly place each fiber aligned to `chroms` somewhere on the same chromosome, avoiding the `--shuffle-exclude` regions.
+    /// Fibers that cannot be placed outside the excluded regions are left out of the shuffle.
+    pub fn from_bam(
+        bam: &mut IndexedReader,
+        chroms: &[String],
+        pileup_opts: &PileupOptions,
+    ) -> Result<Self> {
+        let exclude = match &pileup_opts.shuffle_exclude {
+            Some(path) => read_exclude_regions(path)?,
+            None => HashMap::new(),
+        };
+        let mut rng = StdRng::seed_from_u64(pileup_opts.shuffle_seed);
+        let mut out = match &pileup_opts.shuffle_out {
+            Some(path) => {
+                let mut out = bio_io::writer(path)?;
+                out.write_all(
+                    b"#chrom\tshuffled_start\tshuffled_end\tread_name\toriginal_start\n",
+                )?;
+                Some(out)
+            }
+            None => None,
+        };
+
+        let mut shuffled = vec![];
+        let mut not_placed = 0;
+        for chrom in chroms {
+            let tid = bam
+                .header()
+                .tid(chrom.as_bytes())
+                .ok_or(anyhow!("{} is not in the bam header", chrom))?;
+            let chrom_len = bam.header().target_len(tid).unwrap() as i64;
+            let no_regions = vec![];
+            let chrom_exclude = exclude.get(chrom).unwrap_or(&no_regions);
+            bam.fetch(tid)?;
+            for rec in pileup_opts.input.filters.filter_on_bit_flags(bam.records()) {
+                if rec.is_unmapped() {
+                    continue;
+                }
+                let (start, end) = (rec.reference_start(), rec.reference_end());
+                let max_start = chrom_len - (end - start);
+                let shuffled_start = (0..SHUFFLE_TRIES)
+                    .map(|_| rng.gen_range(0..=max_start.max(0)))
+                    .find(|&st| !overlaps_region(chrom_exclude, st, st + end - start));
+                let Some(shuffled_start) = shuffled_start else {
+                    not_placed += 1;
+                    continue;
+                };
+                let name = String::from_utf8_lossy(rec.qname()).to_string();
+                if let Some(out) = out.as_mut() {
+                    out.write_all(
+                        format!(
+                            "{}\t{}\t{}\t{}\t{}\n",
+                            chrom,
+                            shuffled_start,
+                            shuffled_start + end - start,
+                            name,
+                            start
+                        )
+                        .as_bytes(),
+                    )?;
+                }
+                shuffled.push((
+                    chrom.clone(),
+                    (shuffled_start, shuffled_start + end - start, name, start),
+                ));
+            }
+        }
+        if not_placed > 0 {
+            log::warn!(
+                "{} fibers could not be placed outside of the excluded regions and were left out of the shuffle",
+                not_placed
+            );
+        }
+        log::info!("Shuffled {} fibers", shuffled.len());
+        Ok(Self::from_shuffled(shuffled))
     }
 
     pub fn get_shuffled_start(&self, fiber: &FiberseqData) -> Option<i64> {
@@ -143,6 +381,134 @@ impl ShuffledFibers {
     }
 }
 
+/// The value that assigns a fiber to a group
+pub enum GroupKey {
+    /// the HP tag, H1 or H2
+    Haplotype,
+    /// the value of a string tag
+    Tag([u8; 2]),
+    /// read name to group
+    ReadName(HashMap<String, String>),
+}
+
+/// Splits fibers into groups that each get their own `FireTrack` in the pileup
+pub struct FiberGrouping {
+    key: GroupKey,
+    pub groups: Vec<String>,
+    group_index: HashMap<String, usize>,
+    /// the group is a prefix of the column names instead of a suffix
+    prefix: bool,
+}
+
+impl FiberGrouping {
+    pub fn new(key: GroupKey, groups: Vec<String>, prefix: bool) -> Self {
+        let group_index = groups
+            .iter()
+            .enumerate()
+            .map(|(i, g)| (g.clone(), i))
+            .collect();
+        Self {
+            key,
+            groups,
+            group_index,
+            prefix,
+        }
+    }
+
+    /// The hap1 and hap2 split of --haps
+    pub fn haplotypes() -> Self {
+        Self::new(
+            GroupKey::Haplotype,
+            vec!["H1".to_string(), "H2".to_string()],
+            false,
+        )
+    }
+
+    /// The grouping of --group-by or --group-tsv, if either is used
+    pub fn from_opts(bam: &IndexedReader, pileup_opts: &PileupOptions) -> Result<Option<Self>> {
+        let mut found_groups = vec![];
+        let key = if let Some(tag) = &pileup_opts.group_by {
+            let tag: [u8; 2] = tag
+                .as_bytes()
+                .try_into()
+                .map_err(|_| anyhow!("--group-by must be a two character tag: {}", tag))?;
+            // only the read groups can be found without an extra pass over the bam
+            if pileup_opts.groups.is_empty() {
+                if &tag != b"RG" {
+                    return Err(anyhow!(
+                        "--groups is required with --group-by {}, only the read groups of --group-by RG are read from the bam header.",
+                        String::from_utf8_lossy(&tag)
+                    ));
+                }
+                found_groups = Self::read_groups(bam);
+            }
+            GroupKey::Tag(tag)
+        } else if let Some(path) = &pileup_opts.group_tsv {
+            let mut read_groups = HashMap::new();
+            for line in bio_io::buffer_from(path)?.lines() {
+                let line = line?;
+                if line.starts_with('#') || line.trim().is_empty() {
+                    continue;
+                }
+                let mut parts = line.split('\t');
+                let name = parts.next().ok_or(anyhow!("missing read name"))?;
+                let group = parts.next().ok_or(anyhow!("missing group"))?;
+                if !found_groups.iter().any(|g| g == group) {
+                    found_groups.push(group.to_string());
+                }
+                read_groups.insert(name.to_string(), group.to_string());
+            }
+            GroupKey::ReadName(read_groups)
+        } else {
+            return Ok(None);
+        };
+        let groups = if pileup_opts.groups.is_empty() {
+            found_groups
+        } else {
+            pileup_opts.groups.clone()
+        };
+        if groups.is_empty() {
+            return Err(anyhow!(
+                "No groups were found for --group-by or --group-tsv."
+            ));
+        }
+        log::info!("Making a pileup for groups: {}", groups.join(", "));
+        Ok(Some(Self::new(key, groups, true)))
+    }
+
+    /// IDs of the read groups in the bam header
+    fn read_groups(bam: &IndexedReader) -> Vec<String> {
+        let header = bam::Header::from_template(bam.header());
+        header
+            .to_hashmap()
+            .get("RG")
+            .map(|rgs| rgs.iter().filter_map(|rg| rg.get("ID").cloned()).collect())
+            .unwrap_or_default()
+    }
+
+    /// index of the group of a fiber, if it is in one of the groups
+    pub fn group(&self, fiber: &FiberseqData) -> Option<usize> {
+        let group = match &self.key {
+            GroupKey::Haplotype => fiber.get_hp(),
+            GroupKey::Tag(tag) => match fiber.record.aux(tag) {
+                std::result::Result::Ok(Aux::String(v)) => v.to_string(),
+                _ => return None,
+            },
+            GroupKey::ReadName(read_groups) => read_groups.get(&fiber.get_qname())?.clone(),
+        };
+        self.group_index.get(&group).copied()
+    }
+
+    /// name of a column in the track of a group
+    pub fn column_name(&self, group: usize, column: &str) -> String {
+        if self.prefix {
+            format!("{}_{}", self.groups[group], column)
+        } else {
+            format!("{}_{}", column, self.groups[group])
+        }
+    }
+}
+
 pub struct FireTrack<'a> {
     pub chrom_start: usize,
     pub chrom_end: usize,
@@ -155,6 +521,17 @@ pub struct FireTrack<'a> {
     pub nuc_coverage: Vec<i32>,
     pub cpg_coverage: Vec<i32>,
     pub m6a_coverage: Vec<i32>,
+    /// coverage of each modification in `--basemod`
+    pub basemod_coverage: Vec<Vec<i32>>,
+    /// fibers with an A or T assessed for m6A at each base, only with --m6a-fraction
+    pub a_coverage: Vec<i32>,
+    pub t_coverage: Vec<i32>,
+    /// m6A calls on an A or T, only with --m6a-fraction
+    pub m6a_a: Vec<i32>,
+    pub m6a_t: Vec<i32>,
+    pub at_coverage: Vec<i32>,
+    /// m6A fraction over both bases, A, and T
+    pub m6a_fraction: [Vec<f32>; 3],
     pileup_opts: &'a PileupOptions,
     shuffled_fibers: &'a Option<ShuffledFibers>,
     cur_offset: i64,
@@ -170,6 +547,11 @@ impl<'a> FireTrack<'a> {
         let track_len = chrom_end - chrom_start + 1;
         let raw_scores = vec![-1.0; track_len];
         let scores = vec![-1.0; track_len];
+        let at_len = if pileup_opts.m6a_fraction {
+            track_len
+        } else {
+            0
+        };
         Self {
             chrom_start,
             chrom_end,
@@ -182,6 +564,13 @@ impl<'a> FireTrack<'a> {
             nuc_coverage: vec![0; track_len],
             cpg_coverage: vec![0; track_len],
             m6a_coverage: vec![0; track_len],
+            basemod_coverage: vec![vec![0; track_len]; pileup_opts.basemod.len()],
+            a_coverage: vec![0; at_len],
+            t_coverage: vec![0; at_len],
+            m6a_a: vec![0; at_len],
+            m6a_t: vec![0; at_len],
+            at_coverage: vec![0; at_len],
+            m6a_fraction: [vec![-1.0; at_len], vec![-1.0; at_len], vec![-1.0; at_len]],
             pileup_opts,
             shuffled_fibers,
             cur_offset: 0,
@@ -270,10 +659,6 @@ impl<'a> FireTrack<'a> {
             None => 0,
         };
 
-        if self.cur_offset != 0 && self.chrom_start != 0 {
-            panic!("Cannot apply shuffling unless the entire chromosome is being read at once.");
-        }
-
         let (start, end) = self.fiber_start_and_end(fiber);
         // calculate the coverage
         for i in start..end {
@@ -323,21 +708,70 @@ impl<'a> FireTrack<'a> {
         for (array, ranges) in pairs {
             Self::add_range_set(array, ranges, self.cur_offset, self.chrom_start);
         }
+
+        for (array, code) in self
+            .basemod_coverage
+            .iter_mut()
+            .zip(self.pileup_opts.basemod.iter())
+        {
+            let ranges = fiber.base_mods.get_mod(code);
+            Self::add_range_set(array, &ranges, self.cur_offset, self.chrom_start);
+        }
+
+        if self.pileup_opts.m6a_fraction {
+            self.add_m6a_fraction(fiber);
+        }
+    }
+
+    /// Count the A/T bases of a fiber that were assessed for m6A and the m6A calls on them, see `FiberseqData::m6a_assessed`.
+    /// Bases are split into A and T by the base of the read in the orientation of the reference.
+    fn add_m6a_fraction(&mut self, fiber: &FiberseqData) {
+        let seq = fiber.record.seq().as_bytes();
+        let assessed = fiber.m6a_assessed();
+        let m6a: HashSet<i64> = fiber.m6a.starts.iter().flatten().copied().collect();
+        for [q, r] in fiber.record.aligned_pairs() {
+            let pos = r + self.cur_offset - self.chrom_start as i64;
+            if pos < 0 || pos >= self.track_len as i64 || !assessed[q as usize] {
+                continue;
+            }
+            let (coverage, calls) = if seq[q as usize] == b'A' {
+                (&mut self.a_coverage, &mut self.m6a_a)
+            } else {
+                (&mut self.t_coverage, &mut self.m6a_t)
+            };
+            coverage[pos as usize] += 1;
+            if m6a.contains(&q) {
+                calls[pos as usize] += 1;
+            }
+        }
     }
 
     pub fn calculate_scores(&mut self) {
         for i in 0..self.track_len {
             if self.fire_coverage[i] <= 0 {
                 self.scores[i] = -1.0;
-            } else if self.fire_coverage[i] < MIN_FIRE_COVERAGE
-                && self.pileup_opts.shuffle.is_none()
-            {
+            } else if self.fire_coverage[i] < MIN_FIRE_COVERAGE && !self.pileup_opts.shuffling() {
                 // there is no minimum fire coverage if we are shuffling
                 self.scores[i] = -1.0;
             } else {
                 self.scores[i] = self.raw_scores[i] / self.coverage[i] as f32;
             }
         }
+        if self.pileup_opts.m6a_fraction {
+            let frac = |calls: i32, coverage: i32| {
+                if coverage > 0 {
+                    calls as f32 / coverage as f32
+                } else {
+                    -1.0
+                }
+            };
+            for i in 0..self.track_len {
+                self.at_coverage[i] = self.a_coverage[i] + self.t_coverage[i];
+                self.m6a_fraction[0][i] = frac(self.m6a_a[i] + self.m6a_t[i], self.at_coverage[i]);
+                self.m6a_fraction[1][i] = frac(self.m6a_a[i], self.a_coverage[i]);
+                self.m6a_fraction[2][i] = frac(self.m6a_t[i], self.t_coverage[i]);
+            }
+        }
     }
 
     pub fn calculate_rolling_max_score(&mut self) -> Vec<f32> {
@@ -359,6 +793,88 @@ impl<'a> FireTrack<'a> {
         rolling_max
     }
 
+    /// names of the columns of a track
+    pub fn column_names(pileup_opts: &PileupOptions) -> Vec<String> {
+        let mut names = vec![
+            "coverage".to_string(),
+            "fire_coverage".to_string(),
+            "score".to_string(),
+        ];
+        if !pileup_opts.no_nuc {
+            names.push("nuc_coverage".to_string());
+        }
+        if !pileup_opts.no_msp {
+            names.push("msp_coverage".to_string());
+        }
+        if pileup_opts.m6a {
+            names.push("m6a_coverage".to_string());
+        }
+        if pileup_opts.cpg {
+            names.push("cpg_coverage".to_string());
+        }
+        for code in pileup_opts.basemod.iter() {
+            names.push(format!("{}_coverage", mod_code_name(code)));
+        }
+        if pileup_opts.m6a_fraction {
+            names.push("at_coverage".to_string());
+            names.push("m6a_fraction".to_string());
+        }
+        if pileup_opts.m6a_fraction_by_strand {
+            names.push("a_coverage".to_string());
+            names.push("m6a_fraction_a".to_string());
+            names.push("t_coverage".to_string());
+            names.push("m6a_fraction_t".to_string());
+        }
+        names
+    }
+
+    /// the data of the columns of this track, in the same order as `column_names`
+    pub fn columns(&self) -> Vec<TrackColumn<'_>> {
+        let mut columns = vec![
+            TrackColumn::Count(&self.coverage),
+            TrackColumn::Count(&self.fire_coverage),
+            TrackColumn::Score(&self.scores),
+        ];
+        if !self.pileup_opts.no_nuc {
+            columns.push(TrackColumn::Count(&self.nuc_coverage));
+        }
+        if !self.pileup_opts.no_msp {
+            columns.push(TrackColumn::Count(&self.msp_coverage));
+        }
+        if self.pileup_opts.m6a {
+            columns.push(TrackColumn::Count(&self.m6a_coverage));
+        }
+        if self.pileup_opts.cpg {
+            columns.push(TrackColumn::Count(&self.cpg_coverage));
+        }
+        for coverage in self.basemod_coverage.iter() {
+            columns.push(TrackColumn::Count(coverage));
+        }
+        if self.pileup_opts.m6a_fraction {
+            columns.push(TrackColumn::Count(&self.at_coverage));
+            columns.push(TrackColumn::Score(&self.m6a_fraction[0]));
+        }
+        if self.pileup_opts.m6a_fraction_by_strand {
+            columns.push(TrackColumn::Count(&self.a_coverage));
+            columns.push(TrackColumn::Score(&self.m6a_fraction[1]));
+            columns.push(TrackColumn::Count(&self.t_coverage));
+            columns.push(TrackColumn::Score(&self.m6a_fraction[2]));
+        }
+        columns
+    }
+
+    fn m6a_fraction_row(&self, i: usize) -> Vec<(i32, f32)> {
+        let mut row = vec![];
+        if self.pileup_opts.m6a_fraction {
+            row.push((self.at_coverage[i], self.m6a_fraction[0][i]));
+        }
+        if self.pileup_opts.m6a_fraction_by_strand {
+            row.push((self.a_coverage[i], self.m6a_fraction[1][i]));
+            row.push((self.t_coverage[i], self.m6a_fraction[2][i]));
+        }
+        row
+    }
+
     pub fn row(&self, i: usize) -> FireRow {
         FireRow {
             score: &self.scores[i],
@@ -368,6 +884,8 @@ impl<'a> FireTrack<'a> {
             nuc_coverage: &self.nuc_coverage[i],
             cpg_coverage: &self.cpg_coverage[i],
             m6a_coverage: &self.m6a_coverage[i],
+            basemod_coverage: self.basemod_coverage.iter().map(|c| c[i]).collect(),
+            m6a_fraction: self.m6a_fraction_row(i),
             pileup_opts: self.pileup_opts,
         }
     }
@@ -375,8 +893,8 @@ impl<'a> FireTrack<'a> {
 
 pub struct FiberseqPileup<'a> {
     pub all_data: FireTrack<'a>,
-    pub hap1_data: Option<FireTrack<'a>>,
-    pub hap2_data: Option<FireTrack<'a>>,
+    /// a track for each group of each grouping, e.g. hap1 and hap2
+    pub group_data: Vec<Vec<FireTrack<'a>>>,
     pub shuffled_data: Option<FireTrack<'a>>,
     pub chrom: String,
     pub chrom_start: usize,
@@ -385,6 +903,7 @@ pub struct FiberseqPileup<'a> {
     has_data: bool,
     pileup_opts: &'a PileupOptions,
     shuffled_fibers: &'a Option<ShuffledFibers>,
+    groupings: &'a [FiberGrouping],
     rolling_max: Option<Vec<f32>>,
 }
 
@@ -395,17 +914,20 @@ impl<'a> FiberseqPileup<'a> {
         chrom_end: usize,
         pileup_opts: &'a PileupOptions,
         shuffled_fibers: &'a Option<ShuffledFibers>,
+        groupings: &'a [FiberGrouping],
     ) -> Self {
         let track_len = chrom_end - chrom_start + 1;
         let all_data = FireTrack::new(chrom_start, chrom_end, pileup_opts, &None);
-        let (hap1_data, hap2_data) = if pileup_opts.haps {
-            (
-                Some(FireTrack::new(chrom_start, chrom_end, pileup_opts, &None)),
-                Some(FireTrack::new(chrom_start, chrom_end, pileup_opts, &None)),
-            )
-        } else {
-            (None, None)
-        };
+        let group_data = groupings
+            .iter()
+            .map(|grouping| {
+                grouping
+                    .groups
+                    .iter()
+                    .map(|_| FireTrack::new(chrom_start, chrom_end, pileup_opts, &None))
+                    .collect()
+            })
+            .collect();
 
         let shuffled_data = if shuffled_fibers.is_some() {
             Some(FireTrack::new(
@@ -420,8 +942,7 @@ impl<'a> FiberseqPileup<'a> {
 
         Self {
             all_data,
-            hap1_data,
-            hap2_data,
+            group_data,
             shuffled_data,
             chrom: chrom.to_string(),
             chrom_start,
@@ -430,6 +951,7 @@ impl<'a> FiberseqPileup<'a> {
             has_data: false,
             pileup_opts,
             shuffled_fibers,
+            groupings,
             rolling_max: None,
         }
     }
@@ -440,7 +962,7 @@ impl<'a> FiberseqPileup<'a> {
 
     pub fn add_records(
         &mut self,
-        records: bam::Records<'a, IndexedReader>,
+        records: bam::Records<'_, IndexedReader>,
     ) -> Result<(), anyhow::Error> {
         self.pileup_opts
             .input
@@ -467,65 +989,98 @@ impl<'a> FiberseqPileup<'a> {
                     }
 
                     self.all_data.update_with_fiber(&fiber);
-                    // add hap1 data
-                    if let Some(hap1_data) = &mut self.hap1_data {
-                        if fiber.get_hp() == "H1" {
-                            hap1_data.update_with_fiber(&fiber);
-                        }
-                    }
-                    // add hap2 data
-                    if let Some(hap2_data) = &mut self.hap2_data {
-                        if fiber.get_hp() == "H2" {
-                            hap2_data.update_with_fiber(&fiber);
+                    // add the data of the groups the fiber is in
+                    for (grouping, tracks) in self.groupings.iter().zip(self.group_data.iter_mut())
+                    {
+                        if let Some(group) = grouping.group(&fiber) {
+                            tracks[group].update_with_fiber(&fiber);
                         }
                     }
-                    // add shuffled data
-                    if let Some(shuffled_data) = &mut self.shuffled_data {
-                        shuffled_data.update_with_fiber(&fiber);
-                    }
                 }
             });
         self.calculate_scores();
         Ok(())
     }
 
-    pub fn header(pileup_opts: &PileupOptions) -> String {
-        let mut header = format!("{}\t{}\t{}", "#chrom", "start", "end");
-
-        let mut suffixes = vec![""];
-        if pileup_opts.haps {
-            suffixes.push("_H1");
-            suffixes.push("_H2");
-        }
-        if pileup_opts.shuffle.is_some() {
-            suffixes.push("_shuffled");
-        }
-
-        for suffix in suffixes {
-            header += &format!(
-                "\t{}{suffix}\t{}{suffix}\t{}{suffix}",
-                "coverage", "fire_coverage", "score",
+    /// Add the fibers that are shuffled into this pileup to the shuffled track.
+    /// These are read from their original positions, which can be outside of the pileup.
+    pub fn add_shuffled_records(&mut self, records: Vec<bam::Record>) {
+        let Some(shuffled_data) = &mut self.shuffled_data else {
+            return;
+        };
+        for chunk in records.into_iter().chunks(1000).into_iter() {
+            let fibers = FiberseqData::from_records(
+                chunk.collect(),
+                &self.pileup_opts.input.header_view(),
+                &self.pileup_opts.input.filters,
             );
-            if !pileup_opts.no_nuc {
-                header += &format!("\t{}{suffix}", "nuc_coverage");
-            }
-            if !pileup_opts.no_msp {
-                header += &format!("\t{}{suffix}", "msp_coverage");
+            if !fibers.is_empty() {
+                self.has_data = true;
             }
-            if pileup_opts.m6a {
-                header += &format!("\t{}{suffix}", "m6a_coverage");
+            for fiber in fibers {
+                shuffled_data.update_with_fiber(&fiber);
             }
-            if pileup_opts.cpg {
-                header += &format!("\t{}{suffix}", "cpg_coverage");
+        }
+    }
+
+    /// names of the data columns of the pileup, in the order they are written
+    pub fn column_names(pileup_opts: &PileupOptions, groupings: &[FiberGrouping]) -> Vec<String> {
+        let track_names = FireTrack::column_names(pileup_opts);
+        let mut names = track_names.clone();
+        for grouping in groupings {
+            for group in 0..grouping.groups.len() {
+                names.extend(track_names.iter().map(|c| grouping.column_name(group, c)));
             }
         }
+        if pileup_opts.shuffling() {
+            names.extend(track_names.iter().map(|c| format!("{}_shuffled", c)));
+        }
         if pileup_opts.rolling_max.is_some() {
-            header += "\trolling_max";
+            names.push("rolling_max".to_string());
+        }
+        names
+    }
+
+    pub fn header(pileup_opts: &PileupOptions, groupings: &[FiberGrouping]) -> String {
+        let mut header = format!("{}\t{}\t{}", "#chrom", "start", "end");
+        for name in Self::column_names(pileup_opts, groupings) {
+            header += &format!("\t{}", name);
         }
         header += "\n";
         header
     }
 
+    /// the track of a group from any of the groupings
+    pub fn group_track(&self, group: &str) -> Option<&FireTrack<'a>> {
+        self.groupings
+            .iter()
+            .zip(self.group_data.iter())
+            .find_map(|(grouping, tracks)| grouping.group_index.get(group).map(|&i| &tracks[i]))
+    }
+
+    /// the data tracks of the pileup, in the order they are written
+    fn data_tracks(&self) -> Vec<&FireTrack<'a>> {
+        let mut data_tracks = vec![&self.all_data];
+        data_tracks.extend(self.group_data.iter().flatten());
+        if let Some(shuffled_data) = &self.shuffled_data {
+            data_tracks.push(shuffled_data);
+        }
+        data_tracks
+    }
+
+    /// the data columns of the pileup, in the same order as `column_names`
+    pub fn columns(&self) -> Vec<TrackColumn<'_>> {
+        let mut columns: Vec<TrackColumn> = self
+            .data_tracks()
+            .into_iter()
+            .flat_map(|data| data.columns())
+            .collect();
+        if let Some(rolling_max) = &self.rolling_max {
+            columns.push(TrackColumn::Score(rolling_max));
+        }
+        columns
+    }
+
     fn calculate_scores(&mut self) {
         self.all_data.calculate_scores();
         // calculate rolling max
@@ -533,11 +1088,8 @@ impl<'a> FiberseqPileup<'a> {
             self.rolling_max = Some(self.all_data.calculate_rolling_max_score());
         }
         // scores for other tracks
-        if let Some(hap1_data) = &mut self.hap1_data {
-            hap1_data.calculate_scores();
-        }
-        if let Some(hap2_data) = &mut self.hap2_data {
-            hap2_data.calculate_scores();
+        for data in self.group_data.iter_mut().flatten() {
+            data.calculate_scores();
         }
         if let Some(shuffled_data) = &mut self.shuffled_data {
             shuffled_data.calculate_scores();
@@ -551,23 +1103,9 @@ impl<'a> FiberseqPileup<'a> {
         if i == 0 {
             true
         } else {
-            let total_same = self.all_data.row(i) == self.all_data.row(i - 1);
-            let haps_same = if self.pileup_opts.haps {
-                let h1 = self.hap1_data.as_ref().unwrap();
-                let h2 = self.hap2_data.as_ref().unwrap();
-                let hap1_same = h1.row(i) == h1.row(i - 1);
-                let hap2_same = h2.row(i) == h2.row(i - 1);
-                hap1_same && hap2_same
-            } else {
-                true
-            };
-            let shuffled_same = if self.shuffled_data.is_some() {
-                self.shuffled_data.as_ref().unwrap().row(i)
-                    == self.shuffled_data.as_ref().unwrap().row(i - 1)
-            } else {
-                true
-            };
-            total_same && haps_same && shuffled_same
+            self.data_tracks()
+                .iter()
+                .all(|data| data.row(i) == data.row(i - 1))
         }
     }
 
@@ -580,15 +1118,7 @@ impl<'a> FiberseqPileup<'a> {
     }
 
     pub fn log_stats(&self) {
-        let mut data_tracks = vec![&self.all_data];
-        if self.pileup_opts.haps {
-            data_tracks.push(self.hap1_data.as_ref().unwrap());
-            data_tracks.push(self.hap2_data.as_ref().unwrap());
-        }
-        if self.shuffled_data.is_some() {
-            data_tracks.push(self.shuffled_data.as_ref().unwrap());
-        }
-        for data in data_tracks {
+        for data in self.data_tracks() {
             let total_coverage: i64 = data.coverage.iter().map(|x| *x as i64).sum();
             let total_fire_coverage: i64 = data.fire_coverage.iter().map(|x| *x as i64).sum();
             let total_score: f64 = data.scores.iter().map(|x| *x as f64).sum();
@@ -622,16 +1152,7 @@ impl<'a> FiberseqPileup<'a> {
                     write_end_index + self.chrom_start
                 );
 
-                let mut data_tracks = vec![&self.all_data];
-                if self.pileup_opts.haps {
-                    data_tracks.push(self.hap1_data.as_ref().unwrap());
-                    data_tracks.push(self.hap2_data.as_ref().unwrap());
-                }
-                if self.shuffled_data.is_some() {
-                    data_tracks.push(self.shuffled_data.as_ref().unwrap());
-                }
-
-                for data in data_tracks {
+                for data in self.data_tracks() {
                     line += data.row(write_start_index).to_string().as_str();
                 }
                 if self.pileup_opts.rolling_max.is_some() {
@@ -659,8 +1180,510 @@ impl<'a> FiberseqPileup<'a> {
     }
 }
 
+/// A stretch of bases with the same FIRE score
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScoreSegment {
+    /// index of the chromosome in `FirePeakCaller::chroms`
+    pub chrom: usize,
+    pub start: i64,
+    pub end: i64,
+    pub score: f32,
+}
+
+/// A merged FIRE peak with the position of its highest score
+#[derive(Debug, Clone, PartialEq)]
+pub struct FirePeak {
+    pub chrom: String,
+    pub start: i64,
+    pub end: i64,
+    pub summit: i64,
+    pub max_score: f32,
+    pub fdr: f64,
+}
+
+impl FirePeak {
+    fn from_segment(chrom: &str, seg: &ScoreSegment) -> Self {
+        Self {
+            chrom: chrom.to_string(),
+            start: seg.start,
+            end: seg.end,
+            summit: (seg.start + seg.end) / 2,
+            max_score: seg.score,
+            fdr: 1.0,
+        }
+    }
+
+    fn add_segment(&mut self, seg: &ScoreSegment) {
+        self.end = seg.end;
+        if seg.score > self.max_score {
+            self.max_score = seg.score;
+            self.summit = (seg.start + seg.end) / 2;
+        }
+    }
+
+    /// narrowPeak line, the score is -10*log10(FDR) capped at 1,000 and the qValue is -log10(FDR)
+    pub fn to_narrow_peak(&self, idx: usize) -> String {
+        let q_value = -self.fdr.log10();
+        format!(
+            "{}\t{}\t{}\tFIRE_peak_{}\t{}\t.\t{}\t-1\t{:.4}\t{}\n",
+            self.chrom,
+            self.start,
+            self.end,
+            idx,
+            (10.0 * q_value).round().min(1000.0) as i64,
+            self.max_score,
+            q_value,
+            self.summit - self.start
+        )
+    }
+}
+
+/// Calls FIRE peaks from observed and shuffled pileups.
+///
+/// Scores are collected over all the chromosomes before calling peaks so the FDR is estimated genome wide.
+/// The FDR of a score threshold is the number of shuffled bases at or above the threshold divided by the
+/// number of observed bases at or above the threshold.
+pub struct FirePeakCaller {
+    /// score (rounded to 0.001) -> (observed bp, shuffled bp)
+    score_bp: BTreeMap<i64, (u64, u64)>,
+    /// names of the chromosomes the segments are on, so each segment does not need its own copy
+    chroms: Vec<String>,
+    segments: Vec<ScoreSegment>,
+}
+
+impl FirePeakCaller {
+    pub fn new() -> Self {
+        Self {
+            score_bp: BTreeMap::new(),
+            chroms: vec![],
+            segments: vec![],
+        }
+    }
+
+    /// index of a chromosome in `chroms`, adding it if it is new
+    fn chrom_index(&mut self, chrom: &str) -> usize {
+        match self.chroms.iter().rposition(|c| c == chrom) {
+            Some(idx) => idx,
+            None => {
+                self.chroms.push(chrom.to_string());
+                self.chroms.len() - 1
+            }
+        }
+    }
+
+    fn score_key(score: f32) -> i64 {
+        (score as f64 * 1000.0).round() as i64
+    }
+
+    /// Add the observed and shuffled scores of a pileup
+    pub fn add_pileup(&mut self, pileup: &FiberseqPileup) {
+        if !pileup.has_data() {
+            return;
+        }
+        let chrom = self.chrom_index(&pileup.chrom);
+        // the last position of the track is the first position of the next window
+        let len = pileup.track_len - 1;
+        let mut cur: Option<ScoreSegment> = None;
+        for (i, &score) in pileup.all_data.scores.iter().take(len).enumerate() {
+            if score >= 0.0 {
+                self.score_bp.entry(Self::score_key(score)).or_default().0 += 1;
+            }
+            let pos = (pileup.chrom_start + i) as i64;
+            match cur.as_mut() {
+                Some(seg) if seg.score == score && seg.end == pos => seg.end += 1,
+                _ => {
+                    if let Some(seg) = cur.take() {
+                        self.segments.push(seg);
+                    }
+                    if score > 0.0 {
+                        cur = Some(ScoreSegment {
+                            chrom,
+                            start: pos,
+                            end: pos + 1,
+                            score,
+                        });
+                    }
+                }
+            }
+        }
+        if let Some(seg) = cur {
+            self.segments.push(seg);
+        }
+        if let Some(shuffled) = &pileup.shuffled_data {
+            for &score in shuffled.scores.iter().take(len).filter(|&&s| s >= 0.0) {
+                self.score_bp.entry(Self::score_key(score)).or_default().1 += 1;
+            }
+        }
+    }
+
+    /// FDR of each score threshold, made monotonic so higher thresholds never have a higher FDR
+    pub fn fdr_table(&self) -> Vec<(f32, f64)> {
+        let (mut observed, mut shuffled) = (0u64, 0u64);
+        let mut table = vec![];
+        for (&key, &(obs, shuf)) in self.score_bp.iter().rev() {
+            observed += obs;
+            shuffled += shuf;
+            if observed > 0 {
+                table.push((key as f32 / 1000.0, shuffled as f64 / observed as f64));
+            }
+        }
+        table.reverse();
+        let mut min_fdr = f64::MAX;
+        for (_, fdr) in table.iter_mut() {
+            min_fdr = min_fdr.min(*fdr);
+            *fdr = min_fdr;
+        }
+        table
+    }
+
+    /// Merge the segments that pass the FDR threshold into peaks
+    pub fn call_peaks(&self, max_fdr: f64, merge_dist: i64) -> Vec<FirePeak> {
+        let table = self.fdr_table();
+        let Some(&(threshold, _)) = table.iter().find(|(_, fdr)| *fdr <= max_fdr) else {
+            log::warn!("No score threshold has an FDR at or below {}.", max_fdr);
+            return vec![];
+        };
+        log::info!(
+            "Calling FIRE peaks with a score threshold of {}.",
+            threshold
+        );
+        // FDR of the score threshold closest to but not above a score
+        let fdr_of = |score: f32| {
+            let idx = table.partition_point(|(s, _)| *s <= score);
+            table[idx.saturating_sub(1)].1
+        };
+
+        let mut peaks: Vec<FirePeak> = vec![];
+        let mut last_chrom = None;
+        for seg in self.segments.iter().filter(|s| s.score >= threshold) {
+            match peaks.last_mut() {
+                Some(peak)
+                    if last_chrom == Some(seg.chrom) && seg.start - peak.end <= merge_dist =>
+                {
+                    peak.add_segment(seg)
+                }
+                _ => peaks.push(FirePeak::from_segment(&self.chroms[seg.chrom], seg)),
+            }
+            last_chrom = Some(seg.chrom);
+        }
+        for peak in peaks.iter_mut() {
+            peak.fdr = fdr_of(peak.max_score).max(f64::MIN_POSITIVE);
+        }
+        peaks
+    }
+
+    pub fn write_peaks(&self, pileup_opts: &PileupOptions, path: &str) -> Result<()> {
+        let peaks = self.call_peaks(pileup_opts.max_peak_fdr, pileup_opts.peak_merge_dist);
+        log::info!("Writing {} FIRE peaks to {}", peaks.len(), path);
+        let mut out = bio_io::writer(path)?;
+        for (idx, peak) in peaks.iter().enumerate() {
+            out.write_all(peak.to_narrow_peak(idx).as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for FirePeakCaller {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A stretch of bases with the same coverage and FIRE coverage in two groups and the p-value of the difference
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiffSegment {
+    pub chrom: String,
+    pub start: i64,
+    pub end: i64,
+    pub fire_coverage: [i32; 2],
+    pub coverage: [i32; 2],
+    pub p_value: f64,
+}
+
+impl DiffSegment {
+    /// difference in the fraction of fibers that are FIREs between the first and second group
+    pub fn diff(&self) -> f64 {
+        let frac = |i: usize| self.fire_coverage[i] as f64 / self.coverage[i] as f64;
+        frac(0) - frac(1)
+    }
+}
+
+/// Merged differential segments, with the counts and p-value of the most significant segment
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiffElement {
+    pub start: i64,
+    pub end: i64,
+    pub best: DiffSegment,
+    pub q_value: f64,
+}
+
+impl DiffElement {
+    pub fn header(groups: &[String; 2]) -> String {
+        format!(
+            "#chrom\tstart\tend\t{g1}_fire_coverage\t{g1}_coverage\t{g2}_fire_coverage\t{g2}_coverage\tdiff\tp_value\tq_value\n",
+            g1 = groups[0],
+            g2 = groups[1]
+        )
+    }
+}
+
+impl std::fmt::Display for DiffElement {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let b = &self.best;
+        write!(
+            f,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{:.4}\t{:.4e}\t{:.4e}",
+            b.chrom,
+            self.start,
+            self.end,
+            b.fire_coverage[0],
+            b.coverage[0],
+            b.fire_coverage[1],
+            b.coverage[1],
+            b.diff(),
+            b.p_value,
+            self.q_value
+        )
+    }
+}
+
+/// Tests for differential FIRE accessibility between two groups of fibers (e.g. haplotypes).
+///
+/// Every stretch of bases with the same counts in both groups is tested, and since only tests with a p-value
+/// at or below the FDR can be significant only those are kept along with the total number of tests.
+pub struct DiffCaller {
+    pub groups: [String; 2],
+    min_coverage: i32,
+    max_fdr: f64,
+    fisher: FisherExact,
+    n_tests: usize,
+    segments: Vec<DiffSegment>,
+}
+
+impl DiffCaller {
+    pub fn new(pileup_opts: &PileupOptions, groupings: &[FiberGrouping]) -> Result<Self> {
+        let groups: [String; 2] = if !pileup_opts.diff_groups.is_empty() {
+            pileup_opts
+                .diff_groups
+                .clone()
+                .try_into()
+                .map_err(|_| anyhow!("--diff-groups must be two comma separated groups."))?
+        } else if pileup_opts.haps {
+            ["H1".to_string(), "H2".to_string()]
+        } else {
+            return Err(anyhow!(
+                "--diff requires --haps or --diff-groups with two groups from --group-by or --group-tsv."
+            ));
+        };
+        for group in groups.iter() {
+            if !groupings.iter().any(|g| g.group_index.contains_key(group)) {
+                return Err(anyhow!(
+                    "--diff group {} is not one of the pileup groups.",
+                    group
+                ));
+            }
+        }
+        Ok(Self {
+            groups,
+            min_coverage: pileup_opts.min_diff_coverage,
+            max_fdr: pileup_opts.max_diff_fdr,
+            fisher: FisherExact::new(),
+            n_tests: 0,
+            segments: vec![],
+        })
+    }
+
+    /// Test the bases of a pileup with enough coverage in both groups
+    pub fn add_pileup(&mut self, pileup: &FiberseqPileup) {
+        if !pileup.has_data() {
+            return;
+        }
+        let (Some(g1), Some(g2)) = (
+            pileup.group_track(&self.groups[0]),
+            pileup.group_track(&self.groups[1]),
+        ) else {
+            return;
+        };
+        let counts = |i: usize| {
+            (
+                [g1.fire_coverage[i], g2.fire_coverage[i]],
+                [g1.coverage[i], g2.coverage[i]],
+            )
+        };
+        // the last position of the track is the first position of the next window
+        let len = pileup.track_len - 1;
+        let mut run_start = 0;
+        while run_start < len {
+            let (fire_coverage, coverage) = counts(run_start);
+            let mut run_end = run_start + 1;
+            while run_end < len && counts(run_end) == (fire_coverage, coverage) {
+                run_end += 1;
+            }
+            let start = run_start;
+            run_start = run_end;
+            if coverage.iter().any(|&c| c < self.min_coverage)
+                || fire_coverage.iter().all(|&f| f == 0)
+            {
+                continue;
+            }
+            self.n_tests += 1;
+            let p_value = self.fisher.two_sided(
+                fire_coverage[0] as u64,
+                (coverage[0] - fire_coverage[0]).max(0) as u64,
+                fire_coverage[1] as u64,
+                (coverage[1] - fire_coverage[1]).max(0) as u64,
+            );
+            if p_value <= self.max_fdr {
+                self.segments.push(DiffSegment {
+                    chrom: pileup.chrom.clone(),
+                    start: (pileup.chrom_start + start) as i64,
+                    end: (pileup.chrom_start + run_end) as i64,
+                    fire_coverage,
+                    coverage,
+                    p_value,
+                });
+            }
+        }
+    }
+
+    /// Merge adjacent significant segments that change in the same direction into elements
+    pub fn call_elements(&self) -> Vec<DiffElement> {
+        let p_values: Vec<f64> = self.segments.iter().map(|s| s.p_value).collect();
+        let q_values = benjamini_hochberg(&p_values, self.n_tests);
+        let mut elements: Vec<DiffElement> = vec![];
+        for (seg, q_value) in self.segments.iter().zip(q_values) {
+            if q_value > self.max_fdr {
+                continue;
+            }
+            match elements.last_mut() {
+                Some(el)
+                    if el.best.chrom == seg.chrom
+                        && el.end == seg.start
+                        && (el.best.diff() > 0.0) == (seg.diff() > 0.0) =>
+                {
+                    el.end = seg.end;
+                    if seg.p_value < el.best.p_value {
+                        el.best = seg.clone();
+                        el.q_value = q_value;
+                    }
+                }
+                _ => elements.push(DiffElement {
+                    start: seg.start,
+                    end: seg.end,
+                    best: seg.clone(),
+                    q_value,
+                }),
+            }
+        }
+        elements
+    }
+
+    pub fn write_elements(&self, path: &str) -> Result<()> {
+        let elements = self.call_elements();
+        log::info!(
+            "Writing {} differential elements between {} and {} from {} tests to {}",
+            elements.len(),
+            self.groups[0],
+            self.groups[1],
+            self.n_tests,
+            path
+        );
+        let mut out = bio_io::writer(path)?;
+        out.write_all(DiffElement::header(&self.groups).as_bytes())?;
+        for element in elements {
+            out.write_all(format!("{}\n", element).as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+enum TrackWriter {
+    BedGraph(Box<dyn Write>),
+    BigWig(Box<BigWigWriter>),
+}
+
+/// Writes each column of the pileup to its own bedGraph and/or bigWig file
+pub struct TrackWriters {
+    /// the writers of each column, in the order of `FiberseqPileup::column_names`
+    columns: Vec<Vec<TrackWriter>>,
+    keep_zeros: bool,
+}
+
+impl TrackWriters {
+    /// `chroms` are the (name, length) of the chromosomes in the order the pileup is made
+    pub fn new(
+        pileup_opts: &PileupOptions,
+        groupings: &[FiberGrouping],
+        chroms: &[(String, u32)],
+    ) -> Result<Self> {
+        let mut columns = vec![];
+        for name in FiberseqPileup::column_names(pileup_opts, groupings) {
+            let mut writers = vec![];
+            if let Some(prefix) = &pileup_opts.bedgraph {
+                let out = bio_io::writer(&format!("{}.{}.bedGraph", prefix, name))?;
+                writers.push(TrackWriter::BedGraph(out));
+            }
+            if let Some(prefix) = &pileup_opts.bigwig {
+                let out = BigWigWriter::create(&format!("{}.{}.bw", prefix, name), chroms)?;
+                writers.push(TrackWriter::BigWig(Box::new(out)));
+            }
+            columns.push(writers);
+        }
+        Ok(Self {
+            columns,
+            keep_zeros: pileup_opts.keep_zeros,
+        })
+    }
+
+    /// Write runs of the same value in each column of the pileup. Negative values (e.g. missing scores)
+    /// are never written and zeros are only written with --keep-zeros.
+    pub fn add_pileup(&mut self, pileup: &FiberseqPileup) -> Result<()> {
+        if !pileup.has_data() {
+            return Ok(());
+        }
+        // the last position of the track is the first position of the next window
+        let len = pileup.track_len - 1;
+        for (column, writers) in pileup.columns().iter().zip(self.columns.iter_mut()) {
+            let mut run_start = 0;
+            while run_start < len {
+                let value = column.value(run_start);
+                let mut run_end = run_start + 1;
+                while run_end < len && column.value(run_end) == value {
+                    run_end += 1;
+                }
+                if value > 0.0 || (value == 0.0 && self.keep_zeros) {
+                    let start = (pileup.chrom_start + run_start) as u32;
+                    let end = (pileup.chrom_start + run_end) as u32;
+                    for writer in writers.iter_mut() {
+                        match writer {
+                            TrackWriter::BedGraph(out) => {
+                                writeln!(out, "{}\t{}\t{}\t{}", pileup.chrom, start, end, value)?
+                            }
+                            TrackWriter::BigWig(out) => {
+                                out.add(&pileup.chrom, start, end, value)?
+                            }
+                        }
+                    }
+                }
+                run_start = run_end;
+            }
+        }
+        Ok(())
+    }
+
+    /// flush the bedGraph files and write the indexes of the bigWig files
+    pub fn finish(self) -> Result<()> {
+        for writer in self.columns.into_iter().flatten() {
+            match writer {
+                TrackWriter::BedGraph(mut out) => out.flush()?,
+                TrackWriter::BigWig(out) => out.finish()?,
+            }
+        }
+        Ok(())
+    }
+}
+
 /// split up a FetchDefinition into multiple regions of a certain size
-/// TODO set up run_rgn to take a list of regions and multithread it
 pub fn split_fetch_definition(
     rgn: &FetchDefinition,
     chrom_len: usize,
@@ -680,25 +1703,108 @@ pub fn split_fetch_definition(
     rgns
 }
 
+/// Make the pileup of a window, None if there are no fibers in the window
+fn window_pileup<'a>(
+    chrom: &str,
+    chrom_start: i64,
+    chrom_end: i64,
+    bam: &mut IndexedReader,
+    pileup_opts: &'a PileupOptions,
+    shuffled_fibers: &'a Option<ShuffledFibers>,
+    groupings: &'a [FiberGrouping],
+) -> Result<Option<FiberseqPileup<'a>>, anyhow::Error> {
+    // fibers shuffled into this window are read from their original positions
+    let shuffled_records = match shuffled_fibers {
+        Some(shuffled_fibers) => shuffled_fibers.fetch_shuffled_records(
+            bam,
+            chrom,
+            chrom_start,
+            chrom_end,
+            pileup_opts,
+        )?,
+        None => vec![],
+    };
+
+    // check if region has data
+    bam.fetch((chrom, chrom_start, chrom_end))?;
+    let mut tmp_records = bam.records();
+    if tmp_records.next().is_none() && shuffled_records.is_empty() {
+        return Ok(None);
+    }
+    // fetch the data
+    bam.fetch((chrom, chrom_start, chrom_end))?;
+    let records = bam.records();
+    // make the pileup
+    log::debug!(
+        "Initializing pileup for {}:{}-{}",
+        chrom,
+        chrom_start,
+        chrom_end
+    );
+    let mut pileup = FiberseqPileup::new(
+        chrom,
+        chrom_start as usize,
+        chrom_end as usize,
+        pileup_opts,
+        shuffled_fibers,
+        groupings,
+    );
+    // shuffled fibers are added first since scores are calculated once all the records are added
+    pileup.add_shuffled_records(shuffled_records);
+    pileup.add_records(records)?;
+    Ok(Some(pileup))
+}
+
+/// Everything that is made from the pileups, which are added in sorted order
+struct PileupOutputs {
+    out: Box<dyn Write>,
+    peak_caller: Option<FirePeakCaller>,
+    diff_caller: Option<DiffCaller>,
+    track_writers: Option<TrackWriters>,
+}
+
+impl PileupOutputs {
+    fn add_pileup(&mut self, pileup: &FiberseqPileup) -> Result<(), anyhow::Error> {
+        pileup.write(&mut self.out)?;
+        if let Some(peak_caller) = &mut self.peak_caller {
+            peak_caller.add_pileup(pileup);
+        }
+        if let Some(diff_caller) = &mut self.diff_caller {
+            diff_caller.add_pileup(pileup);
+        }
+        if let Some(track_writers) = &mut self.track_writers {
+            track_writers.add_pileup(pileup)?;
+        }
+        Ok(())
+    }
+
+    fn finish(self, pileup_opts: &PileupOptions) -> Result<(), anyhow::Error> {
+        if let (Some(peak_caller), Some(path)) = (self.peak_caller, &pileup_opts.peaks) {
+            peak_caller.write_peaks(pileup_opts, path)?;
+        }
+        if let (Some(diff_caller), Some(path)) = (self.diff_caller, &pileup_opts.diff) {
+            diff_caller.write_elements(path)?;
+        }
+        if let Some(track_writers) = self.track_writers {
+            track_writers.finish()?;
+        }
+        Ok(())
+    }
+}
+
 fn run_rgn(
     chrom: &str,
     rgn: FetchDefinition,
     bam: &mut IndexedReader,
-    out: &mut Box<dyn Write>,
+    outputs: &mut PileupOutputs,
     pileup_opts: &PileupOptions,
     shuffled_fibers: &Option<ShuffledFibers>,
+    groupings: &[FiberGrouping],
 ) -> Result<(), anyhow::Error> {
     let tid = bam.header().tid(chrom.as_bytes()).unwrap();
     let chrom_len = bam.header().target_len(tid).unwrap() as i64;
 
-    let window_size = if shuffled_fibers.is_some() {
-        (chrom_len + 1) as usize
-    } else {
-        WINDOW_SIZE
-    };
-    log::info!("Window size on {}: {}", chrom, window_size);
-
-    let windows = split_fetch_definition(&rgn, chrom_len as usize, window_size);
+    let windows = split_fetch_definition(&rgn, chrom_len as usize, WINDOW_SIZE);
     log::debug!("Splitting {} into {} windows", chrom, windows.len());
     for (chrom_start, mut chrom_end) in windows {
         if chrom_start >= chrom_len {
@@ -706,34 +1812,116 @@ fn run_rgn(
         } else if chrom_end > chrom_len {
             chrom_end = chrom_len;
         }
-
-        // check if region has data
-        bam.fetch((chrom, chrom_start, chrom_end))?;
-        let mut tmp_records = bam.records();
-        if tmp_records.next().is_none() {
-            continue;
-        }
-        // fetch the data
-        bam.fetch((chrom, chrom_start, chrom_end))?;
-        let records = bam.records();
-        // make the pileup
-        log::debug!(
-            "Initializing pileup for {}:{}-{}",
+        if let Some(pileup) = window_pileup(
             chrom,
             chrom_start,
-            chrom_end
-        );
-        let mut pileup = FiberseqPileup::new(
-            chrom,
-            chrom_start as usize,
-            chrom_end as usize,
+            chrom_end,
+            bam,
             pileup_opts,
             shuffled_fibers,
-        );
-        pileup.add_records(records)?;
-        pileup.write(out)?;
+            groupings,
+        )? {
+            outputs.add_pileup(&pileup)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Read the regions of a bed file, sorted in the order of the bam header with overlapping regions merged
+fn read_bed_regions(path: &str, header: &bam::HeaderView) -> Result<Vec<(String, i64, i64)>> {
+    let mut regions = vec![];
+    for line in bio_io::buffer_from(path)?.lines() {
+        let line = line?;
+        if line.starts_with('#') || line.trim().is_empty() {
+            continue;
+        }
+        let mut parts = line.split('\t');
+        let chrom = parts.next().ok_or(anyhow!("missing chrom"))?;
+        let start = parts
+            .next()
+            .ok_or(anyhow!("missing start"))?
+            .parse::<i64>()?;
+        let end = parts.next().ok_or(anyhow!("missing end"))?.parse::<i64>()?;
+        let Some(tid) = header.tid(chrom.as_bytes()) else {
+            log::warn!(
+                "Skipping region on {}, which is not in the bam header",
+                chrom
+            );
+            continue;
+        };
+        let chrom_len = header.target_len(tid).unwrap() as i64;
+        let (start, end) = (start.max(0), end.min(chrom_len));
+        if start < end {
+            regions.push((tid, start, end));
+        }
+    }
+    regions.sort();
+    let mut merged: Vec<(u32, i64, i64)> = vec![];
+    for (tid, start, end) in regions {
+        match merged.last_mut() {
+            Some(last) if last.0 == tid && start <= last.2 => last.2 = last.2.max(end),
+            _ => merged.push((tid, start, end)),
+        }
     }
+    Ok(merged
+        .into_iter()
+        .map(|(tid, start, end)| {
+            let chrom = String::from_utf8_lossy(header.tid2name(tid)).to_string();
+            (chrom, start, end)
+        })
+        .collect())
+}
 
+/// Make pileups of the regions in parallel, each thread reading from its own indexed bam.
+/// Regions are processed in batches and the pileups of a batch are added to the outputs in order.
+fn run_bed_regions(
+    regions: &[(String, i64, i64)],
+    outputs: &mut PileupOutputs,
+    pileup_opts: &PileupOptions,
+    shuffled_fibers: &Option<ShuffledFibers>,
+    groupings: &[FiberGrouping],
+) -> Result<(), anyhow::Error> {
+    let windows: Vec<(&str, i64, i64)> = regions
+        .iter()
+        .flat_map(|(chrom, start, end)| {
+            let rgn = FetchDefinition::RegionString(chrom.as_bytes(), *start, *end);
+            split_fetch_definition(&rgn, *end as usize, WINDOW_SIZE)
+                .into_iter()
+                .map(move |(st, en)| (chrom.as_str(), st, en))
+        })
+        .collect();
+    log::info!(
+        "Making pileups of {} regions in {} windows",
+        regions.len(),
+        windows.len()
+    );
+    let batch_size = pileup_opts.input.global.threads.max(1) * 4;
+    for batch in windows.chunks(batch_size) {
+        let pileups: Vec<Option<FiberseqPileup>> = batch
+            .par_iter()
+            .map_init(
+                || bam::IndexedReader::from_path(&pileup_opts.input.bam),
+                |bam, (chrom, start, end)| {
+                    let bam = bam
+                        .as_mut()
+                        .map_err(|e| anyhow!("Unable to open {}: {}", pileup_opts.input.bam, e))?;
+                    window_pileup(
+                        chrom,
+                        *start,
+                        *end,
+                        bam,
+                        pileup_opts,
+                        shuffled_fibers,
+                        groupings,
+                    )
+                },
+            )
+            .collect::<Result<_, _>>()?;
+        for pileup in pileups.iter().flatten() {
+            outputs.add_pileup(pileup)?;
+        }
+    }
     Ok(())
 }
 
@@ -742,43 +1930,355 @@ pub fn pileup_track(pileup_opts: &mut PileupOptions) -> Result<(), anyhow::Error
     // read in the bam from stdin or from a file
     let mut bam = pileup_opts.input.indexed_bam_reader();
     let header = pileup_opts.input.header_view();
+    let bed_regions = match &pileup_opts.bed {
+        Some(bed) => Some(read_bed_regions(bed, &header)?),
+        None => None,
+    };
+    // chromosomes that are part of the pileup
+    let chroms: Vec<String> = match (&pileup_opts.rgn, &bed_regions) {
+        (Some(rgn), _) => vec![region_parser(rgn).1],
+        (None, Some(regions)) => regions
+            .iter()
+            .map(|(chrom, _, _)| chrom.clone())
+            .dedup()
+            .collect(),
+        (None, None) => header
+            .target_names()
+            .iter()
+            .map(|c| String::from_utf8_lossy(c).to_string())
+            .collect(),
+    };
+
+    let mut groupings = vec![];
+    if pileup_opts.haps {
+        groupings.push(FiberGrouping::haplotypes());
+    }
+    if let Some(grouping) = FiberGrouping::from_opts(&bam, pileup_opts)? {
+        groupings.push(grouping);
+    }
 
     let mut out = bio_io::writer(&pileup_opts.out)?;
     // add the header
-    out.write_all(FiberseqPileup::header(pileup_opts).as_bytes())?;
+    out.write_all(FiberseqPileup::header(pileup_opts, &groupings).as_bytes())?;
 
     let shuffled_fibers = match &pileup_opts.shuffle {
         Some(file_path) => Some(ShuffledFibers::new(file_path)?),
+        None if pileup_opts.auto_shuffle => {
+            Some(ShuffledFibers::from_bam(&mut bam, &chroms, pileup_opts)?)
+        }
+        None => None,
+    };
+    if pileup_opts.peaks.is_some() && shuffled_fibers.is_none() {
+        return Err(anyhow!(
+            "--peaks requires a shuffled track, use --shuffle or --auto-shuffle."
+        ));
+    }
+    let peak_caller = pileup_opts.peaks.as_ref().map(|_| FirePeakCaller::new());
+    let diff_caller = match &pileup_opts.diff {
+        Some(_) => Some(DiffCaller::new(pileup_opts, &groupings)?),
         None => None,
     };
+    let track_writers = if pileup_opts.bedgraph.is_some() || pileup_opts.bigwig.is_some() {
+        let chrom_sizes: Vec<(String, u32)> = header
+            .target_names()
+            .iter()
+            .enumerate()
+            .map(|(tid, name)| {
+                (
+                    String::from_utf8_lossy(name).to_string(),
+                    header.target_len(tid as u32).unwrap() as u32,
+                )
+            })
+            .collect();
+        Some(TrackWriters::new(pileup_opts, &groupings, &chrom_sizes)?)
+    } else {
+        None
+    };
+    let mut outputs = PileupOutputs {
+        out,
+        peak_caller,
+        diff_caller,
+        track_writers,
+    };
 
-    match &pileup_opts.rgn {
+    match (&pileup_opts.rgn, &bed_regions) {
         // if a region is specified, only process that region
-        Some(rgn) => {
+        (Some(rgn), _) => {
             let (rgn, chrom) = region_parser(rgn);
             run_rgn(
                 &chrom,
                 rgn,
                 &mut bam,
-                &mut out,
+                &mut outputs,
+                pileup_opts,
+                &shuffled_fibers,
+                &groupings,
+            )?;
+        }
+        // if a bed file is specified, process its regions in parallel
+        (None, Some(regions)) => {
+            run_bed_regions(
+                regions,
+                &mut outputs,
                 pileup_opts,
                 &shuffled_fibers,
+                &groupings,
             )?;
         }
         // if no region is specified, process all regions
-        None => {
+        (None, None) => {
             for chrom in header.target_names() {
                 let rgn = FetchDefinition::String(chrom);
                 run_rgn(
                     &String::from_utf8_lossy(chrom),
                     rgn,
                     &mut bam,
-                    &mut out,
+                    &mut outputs,
                     pileup_opts,
                     &shuffled_fibers,
+                    &groupings,
                 )?;
             }
         }
     }
-    Ok(())
+    outputs.finish(pileup_opts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::input_bam::FiberFilters;
+    use clap::Parser;
+    use rust_htslib::bam::record::{Cigar, CigarString};
+
+    fn segment(start: i64, end: i64, score: f32) -> ScoreSegment {
+        ScoreSegment {
+            chrom: 0,
+            start,
+            end,
+            score,
+        }
+    }
+
+    /// a fiber aligned to 100-108 with the given MM tag and an ML of 200 for each call
+    fn m6a_fiber(seq: &[u8], mm: Option<&str>, n_calls: usize) -> FiberseqData {
+        let mut rec = bam::Record::new();
+        let cigar = CigarString(vec![Cigar::Match(seq.len() as u32)]);
+        rec.set(b"read", Some(&cigar), seq, &vec![30; seq.len()]);
+        rec.set_tid(0);
+        rec.set_pos(100);
+        if let Some(mm) = mm {
+            rec.push_aux(b"MM", Aux::String(mm)).unwrap();
+            rec.push_aux(b"ML", Aux::ArrayU8((&vec![200u8; n_calls]).into()))
+                .unwrap();
+        }
+        FiberseqData::new(rec, None, &FiberFilters::default())
+    }
+
+    #[test]
+    fn test_m6a_fraction() {
+        #[derive(Parser)]
+        struct Cli {
+            #[clap(flatten)]
+            opts: PileupOptions,
+        }
+        let opts = Cli::parse_from(["ft", "test.bam", "--m6a-fraction"]).opts;
+        let mut track = FireTrack::new(100, 108, &opts, &None);
+        // m6A on both strands, A at 1 and T at 2
+        track.add_m6a_fraction(&m6a_fiber(b"AATTCCAT", Some("A+a,1;T-a,0;"), 2));
+        // single strand m6A at the A at 1, the As at 0 and 6 were not assessed
+        track.add_m6a_fraction(&m6a_fiber(b"AATTCCAT", Some("A+a?,1;"), 1));
+        // no MM tag, nothing is assessed
+        track.add_m6a_fraction(&m6a_fiber(b"AATTCCAT", None, 0));
+        // single strand m6A code without any calls, the As are assessed and unmodified
+        track.add_m6a_fraction(&m6a_fiber(b"AATTCCAT", Some("A+a.;"), 0));
+        track.calculate_scores();
+        assert_eq!(track.a_coverage, vec![2, 3, 0, 0, 0, 0, 2, 0, 0]);
+        assert_eq!(track.t_coverage, vec![0, 0, 1, 1, 0, 0, 0, 1, 0]);
+        assert_eq!(track.at_coverage, vec![2, 3, 1, 1, 0, 0, 2, 1, 0]);
+        assert_eq!(
+            track.m6a_fraction[0],
+            vec![0.0, 2.0 / 3.0, 1.0, 0.0, -1.0, -1.0, 0.0, 0.0, -1.0]
+        );
+        assert_eq!(track.m6a_fraction[1][1], 2.0 / 3.0);
+        assert_eq!(track.m6a_fraction[2][3], 0.0);
+    }
+
+    #[test]
+    fn test_read_bed_regions() {
+        let mut header = bam::Header::new();
+        for (name, len) in [("chr2", 1000), ("chr1", 500)] {
+            let mut sq = bam::header::HeaderRecord::new(b"SQ");
+            sq.push_tag(b"SN", name);
+            sq.push_tag(b"LN", len);
+            header.push_record(&sq);
+        }
+        let header = bam::HeaderView::from_header(&header);
+        let mut bed = tempfile::NamedTempFile::new().unwrap();
+        bed.write_all(
+            b"# regions\nchr1\t100\t200\tname\nchr2\t50\t80\nchr1\t150\t300\nchrUn\t0\t10\nchr2\t0\t20\nchr2\t80\t90\nchr1\t450\t900\n",
+        )
+        .unwrap();
+        let regions = read_bed_regions(bed.path().to_str().unwrap(), &header).unwrap();
+        assert_eq!(
+            regions,
+            vec![
+                // sorted in the order of the header, with overlapping and book-ended regions merged
+                ("chr2".to_string(), 0, 20),
+                ("chr2".to_string(), 50, 90),
+                ("chr1".to_string(), 100, 300),
+                // clipped to the length of the chromosome
+                ("chr1".to_string(), 450, 500),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_overlaps_region() {
+        let regions = vec![(10, 20), (30, 40)];
+        assert!(!overlaps_region(&regions, 0, 10));
+        assert!(overlaps_region(&regions, 0, 11));
+        assert!(!overlaps_region(&regions, 20, 30));
+        assert!(overlaps_region(&regions, 25, 45));
+        assert!(!overlaps_region(&regions, 40, 50));
+    }
+
+    #[test]
+    fn test_shuffled_overlapping() {
+        let fiber =
+            |st: i64, en: i64, name: &str| ("chr1".to_string(), (st, en, name.to_string(), 5_000));
+        let shuffled = ShuffledFibers::from_shuffled(vec![
+            fiber(500, 1500, "b"),
+            fiber(0, 100, "a"),
+            fiber(2000, 2100, "c"),
+        ]);
+        let names = |start, end| {
+            shuffled
+                .overlapping("chr1", start, end)
+                .iter()
+                .map(|(_, _, name, _)| name.as_str())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(0, 3000), vec!["a", "b", "c"]);
+        assert_eq!(names(1000, 1100), vec!["b"]);
+        assert_eq!(names(100, 500), Vec::<&str>::new());
+        assert!(shuffled.overlapping("chr2", 0, 3000).is_empty());
+    }
+
+    #[test]
+    fn test_group_column_names() {
+        let haps = FiberGrouping::haplotypes();
+        assert_eq!(haps.column_name(1, "coverage"), "coverage_H2");
+        let read_groups = HashMap::from([("read".to_string(), "sample2".to_string())]);
+        let samples = FiberGrouping::new(
+            GroupKey::ReadName(read_groups),
+            vec!["sample1".to_string(), "sample2".to_string()],
+            true,
+        );
+        assert_eq!(samples.column_name(0, "score"), "sample1_score");
+        assert_eq!(samples.group_index.get("sample2"), Some(&1));
+    }
+
+    #[test]
+    fn test_diff_elements() {
+        let mut caller = DiffCaller {
+            groups: ["H1".to_string(), "H2".to_string()],
+            min_coverage: 4,
+            max_fdr: 0.05,
+            fisher: FisherExact::new(),
+            n_tests: 10,
+            segments: vec![],
+        };
+        let seg = |start: i64, end: i64, fire: [i32; 2], p_value: f64| DiffSegment {
+            chrom: "chr1".to_string(),
+            start,
+            end,
+            fire_coverage: fire,
+            coverage: [10, 10],
+            p_value,
+        };
+        caller.segments = vec![
+            seg(0, 10, [9, 1], 0.001),
+            seg(10, 20, [8, 1], 0.0001),
+            // opposite direction
+            seg(20, 30, [1, 9], 0.001),
+            // not significant after correction
+            seg(50, 60, [6, 2], 0.04),
+        ];
+        let elements = caller.call_elements();
+        assert_eq!(elements.len(), 2);
+        assert_eq!((elements[0].start, elements[0].end), (0, 20));
+        assert_eq!(elements[0].best.p_value, 0.0001);
+        assert!((elements[0].q_value - 0.001).abs() < 1e-12);
+        assert_eq!((elements[1].start, elements[1].end), (20, 30));
+        assert!(elements[1].best.diff() < 0.0);
+    }
+
+    #[test]
+    /// the last base of a window is the first base of the next window and is only counted once
+    fn test_fire_peak_window_boundaries() {
+        #[derive(Parser)]
+        struct Cli {
+            #[clap(flatten)]
+            opts: PileupOptions,
+        }
+        let opts = Cli::parse_from(["ft", "test.bam"]).opts;
+        let mut caller = FirePeakCaller::new();
+        for start in [100, 110] {
+            let mut pileup = FiberseqPileup::new("chr1", start, start + 10, &opts, &None, &[]);
+            pileup.has_data = true;
+            pileup.all_data.scores = vec![5.0; pileup.track_len];
+            caller.add_pileup(&pileup);
+        }
+        assert_eq!(caller.score_bp[&FirePeakCaller::score_key(5.0)], (20, 0));
+        let segments: Vec<(i64, i64)> = caller.segments.iter().map(|s| (s.start, s.end)).collect();
+        assert_eq!(segments, vec![(100, 110), (110, 120)]);
+    }
+
+    #[test]
+    fn test_fire_peaks() {
+        let mut caller = FirePeakCaller::new();
+        // observed: 100 bp at score 10 and 100 bp at score 50, shuffled: 100 bp at score 10
+        caller
+            .score_bp
+            .insert(FirePeakCaller::score_key(10.0), (100, 100));
+        caller
+            .score_bp
+            .insert(FirePeakCaller::score_key(50.0), (100, 0));
+        caller.chrom_index("chr1");
+        caller.segments = vec![
+            segment(0, 50, 10.0),
+            segment(100, 140, 50.0),
+            segment(140, 150, 20.0),
+            segment(160, 220, 50.0),
+        ];
+        let table = caller.fdr_table();
+        assert_eq!(table, vec![(10.0, 0.5), (50.0, 0.0)]);
+
+        let peaks = caller.call_peaks(0.05, 0);
+        assert_eq!(peaks.len(), 2);
+        assert_eq!(
+            (peaks[0].start, peaks[0].end, peaks[0].summit),
+            (100, 140, 120)
+        );
+        assert_eq!((peaks[1].start, peaks[1].end), (160, 220));
+
+        let peaks = caller.call_peaks(0.05, 20);
+        assert_eq!(peaks.len(), 1);
+        assert_eq!((peaks[0].start, peaks[0].end), (100, 220));
+        assert!(peaks[0]
+            .to_narrow_peak(0)
+            .starts_with("chr1\t100\t220\tFIRE_peak_0\t1000\t.\t50"));
+
+        // peaks are not merged across chromosomes
+        let chr2 = caller.chrom_index("chr2");
+        caller.segments.push(ScoreSegment {
+            chrom: chr2,
+            ..segment(225, 230, 50.0)
+        });
+        let peaks = caller.call_peaks(0.05, 20);
+        assert_eq!(peaks.len(), 2);
+        assert_eq!(peaks[1].chrom, "chr2");
+        assert_eq!(caller.chrom_index("chr1"), 0);
+    }
 }
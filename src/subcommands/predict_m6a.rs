@@ -1,18 +1,25 @@
 use crate::cli::PredictM6AOptions;
 use crate::utils::basemods;
 use crate::utils::bio_io;
+use crate::utils::checkpoint::Checkpoint;
 use crate::utils::nucleosome;
 use crate::*;
 use bio::alphabets::dna::revcomp;
 use burn::tensor::backend::Backend;
 use fiber::FiberseqData;
+use gbdt::gradient_boost::GBDT;
 use ordered_float::OrderedFloat;
 use rayon::iter::IndexedParallelIterator;
 use rayon::iter::ParallelIterator;
 use rayon::prelude::IntoParallelRefMutIterator;
+use rust_htslib::bam::record::{Aux, AuxArray};
 use rust_htslib::{bam, bam::Read};
 use serde::Deserialize;
 use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::sync_channel;
+use std::time::{Duration, Instant};
+use utils::fire::MapPrecisionValues;
 
 pub const WINDOW: usize = 15;
 pub const LAYERS: usize = 6;
@@ -41,10 +48,13 @@ where
     pub batch_size: usize,
     map: BTreeMap<OrderedFloat<f32>, u8>,
     pub model: Vec<u8>,
+    pub model_path: Option<String>,
+    pub precision_table_path: Option<String>,
     pub min_ml: u8,
     pub nuc_opts: cli::NucleosomeParameters,
     pub burn_models: m6a_burn::BurnModels<B>,
     pub fake: bool,
+    pub raw_probabilities: bool,
 }
 
 impl<B> PredictOptions<B>
@@ -60,11 +70,22 @@ where
         batch_size: usize,
         nuc_opts: cli::NucleosomeParameters,
         fake: bool,
+        model_path: Option<String>,
+        precision_table_path: Option<String>,
+        raw_probabilities: bool,
     ) -> Self {
         // set up a precision table
         let mut map = BTreeMap::new();
         map.insert(OrderedFloat(0.0), 0);
 
+        // load a user supplied model or the default model for the chemistry
+        let burn_models = match &model_path {
+            Some(path) => {
+                m6a_burn::BurnModels::from_record_file(path).expect("Error loading model")
+            }
+            None => m6a_burn::BurnModels::new(&polymerase),
+        };
+
         // return prediction options
         let mut options = PredictOptions {
             keep,
@@ -74,10 +95,13 @@ where
             batch_size,
             map,
             model: vec![],
+            model_path,
+            precision_table_path,
             min_ml: 0,
             nuc_opts,
-            burn_models: m6a_burn::BurnModels::new(&polymerase),
+            burn_models,
             fake,
+            raw_probabilities,
         };
         options.add_model().expect("Error loading model");
         options
@@ -85,7 +109,7 @@ where
 
     fn get_precision_table_and_ml(&self) -> Result<(Option<PrecisionTable>, u8)> {
         let mut precision_json = "".to_string();
-        let min_ml = if let Ok(_file) = std::env::var("FT_MODEL") {
+        let min_ml = if self.model_path.is_some() {
             244
         } else {
             log::info!("Using semi-supervised CNN m6A model.");
@@ -109,11 +133,11 @@ where
             }
         };
 
-        // load precision json from env var if needed
-        if let Ok(json) = std::env::var("FT_JSON") {
-            log::info!("Loading precision table from environment variable.");
+        // load a user supplied precision json if needed
+        if let Some(json) = &self.precision_table_path {
+            log::info!("Loading precision table from {}.", json);
             precision_json =
-                std::fs::read_to_string(json).expect("Unable to read file specified by FT_JSON");
+                std::fs::read_to_string(json).expect("Unable to read the precision table file");
         }
 
         // load the precision table
@@ -223,12 +247,17 @@ where
                 Some((a_data, t_data)) => (a_data, t_data),
                 None => continue,
             };
+            // raw model outputs for every A and T in the read
+            let mut raw_probabilities = vec![];
             // iterate over A and then T basemods
             for data in &[a_data, t_data] {
                 let cur_predict_en = cur_predict_st + data.count;
                 let cur_predictions = &predictions[cur_predict_st..cur_predict_en];
 
                 cur_predict_st += data.count;
+                if opts.raw_probabilities {
+                    raw_probabilities.extend(data.positions.iter().zip(cur_predictions.iter()));
+                }
                 cur_basemods.base_mods.push(opts.basemod_from_ml(
                     record,
                     cur_predictions,
@@ -238,6 +267,9 @@ where
             }
             // write the ml and mm tags
             cur_basemods.add_mm_and_ml_tags(record);
+            if opts.raw_probabilities {
+                add_raw_probabilities_to_record(record, raw_probabilities);
+            }
 
             //let modified_bases_forward = cur_basemods.forward_m6a().0;
             let modified_bases_forward = cur_basemods.m6a().get_forward_starts();
@@ -294,10 +326,10 @@ where
             predictions.len()
         );
 
+        let modification_type = &base_mod[2..];
         let base_mod = base_mod.as_bytes();
         let modified_base = base_mod[0];
         let strand = base_mod[1] as char;
-        let modification_type = base_mod[2] as char;
 
         basemods::BaseMod::new(
             record,
@@ -318,6 +350,22 @@ where
     }
 }
 
+/// Add the raw model output for every A and T in the read to the `mp` tag.
+/// Values are ordered by position in the original (unaligned) read sequence,
+/// so the nth value belongs to the nth A or T of the forward read.
+pub fn add_raw_probabilities_to_record(
+    record: &mut bam::Record,
+    mut raw_probabilities: Vec<(&usize, &f32)>,
+) {
+    raw_probabilities.sort_by_key(|(pos, _)| **pos);
+    let probabilities: Vec<f32> = raw_probabilities.into_iter().map(|(_, p)| *p).collect();
+    let aux_array: AuxArray<f32> = (&probabilities).into();
+    record.remove_aux(b"mp").unwrap_or(());
+    record
+        .push_aux(b"mp", Aux::ArrayFloat(aux_array))
+        .expect("Cannot add raw m6A probabilities to bam");
+}
+
 /// ```
 /// use fibertools_rs::subcommands::predict_m6a::hot_one_dna;
 /// let x: Vec<u8> = vec![b'A', b'G', b'T', b'C', b'A'];
@@ -484,10 +532,126 @@ fn get_m6a_data_windows(record: &bam::Record) -> Option<(DataWidows, DataWidows)
     Some((a_data, t_data))
 }
 
-pub fn read_bam_into_fiberdata(opts: &mut PredictM6AOptions) {
+/// Get the chemistry from the command line or from the bam header
+pub fn get_chemistry(opts: &PredictM6AOptions, header: &bam::Header) -> Result<PbChem> {
+    match &opts.chemistry {
+        Some(chem) => {
+            let chemistry: PbChem = chem.parse()?;
+            log::info!(
+                "Using PacBio chemistry {} from the command line.",
+                chemistry.name()
+            );
+            Ok(chemistry)
+        }
+        None => find_pb_polymerase(header, opts.chemistry_registry.as_deref()),
+    }
+}
+
+/// Throughput of a single stage in the prediction pipeline
+#[derive(Debug, Default)]
+struct StageStats {
+    reads: usize,
+    chunks: usize,
+    busy: Duration,
+}
+
+impl StageStats {
+    fn add(&mut self, reads: usize, start: Instant) {
+        self.reads += reads;
+        self.chunks += 1;
+        self.busy += start.elapsed();
+    }
+
+    fn log(&self, stage: &str, total: Duration) {
+        let busy = self.busy.as_secs_f64();
+        log::info!(
+            "{} stage: {} reads in {} chunks, {:.2} reads/s while busy, busy for {:.1}% of the run.",
+            stage,
+            self.reads,
+            self.chunks,
+            if busy > 0.0 { self.reads as f64 / busy } else { 0.0 },
+            100.0 * busy / total.as_secs_f64().max(f64::EPSILON)
+        );
+    }
+}
+
+/// Predict m6A, add nucleosomes, and add FIREs (unless `fire_model` is None) to a chunk of bam records
+fn predict_chunk<B>(
+    mut chunk: Vec<bam::Record>,
+    predict_options: &PredictOptions<B>,
+    header_view: &bam::HeaderView,
+    opts: &PredictM6AOptions,
+    fire_opts: &cli::FireOptions,
+    fire_model: &Option<(GBDT, MapPrecisionValues)>,
+) -> Vec<bam::Record>
+where
+    B: Backend<Device = m6a_burn::BurnDevice>,
+{
+    // add m6a calls
+    let number_of_reads_with_predictions = chunk
+        .par_iter_mut()
+        .chunks(predict_options.batch_size)
+        .map(|records| PredictOptions::predict_m6a_on_records(predict_options, records))
+        .sum::<usize>() as f32;
+
+    let frac_called = number_of_reads_with_predictions / chunk.len() as f32;
+    if frac_called < 0.05 {
+        log::warn!("More than 5% ({:.2}%) of reads were not predicted on. Are HiFi kinetics missing from this file? Enable Debug logging level to show which reads lack kinetics.", 100.0-100.0*frac_called);
+    }
+
+    let (model, precision_table) = match fire_model {
+        Some(fire_model) => fire_model,
+        None => return chunk,
+    };
+
+    // covert to FiberData and do FIRE predictions
+    let mut fd_recs = FiberseqData::from_records(chunk, header_view, &opts.input.filters);
+    let labelling = fire_opts.labelling_tag || fire_opts.min_labelling_efficiency.is_some();
+    let efficiencies: Vec<Option<f64>> = fd_recs
+        .par_iter_mut()
+        .map(|fd| {
+            crate::subcommands::fire::add_fire_to_rec(fd, fire_opts, model, precision_table);
+            if labelling {
+                crate::subcommands::fire::add_labelling_to_rec(fd, fire_opts)
+            } else {
+                None
+            }
+        })
+        .collect();
+    let n_reads = fd_recs.len();
+    // skip under labelled reads, reads without an estimate are kept
+    let records: Vec<bam::Record> = fd_recs
+        .into_iter()
+        .zip(efficiencies)
+        .filter(
+            |(_, efficiency)| match (fire_opts.min_labelling_efficiency, efficiency) {
+                (Some(min), Some(efficiency)) => *efficiency >= min,
+                _ => true,
+            },
+        )
+        .map(|(fd, _)| fd.record)
+        .collect();
+    if records.len() < n_reads {
+        log::debug!(
+            "Skipped {} reads with a labelling efficiency less than {}",
+            n_reads - records.len(),
+            fire_opts.min_labelling_efficiency.unwrap_or_default()
+        );
+    }
+    records
+}
+
+/// Predict m6A (and optionally FIREs) on a bam file using three stages connected by bounded queues:
+/// a reader thread, m6A inference on the calling thread (parallelized with rayon), and a writer thread.
+/// Chunks pass through each stage one at a time, in order, so the output has the same order as the input.
+pub fn read_bam_into_fiberdata(opts: &mut PredictM6AOptions) -> Result<()> {
     let mut bam = opts.input.bam_reader();
+    let mut checkpoint = Checkpoint::new(&opts.out, &opts.checkpoint)?;
     let mut out = opts.input.bam_writer(&opts.out);
+    checkpoint.resume(&mut bam, &mut out)?;
     let header = bam::Header::from_template(bam.header());
+    let opts = &*opts;
+    let header_view = opts.input.header_view();
     // log the options
     log::info!(
         "{} reads included at once in batch prediction.",
@@ -509,41 +673,123 @@ pub fn read_bam_into_fiberdata(opts: &mut PredictM6AOptions) {
         opts.keep,
         opts.force_min_ml_score,
         opts.all_calls,
-        find_pb_polymerase(&header),
+        get_chemistry(opts, &header)?,
         opts.batch_size,
         opts.nuc.clone(),
         opts.fake,
+        opts.model.clone(),
+        opts.precision_table.clone(),
+        opts.raw_probabilities,
     );
-    // get default fire options
-    let fire_opts = crate::cli::FireOptions::default();
-    let (model, precision_table) = crate::utils::fire::get_model(&fire_opts);
-
-    // read in bam data
-    let bam_chunk_iter = BamChunk::new(bam.records(), None);
-    // iterate over chunks
-    for mut chunk in bam_chunk_iter {
-        // add m6a calls
-        let number_of_reads_with_predictions = chunk
-            .par_iter_mut()
-            .chunks(predict_options.batch_size)
-            .map(|records| PredictOptions::predict_m6a_on_records(&predict_options, records))
-            .sum::<usize>() as f32;
-
-        let frac_called = number_of_reads_with_predictions / chunk.len() as f32;
-        if frac_called < 0.05 {
-            log::warn!("More than 5% ({:.2}%) of reads were not predicted on. Are HiFi kinetics missing from this file? Enable Debug logging level to show which reads lack kinetics.", 100.0-100.0*frac_called);
-        }
+    // get the fire options and model
+    let fire_opts = opts.fire_options();
+    let fire_model = if opts.skip_fire {
+        log::info!("Skipping FIRE calls.");
+        None
+    } else {
+        Some(crate::utils::fire::get_model(&fire_opts))
+    };
 
-        // covert to FiberData and do FIRE predictions
-        let mut fd_recs =
-            FiberseqData::from_records(chunk, &opts.input.header_view(), &opts.input.filters);
-        fd_recs.par_iter_mut().for_each(|fd| {
-            crate::subcommands::fire::add_fire_to_rec(fd, &fire_opts, &model, &precision_table);
+    // bounded queues between the stages, with counters so we can log how full they are
+    let queue_size = opts.queue_size.max(1);
+    // chunks are sent along with the number of input records they consumed, for checkpointing
+    let (read_tx, read_rx) = sync_channel::<(Vec<bam::Record>, u64)>(queue_size);
+    let (write_tx, write_rx) = sync_channel::<(Vec<bam::Record>, u64)>(queue_size);
+    let read_depth = AtomicUsize::new(0);
+    let write_depth = AtomicUsize::new(0);
+    let run_start = Instant::now();
+
+    std::thread::scope(|scope| -> Result<()> {
+        // reader stage
+        let read_depth_ref = &read_depth;
+        let reader = scope.spawn(move || {
+            let mut stats = StageStats::default();
+            let mut start = Instant::now();
+            let mut chunks = BamChunk::new(bam.records(), None);
+            let mut records_read = 0;
+            while let Some(chunk) = chunks.next() {
+                stats.add(chunk.len(), start);
+                read_depth_ref.fetch_add(1, Ordering::Relaxed);
+                let consumed = chunks.records_read - records_read;
+                records_read = chunks.records_read;
+                // the receiver is only dropped if inference stopped early
+                if read_tx.send((chunk, consumed)).is_err() {
+                    break;
+                }
+                start = Instant::now();
+            }
+            stats
         });
 
-        // write to output
-        fd_recs.iter().for_each(|fd| out.write(&fd.record).unwrap());
-    }
+        // writer stage
+        let write_depth_ref = &write_depth;
+        let writer = scope.spawn(move || -> Result<(StageStats, Checkpoint)> {
+            let mut stats = StageStats::default();
+            for (records, consumed) in write_rx {
+                write_depth_ref.fetch_sub(1, Ordering::Relaxed);
+                let start = Instant::now();
+                for record in records.iter() {
+                    out.write(record)?;
+                }
+                checkpoint.add_chunk(
+                    consumed,
+                    records.len() as u64,
+                    records.last().map(|r| r.qname()),
+                )?;
+                stats.add(records.len(), start);
+            }
+            // close the output before the checkpoint is removed
+            drop(out);
+            Ok((stats, checkpoint))
+        });
+
+        // inference stage
+        let mut stats = StageStats::default();
+        for (chunk, consumed) in read_rx {
+            read_depth.fetch_sub(1, Ordering::Relaxed);
+            let start = Instant::now();
+            let n_reads = chunk.len();
+            let records = predict_chunk(
+                chunk,
+                &predict_options,
+                &header_view,
+                opts,
+                &fire_opts,
+                &fire_model,
+            );
+            stats.add(n_reads, start);
+            log::debug!(
+                "Predicted m6A on {} reads at {:.2} reads/s. Queue depths: reader {}/{}, writer {}/{}.",
+                n_reads,
+                n_reads as f64 / start.elapsed().as_secs_f64(),
+                read_depth.load(Ordering::Relaxed),
+                queue_size,
+                write_depth.load(Ordering::Relaxed),
+                queue_size
+            );
+            write_depth.fetch_add(1, Ordering::Relaxed);
+            // the writer only hangs up if it hit an error, which is reported below
+            if write_tx.send((records, consumed)).is_err() {
+                break;
+            }
+        }
+        // let the writer know there is no more data
+        drop(write_tx);
+
+        let read_stats = reader
+            .join()
+            .map_err(|_| anyhow::anyhow!("The bam reading thread panicked."))?;
+        let (write_stats, checkpoint) = writer
+            .join()
+            .map_err(|_| anyhow::anyhow!("The bam writing thread panicked."))??;
+        checkpoint.finish()?;
+
+        let total = run_start.elapsed();
+        read_stats.log("Read", total);
+        stats.log("Prediction", total);
+        write_stats.log("Write", total);
+        Ok(())
+    })
 }
 
 /// tests
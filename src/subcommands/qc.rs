@@ -1,13 +1,36 @@
-use crate::cli::QcOpts;
+use crate::cli::{QcMergeOpts, QcOpts};
 use crate::fiber;
+use crate::utils::acf::{self, acf_peak, bootstrap_acf, AcfEstimate, AcfSums};
 use crate::utils::bio_io;
-use anyhow::Result;
+use crate::utils::bio_io::buffer_from;
+use crate::utils::labelling::LabellingCounts;
+use anyhow::{anyhow, Result};
 use itertools::Itertools;
 use ordered_float::OrderedFloat;
 use rand::prelude::*;
-use std::collections::HashMap;
+use rand::rngs::StdRng;
+use rayon::prelude::*;
+use rust_htslib::bam::ext::BamRecordExtensions;
+use serde::Serialize;
 use std::collections::VecDeque;
-use std::io::Write;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{BufRead, Write};
+
+/// Version of the JSON QC report, increment when fields are renamed or removed
+pub static QC_JSON_SCHEMA_VERSION: u32 = 1;
+/// MSPs with a FIRE quality at or above this are counted as FIREs
+static MIN_FIRE_QUAL: u8 = 230;
+/// Shortest lag considered for the peak of the m6A ACF, shorter lags are within a linker
+static MIN_ACF_PEAK_LAG: usize = 100;
+/// Seed of the bootstrap resampling of the ACF reads
+static ACF_BOOTSTRAP_SEED: u64 = 42;
+/// Seed of the random sampling of reads for the ACF, so that repeated runs sample the same reads
+static ACF_SAMPLE_SEED: u64 = 42;
+/// Number of fibers whose stats are collected in parallel at a time
+static QC_CHUNK_SIZE: usize = 1_000;
+/// number of integer and float histograms in the original qc table, which are written before any added since
+static ORIGINAL_INT_HISTOGRAMS: usize = 7;
+static ORIGINAL_FLOAT_HISTOGRAMS: usize = 4;
 
 // set the precision of the floats to be saved and printed
 fn ordered_float_100k_round(f: f32) -> OrderedFloat<f32> {
@@ -18,19 +41,218 @@ fn ordered_float_10k_round(f: f32) -> OrderedFloat<f32> {
     OrderedFloat((f * 10_000.0).round() / 10_000.0)
 }
 
-#[derive(Eq, Hash, PartialEq, PartialOrd, Ord)]
+#[derive(Eq, Hash, PartialEq, PartialOrd, Ord, Serialize)]
 pub struct M6aPerMsp {
     pub m6a_count: i64,
     pub msp_size: i64,
     pub is_fire: bool,
 }
 
+impl std::str::FromStr for M6aPerMsp {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (m6a_count, msp_size, is_fire) = s
+            .split(',')
+            .collect_tuple()
+            .ok_or(anyhow!("Invalid m6a_per_msp_size value: {}", s))?;
+        Ok(Self {
+            m6a_count: m6a_count.parse()?,
+            msp_size: msp_size.parse()?,
+            is_fire: is_fire.parse()?,
+        })
+    }
+}
+
 impl core::fmt::Display for M6aPerMsp {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{},{},{}", self.m6a_count, self.msp_size, self.is_fire)
     }
 }
 
+/// Positions of the m6A calls and nucleosome starts of a read sampled for the ACF
+struct AcfRead {
+    len: usize,
+    m6a: Vec<i64>,
+    nuc: Vec<i64>,
+}
+
+impl AcfRead {
+    fn new(fiber: &fiber::FiberseqData) -> Self {
+        Self {
+            len: fiber.record.seq_len(),
+            m6a: fiber.m6a.starts.iter().flatten().copied().collect(),
+            nuc: fiber.nuc.starts.iter().flatten().copied().collect(),
+        }
+    }
+}
+
+/// Reads sampled for the ACF. The first "acf-max-reads" reads are kept, after which one of every
+/// "acf-sample-rate" reads is sampled at random and replaces one of the previous reads at random.
+#[derive(Default)]
+struct AcfReservoir {
+    reads: VecDeque<AcfRead>,
+    // times reads have been sampled at random
+    sampled: usize,
+}
+
+impl AcfReservoir {
+    fn add(&mut self, fiber: &fiber::FiberseqData, qc_opts: &QcOpts, rng: &mut StdRng) {
+        // test if we should skip or not based on length and random sampling
+        let rand_float: f32 = rng.gen_range(0.0..1.0);
+        let sample = rand_float < 1.0 / qc_opts.acf_sample_rate;
+        if !(self.reads.len() < qc_opts.acf_max_reads || sample) {
+            return;
+        };
+
+        // note how many times we have sampled
+        if sample {
+            self.sampled += 1;
+        }
+
+        // if we have sampled enough that all reads are random replace
+        // a random previous read with the current read
+        if sample && self.sampled > qc_opts.acf_max_reads {
+            let idx = rng.gen_range(0..self.reads.len());
+            self.reads[idx] = AcfRead::new(fiber);
+            log::debug!(
+                "Replaced read at index {} after the {}th sample",
+                idx,
+                self.sampled
+            );
+            return;
+        }
+
+        // otherwise add to the end while constraining the size of the queue
+        self.reads.push_back(AcfRead::new(fiber));
+        if self.reads.len() > qc_opts.acf_max_reads {
+            self.reads.pop_front();
+        }
+    }
+
+    /// keep the reads of both, randomly dropping reads past the maximum number of reads
+    fn merge(&mut self, other: AcfReservoir, max_reads: Option<usize>, rng: &mut StdRng) {
+        self.sampled += other.sampled;
+        self.reads.extend(other.reads);
+        if let Some(max_reads) = max_reads {
+            while self.reads.len() > max_reads {
+                let idx = rng.gen_range(0..self.reads.len());
+                self.reads.swap_remove_back(idx);
+            }
+        }
+    }
+
+    /// sums of the m6A or nucleosome start indicators of each read for the ACF
+    fn sums(&self, nuc: bool, max_lag: usize) -> Vec<AcfSums> {
+        self.reads
+            .par_iter()
+            .map(|read| {
+                let positions = if nuc { &read.nuc } else { &read.m6a };
+                AcfSums::from_positions(positions, read.len, max_lag)
+            })
+            .collect()
+    }
+}
+
+/// ACF of the m6A calls or nucleosome starts of the reads sampled for a group
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct AcfSeries {
+    pub feature: &'static str,
+    pub group: String,
+    pub fibers: usize,
+    /// lag of the highest peak of the ACF, roughly the nucleosome repeat length
+    pub peak_lag: Option<usize>,
+    #[serde(flatten)]
+    pub estimate: AcfEstimate,
+}
+
+/// Labelled regions of a bed file (label in the 4th column) used to stratify the QC metrics
+#[derive(Debug, Default)]
+pub struct BedStrata {
+    /// regions of each chromosome sorted by start: (start, end, label)
+    regions: HashMap<String, Vec<(i64, i64, usize)>>,
+    /// longest region of each chromosome, bounds the overlap search
+    max_len: HashMap<String, i64>,
+    pub labels: Vec<String>,
+}
+
+impl BedStrata {
+    pub fn from_bed(path: &str) -> Result<Self> {
+        let mut strata = Self::default();
+        let mut label_index: HashMap<String, usize> = HashMap::new();
+        for line in buffer_from(path)?.lines() {
+            let line = line?;
+            if line.starts_with('#') || line.trim().is_empty() {
+                continue;
+            }
+            let tokens: Vec<&str> = line.split('\t').collect();
+            anyhow::ensure!(
+                tokens.len() >= 4,
+                "BED line needs a label in the 4th column: {}",
+                line
+            );
+            let (chrom, start, end) = (
+                tokens[0],
+                tokens[1].parse::<i64>()?,
+                tokens[2].parse::<i64>()?,
+            );
+            let label = *label_index.entry(tokens[3].to_string()).or_insert_with(|| {
+                strata.labels.push(tokens[3].to_string());
+                strata.labels.len() - 1
+            });
+            strata
+                .regions
+                .entry(chrom.to_string())
+                .or_default()
+                .push((start, end, label));
+            let max_len = strata.max_len.entry(chrom.to_string()).or_insert(0);
+            *max_len = (*max_len).max(end - start);
+        }
+        for regions in strata.regions.values_mut() {
+            regions.sort();
+        }
+        Ok(strata)
+    }
+
+    /// The regions of each label that overlap [start, end) on chrom
+    pub fn overlapping(
+        &self,
+        chrom: &str,
+        start: i64,
+        end: i64,
+    ) -> BTreeMap<usize, Vec<(i64, i64)>> {
+        let mut overlaps: BTreeMap<usize, Vec<(i64, i64)>> = BTreeMap::new();
+        let (Some(regions), Some(max_len)) = (self.regions.get(chrom), self.max_len.get(chrom))
+        else {
+            return overlaps;
+        };
+        // regions after this index start at or after the end
+        let idx = regions.partition_point(|r| r.0 < end);
+        for &(st, en, label) in regions[..idx].iter().rev() {
+            if st < start - max_len {
+                break;
+            }
+            if en > start {
+                overlaps.entry(label).or_default().push((st, en));
+            }
+        }
+        overlaps
+    }
+}
+
+/// whether the reference midpoint of a range is within one of the regions
+fn midpoint_in(reference: &Option<(i64, i64, i64)>, regions: &[(i64, i64)]) -> bool {
+    match reference {
+        Some((st, en, _)) => {
+            let mid = (st + en) / 2;
+            regions
+                .iter()
+                .any(|(r_st, r_en)| *r_st <= mid && mid < *r_en)
+        }
+        None => false,
+    }
+}
+
 /// Main QC stat object
 pub struct QcStats<'a> {
     pub fiber_count: i64,
@@ -52,28 +274,46 @@ pub struct QcStats<'a> {
     pub m6a_count: HashMap<i64, i64>,
     // m6as over total AT count
     pub m6a_ratio: HashMap<OrderedFloat<f32>, i64>,
+    // m6as per kb of read
+    pub m6a_per_kb: HashMap<OrderedFloat<f32>, i64>,
+    // lengths of FIREs
+    pub fire_lengths: HashMap<i64, i64>,
+    // estimated m6A labelling efficiency per read
+    pub labelling_efficiency: HashMap<OrderedFloat<f32>, i64>,
+    // A/T bases and m6A calls in MSPs and nucleosomes over all reads
+    pub labelling: LabellingCounts,
     // cpg count
     pub cpg_count: HashMap<i64, i64>,
     // add rq to stats
     pub rq: HashMap<OrderedFloat<f32>, i64>,
     // m6a per msp size: (msp size, m6a count, is a FIRE element), number of times seen
     pub m6a_per_msp_size: HashMap<M6aPerMsp, i64>,
-    // m6a starts for acf
-    m6a_acf_starts: VecDeque<Vec<f64>>,
-    // times m6as have been sampled at random for acf
-    sampled: usize,
-    // the qc options for printing
-    qc_opts: &'a QcOpts,
+    // reads sampled for the acf of all reads
+    acf_all: AcfReservoir,
+    // reads sampled for the acf of each group of --acf-group-by, keyed by "<tag>=<value>"
+    acf_groups: BTreeMap<String, AcfReservoir>,
+    // the qc options for printing, None for stats read from a previous qc output
+    qc_opts: Option<&'a QcOpts>,
     // phasing information
     phased_reads: HashMap<String, i64>,
     phased_bp: HashMap<String, i64>,
     //
-    thread_rng: ThreadRng,
+    rng: StdRng,
+    // regions to stratify the stats by and the stats of each label
+    bed_strata: Option<&'a BedStrata>,
+    pub strata_stats: BTreeMap<String, QcStats<'a>>,
 }
 
 impl<'a> QcStats<'a> {
     pub fn new(qc_opts: &'a QcOpts) -> Self {
-        let thread_rng = rand::thread_rng();
+        Self {
+            qc_opts: Some(qc_opts),
+            ..Self::empty()
+        }
+    }
+
+    /// stats without any qc options, used to read and merge previous qc outputs
+    pub fn empty() -> Self {
         Self {
             fiber_count: 0,
             fiber_lengths: HashMap::new(),
@@ -85,84 +325,192 @@ impl<'a> QcStats<'a> {
             ccs_passes: HashMap::new(),
             m6a_count: HashMap::new(),
             m6a_ratio: HashMap::new(),
+            m6a_per_kb: HashMap::new(),
+            fire_lengths: HashMap::new(),
+            labelling_efficiency: HashMap::new(),
+            labelling: LabellingCounts::default(),
             cpg_count: HashMap::new(),
             m6a_per_msp_size: HashMap::new(),
             rq: HashMap::new(),
-            m6a_acf_starts: VecDeque::new(),
-            sampled: 0,
-            qc_opts,
+            acf_all: AcfReservoir::default(),
+            acf_groups: BTreeMap::new(),
+            qc_opts: None,
             phased_reads: HashMap::new(),
             phased_bp: HashMap::new(),
-            thread_rng,
+            rng: StdRng::seed_from_u64(ACF_SAMPLE_SEED),
+            bed_strata: None,
+            strata_stats: BTreeMap::new(),
         }
     }
 
+    /// Also collect the stats of the fibers overlapping each label of a bed file
+    pub fn stratified(mut self, bed_strata: Option<&'a BedStrata>) -> Self {
+        self.bed_strata = bed_strata;
+        self
+    }
+
     pub fn add_read_to_stats(&mut self, fiber: &fiber::FiberseqData) {
         // add auto-correlation of m6a
-        self.add_m6a_starts_for_acf(fiber);
+        self.add_acf_read(fiber);
+        self.add_fiber_stats(fiber);
+    }
 
+    /// add the read to every stat except the ACF, which depends on the order reads are sampled in
+    pub fn add_fiber_stats(&mut self, fiber: &fiber::FiberseqData) {
         self.full_read_stats(fiber);
         self.add_basemod_stats(fiber);
-        self.add_ranges(fiber);
-        if self.qc_opts.m6a_per_msp {
-            self.m6a_per_msp(fiber);
+        self.add_ranges(fiber, None);
+        if self.qc_opts.is_some_and(|opts| opts.m6a_per_msp) {
+            self.m6a_per_msp(fiber, None);
+        }
+        if let Some(bed_strata) = self.bed_strata {
+            self.add_strata_stats(fiber, bed_strata);
         }
     }
 
-    /// converts the m6A calls into a boolean vector for the ACF calculation
-    fn add_m6a_starts_for_acf(&mut self, fiber: &fiber::FiberseqData) {
-        // skip conditions
-        if !self.qc_opts.acf || fiber.m6a.starts.len() < self.qc_opts.acf_min_m6a {
+    /// Add the fiber to the stats of each label it overlaps. Read level stats are from the whole fiber,
+    /// while MSPs, FIREs, and nucleosomes are only counted if their reference midpoint is within a region of the label.
+    fn add_strata_stats(&mut self, fiber: &fiber::FiberseqData, bed_strata: &BedStrata) {
+        if fiber.record.is_unmapped() {
             return;
         }
-
-        // test if we should skip or not based on length and random sampling
-        let rand_float: f32 = self.thread_rng.gen_range(0.0..1.0);
-        let sample = rand_float < 1.0 / self.qc_opts.acf_sample_rate;
-        if !(self.m6a_acf_starts.len() < self.qc_opts.acf_max_reads || sample) {
-            return;
-        };
-
-        // note how many times we have sampled
-        if sample {
-            self.sampled += 1;
+        let overlaps = bed_strata.overlapping(
+            &fiber.target_name,
+            fiber.record.reference_start(),
+            fiber.record.reference_end(),
+        );
+        for (label, regions) in overlaps {
+            let qc_opts = self.qc_opts;
+            let stats = self
+                .strata_stats
+                .entry(bed_strata.labels[label].clone())
+                .or_insert_with(|| QcStats {
+                    qc_opts,
+                    ..QcStats::empty()
+                });
+            stats.full_read_stats(fiber);
+            stats.add_basemod_stats(fiber);
+            stats.add_ranges(fiber, Some(&regions));
+            if qc_opts.is_some_and(|opts| opts.m6a_per_msp) {
+                stats.m6a_per_msp(fiber, Some(&regions));
+            }
         }
+    }
 
-        // add the m6a to the working queue
-        let mut m6a_vec: Vec<f64> = vec![0.0; fiber.record.seq_len()];
-        for m6a in fiber.m6a.starts.iter().flatten() {
-            m6a_vec[*m6a as usize] = 1.0;
+    /// Add the counts of another set of stats to this one. The ACF reads of both are kept,
+    /// randomly dropping reads past the maximum number of ACF reads.
+    pub fn merge(&mut self, other: QcStats<'a>) {
+        fn merge_hashmap<T: std::hash::Hash + Eq>(a: &mut HashMap<T, i64>, b: HashMap<T, i64>) {
+            for (k, v) in b {
+                a.entry(k).and_modify(|e| *e += v).or_insert(v);
+            }
+        }
+        self.fiber_count += other.fiber_count;
+        merge_hashmap(&mut self.fiber_lengths, other.fiber_lengths);
+        merge_hashmap(&mut self.msp_count, other.msp_count);
+        merge_hashmap(&mut self.msp_lengths, other.msp_lengths);
+        merge_hashmap(&mut self.nuc_count, other.nuc_count);
+        merge_hashmap(&mut self.nuc_lengths, other.nuc_lengths);
+        merge_hashmap(&mut self.read_length_per_nuc, other.read_length_per_nuc);
+        merge_hashmap(&mut self.ccs_passes, other.ccs_passes);
+        merge_hashmap(&mut self.m6a_count, other.m6a_count);
+        merge_hashmap(&mut self.m6a_ratio, other.m6a_ratio);
+        merge_hashmap(&mut self.m6a_per_kb, other.m6a_per_kb);
+        merge_hashmap(&mut self.fire_lengths, other.fire_lengths);
+        merge_hashmap(&mut self.labelling_efficiency, other.labelling_efficiency);
+        self.labelling.add(&other.labelling);
+        merge_hashmap(&mut self.cpg_count, other.cpg_count);
+        merge_hashmap(&mut self.rq, other.rq);
+        merge_hashmap(&mut self.m6a_per_msp_size, other.m6a_per_msp_size);
+        merge_hashmap(&mut self.phased_reads, other.phased_reads);
+        merge_hashmap(&mut self.phased_bp, other.phased_bp);
+        for (label, stats) in other.strata_stats {
+            match self.strata_stats.get_mut(&label) {
+                Some(s) => s.merge(stats),
+                None => {
+                    self.strata_stats.insert(label, stats);
+                }
+            }
         }
 
-        // if we have sampled enough that all reads are random replace
-        // a random previous read with the current read
-        if sample && self.sampled > self.qc_opts.acf_max_reads {
-            let idx = self.thread_rng.gen_range(0..self.m6a_acf_starts.len());
-            self.m6a_acf_starts[idx] = m6a_vec;
-            log::debug!(
-                "Replaced read at index {} after the {}th sample",
-                idx,
-                self.sampled
-            );
-            return;
+        let max_reads = self.qc_opts.map(|opts| opts.acf_max_reads);
+        self.acf_all.merge(other.acf_all, max_reads, &mut self.rng);
+        for (group, reservoir) in other.acf_groups {
+            self.acf_groups
+                .entry(group)
+                .or_default()
+                .merge(reservoir, max_reads, &mut self.rng);
         }
+    }
 
-        // otherwise add to the end while constraining the size of the queue
-        self.m6a_acf_starts.push_back(m6a_vec);
-        if self.m6a_acf_starts.len() > self.qc_opts.acf_max_reads {
-            self.m6a_acf_starts.pop_front();
+    /// sample the read for the ACF of all reads and of its group
+    pub fn add_acf_read(&mut self, fiber: &fiber::FiberseqData) {
+        let Some(qc_opts) = self.qc_opts else {
+            return;
+        };
+        // skip conditions
+        if !qc_opts.acf || fiber.m6a.starts.len() < qc_opts.acf_min_m6a {
+            return;
         }
+        self.acf_all.add(fiber, qc_opts, &mut self.rng);
+        let group = match qc_opts.acf_group_by.as_deref() {
+            Some("RG") => format!("RG={}", fiber.rg),
+            Some("HP") => format!("HP={}", fiber.get_hp()),
+            _ => return,
+        };
+        self.acf_groups
+            .entry(group)
+            .or_default()
+            .add(fiber, qc_opts, &mut self.rng);
     }
 
-    fn add_ranges(&mut self, fiber: &fiber::FiberseqData) {
-        Self::add_range_lengths(&mut self.msp_lengths, &fiber.msp);
-        Self::add_range_lengths(&mut self.nuc_lengths, &fiber.nuc);
+    /// add the MSPs, FIREs, and nucleosomes of the fiber, only those with a
+    /// reference midpoint within the regions if regions are given
+    fn add_ranges(&mut self, fiber: &fiber::FiberseqData, regions: Option<&[(i64, i64)]>) {
+        let (msp_count, nuc_count) = match regions {
+            None => {
+                Self::add_range_lengths(&mut self.msp_lengths, &fiber.msp);
+                Self::add_range_lengths(&mut self.nuc_lengths, &fiber.nuc);
+                (fiber.msp.starts.len(), fiber.nuc.starts.len())
+            }
+            Some(regions) => {
+                let mut nuc_count = 0;
+                for (st, en, _, _, reference) in fiber.nuc.into_iter() {
+                    if midpoint_in(&reference, regions) {
+                        nuc_count += 1;
+                        self.nuc_lengths
+                            .entry(en - st)
+                            .and_modify(|e| *e += 1)
+                            .or_insert(1);
+                    }
+                }
+                let mut msp_count = 0;
+                for (st, en, _, _, reference) in fiber.msp.into_iter() {
+                    if midpoint_in(&reference, regions) {
+                        msp_count += 1;
+                        self.msp_lengths
+                            .entry(en - st)
+                            .and_modify(|e| *e += 1)
+                            .or_insert(1);
+                    }
+                }
+                (msp_count, nuc_count)
+            }
+        };
+        for (st, en, _, qual, reference) in fiber.msp.into_iter() {
+            if qual >= MIN_FIRE_QUAL && regions.is_none_or(|r| midpoint_in(&reference, r)) {
+                self.fire_lengths
+                    .entry(en - st)
+                    .and_modify(|e| *e += 1)
+                    .or_insert(1);
+            }
+        }
         self.nuc_count
-            .entry(fiber.nuc.starts.len() as i64)
+            .entry(nuc_count as i64)
             .and_modify(|e| *e += 1)
             .or_insert(1);
         self.msp_count
-            .entry(fiber.msp.starts.len() as i64)
+            .entry(msp_count as i64)
             .and_modify(|e| *e += 1)
             .or_insert(1);
         // read length per nucleosome
@@ -224,6 +572,23 @@ impl<'a> QcStats<'a> {
             .and_modify(|e: &mut i64| *e += 1)
             .or_insert(1);
 
+        // m6a per kb
+        let per_kb = m6a_count as f32 / fiber.record.seq_len() as f32 * 1000.0;
+        self.m6a_per_kb
+            .entry(ordered_float_10k_round(per_kb))
+            .and_modify(|e| *e += 1)
+            .or_insert(1);
+
+        // labelling efficiency
+        let labelling = LabellingCounts::from_fiber(fiber);
+        self.labelling.add(&labelling);
+        if let Some(efficiency) = labelling.efficiency() {
+            self.labelling_efficiency
+                .entry(ordered_float_10k_round(efficiency as f32))
+                .and_modify(|e| *e += 1)
+                .or_insert(1);
+        }
+
         // cpg count
         self.cpg_count
             .entry(fiber.cpg.starts.len() as i64)
@@ -237,10 +602,13 @@ impl<'a> QcStats<'a> {
         }
     }
 
-    /// calculate the m6a per MSP/FIRE element
-    fn m6a_per_msp(&mut self, fiber: &fiber::FiberseqData) {
-        for (st, en, _, qual, _) in fiber.msp.into_iter() {
-            let is_fire = qual >= 230;
+    /// calculate the m6a per MSP/FIRE element, only for MSPs with a reference midpoint within the regions if given
+    fn m6a_per_msp(&mut self, fiber: &fiber::FiberseqData, regions: Option<&[(i64, i64)]>) {
+        for (st, en, _, qual, reference) in fiber.msp.into_iter() {
+            if !regions.is_none_or(|r| midpoint_in(&reference, r)) {
+                continue;
+            }
+            let is_fire = qual >= MIN_FIRE_QUAL;
             let msp_size = en - st;
             let m6a_count = fiber
                 .m6a
@@ -260,24 +628,108 @@ impl<'a> QcStats<'a> {
         }
     }
 
-    /// Write auto correlation of m6A in fiber-seq data.
-    pub fn write_m6a_acf(&mut self, out: &mut Box<dyn Write>) -> Result<(), anyhow::Error> {
+    /// Auto correlation of m6A in fiber-seq data, None if the ACF was not requested.
+    pub fn m6a_acf(&self) -> Result<Option<Vec<f64>>, anyhow::Error> {
         // if we don't want to calculate the acf, then return
-        if !self.qc_opts.acf {
-            return Ok(());
+        let Some(qc_opts) = self.qc_opts.filter(|opts| opts.acf) else {
+            return Ok(None);
+        };
+        if self.acf_all.reads.is_empty() {
+            log::warn!(
+                "No reads with at least {} m6A calls for the ACF.",
+                qc_opts.acf_min_m6a
+            );
+            return Ok(None);
         }
         log::info!("Calculating m6A auto-correlation.");
-        let acf = crate::utils::acf::acf_par(
-            &self
-                .m6a_acf_starts
-                .iter()
-                .flatten()
-                .copied()
-                .collect::<Vec<f64>>(),
-            Some(self.qc_opts.acf_max_lag),
-            false,
-        )?;
+        // ACF of the m6A calls of the sampled reads concatenated into one series, the same as
+        // earlier versions so that qc-merge averages like with like; see acf_series for the
+        // ACF pooled over reads with confidence intervals
+        let mut m6a_track: Vec<f64> = vec![];
+        for read in self.acf_all.reads.iter() {
+            let offset = m6a_track.len();
+            m6a_track.resize(offset + read.len, 0.0);
+            for &m6a in read.m6a.iter() {
+                m6a_track[offset + m6a as usize] = 1.0;
+            }
+        }
+        let acf = acf::acf_par(&m6a_track, Some(qc_opts.acf_max_lag), false)?;
         log::info!("Done calculating m6A auto-correlation!");
+        Ok(Some(acf))
+    }
+
+    /// ACF of the m6A calls and nucleosome starts of all reads and each group, with bootstrap confidence intervals
+    pub fn acf_series(&self) -> Vec<AcfSeries> {
+        let Some(qc_opts) = self.qc_opts.filter(|opts| opts.acf) else {
+            return vec![];
+        };
+        log::info!(
+            "Calculating m6A and nucleosome auto-correlation with {} bootstraps.",
+            qc_opts.acf_bootstraps
+        );
+        let mut series = vec![];
+        for (feature, nuc) in [("m6a", false), ("nuc", true)] {
+            let groups = std::iter::once(("all", &self.acf_all))
+                .chain(self.acf_groups.iter().map(|(g, r)| (g.as_str(), r)));
+            for (group, reservoir) in groups {
+                let sums = reservoir.sums(nuc, qc_opts.acf_max_lag);
+                let Some(estimate) = bootstrap_acf(
+                    &sums,
+                    qc_opts.acf_max_lag,
+                    qc_opts.acf_bootstraps,
+                    qc_opts.acf_confidence,
+                    ACF_BOOTSTRAP_SEED,
+                ) else {
+                    continue;
+                };
+                series.push(AcfSeries {
+                    feature,
+                    group: group.to_string(),
+                    fibers: reservoir.reads.len(),
+                    peak_lag: acf_peak(&estimate.acf, MIN_ACF_PEAK_LAG).map(|(lag, _)| lag),
+                    estimate,
+                });
+            }
+        }
+        series
+    }
+
+    /// Write the ACF of each feature and group with the confidence interval of each lag
+    pub fn write_acf_series(
+        out: &mut Box<dyn Write>,
+        series: &[AcfSeries],
+    ) -> Result<(), anyhow::Error> {
+        out.write_all(b"feature\tgroup\tfibers\tlag\tacf\tlower\tupper\n")?;
+        for s in series {
+            let e = &s.estimate;
+            for lag in 0..e.acf.len() {
+                out.write_all(
+                    format!(
+                        "{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+                        s.feature,
+                        s.group,
+                        s.fibers,
+                        lag,
+                        ordered_float_100k_round(e.acf[lag] as f32),
+                        ordered_float_100k_round(e.lower[lag] as f32),
+                        ordered_float_100k_round(e.upper[lag] as f32),
+                    )
+                    .as_bytes(),
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write auto correlation of m6A in fiber-seq data.
+    pub fn write_m6a_acf(
+        &self,
+        out: &mut Box<dyn Write>,
+        acf: &Option<Vec<f64>>,
+    ) -> Result<(), anyhow::Error> {
+        let Some(acf) = acf else {
+            return Ok(());
+        };
         for (i, val) in acf.iter().enumerate() {
             out.write_all(
                 format!(
@@ -295,15 +747,61 @@ impl<'a> QcStats<'a> {
     pub fn write(&self, out: &mut Box<dyn Write>) -> Result<(), anyhow::Error> {
         // write the header
         out.write_all(b"statistic\tvalue\tcount\n")?;
+        out.write_all(self.table().as_bytes())?;
+        Ok(())
+    }
+
+    /// write the stats of each label of the bed file, with the label as the first column
+    pub fn write_strata(&self, out: &mut Box<dyn Write>) -> Result<(), anyhow::Error> {
+        out.write_all(b"label\tstatistic\tvalue\tcount\n")?;
+        for (label, stats) in self.strata_stats.iter() {
+            for line in stats.table().lines() {
+                out.write_all(format!("{}\t{}\n", label, line).as_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The rows of the qc table without the header. The rows of the original table come first and in the
+    /// same order, statistics added since are appended after them so existing parsers of the table keep working.
+    fn table(&self) -> String {
+        let mut table = "".to_string();
+        let ints = self.int_histograms();
+        let floats = self.float_histograms();
         // write the phasing information
         for f in &[
             (&self.phased_reads, "phased_reads"),
             (&self.phased_bp, "phased_bp"),
         ] {
-            out.write_all(Self::hashmap_to_string(f.0, f.1).as_bytes())?;
+            table += &Self::hashmap_to_string(f.0, f.1);
         }
         // write the integers
-        for x in &[
+        for x in &ints[..ORIGINAL_INT_HISTOGRAMS] {
+            table += &Self::hashmap_to_string(x.0, x.1);
+        }
+        // write the floats
+        for f in &floats[..ORIGINAL_FLOAT_HISTOGRAMS] {
+            table += &Self::hashmap_to_string(f.0, f.1);
+        }
+        // write the m6a per msp size
+        table += &Self::hashmap_to_string(&self.m6a_per_msp_size, "m6a_per_msp_size");
+        // write the added histograms
+        for x in &ints[ORIGINAL_INT_HISTOGRAMS..] {
+            table += &Self::hashmap_to_string(x.0, x.1);
+        }
+        for f in &floats[ORIGINAL_FLOAT_HISTOGRAMS..] {
+            table += &Self::hashmap_to_string(f.0, f.1);
+        }
+        // write the labelling counts
+        for (count, name) in self.labelling_bases() {
+            table += &format!("labelling_bases\t{}\t{}\n", name, count);
+        }
+        table
+    }
+
+    /// histograms with integer values and their names in the output, the original histograms first
+    fn int_histograms(&self) -> [(&HashMap<i64, i64>, &'static str); 8] {
+        [
             (&self.fiber_lengths, "fiber_length"),
             (&self.msp_count, "msp_count"),
             (&self.msp_lengths, "msp_length"),
@@ -311,23 +809,243 @@ impl<'a> QcStats<'a> {
             (&self.nuc_lengths, "nuc_length"),
             (&self.m6a_count, "m6a_count"),
             (&self.cpg_count, "cpg_count"),
-        ] {
-            out.write_all(Self::hashmap_to_string(x.0, x.1).as_bytes())?;
-        }
-        // write the floats
-        for f in &[
+            (&self.fire_lengths, "fire_length"),
+        ]
+    }
+
+    /// histograms with float values and their names in the output, the original histograms first
+    fn float_histograms(&self) -> [(&HashMap<OrderedFloat<f32>, i64>, &'static str); 6] {
+        [
             (&self.read_length_per_nuc, "read_length_per_nuc"),
             (&self.ccs_passes, "ccs_passes"),
             (&self.rq, "read_quality"),
             (&self.m6a_ratio, "m6a_ratio"),
+            (&self.m6a_per_kb, "m6a_per_kb"),
+            (&self.labelling_efficiency, "labelling_efficiency"),
+        ]
+    }
+
+    /// the labelling counts and their names in the output
+    fn labelling_bases(&self) -> [(i64, &'static str); 4] {
+        [
+            (self.labelling.msp_at, "msp_at"),
+            (self.labelling.msp_m6a, "msp_m6a"),
+            (self.labelling.nuc_at, "nuc_at"),
+            (self.labelling.nuc_m6a, "nuc_m6a"),
+        ]
+    }
+
+    /// Add one line of a previous qc output, returns the lag and value of m6a_acf lines
+    /// since the ACF cannot be recomputed from the output.
+    pub fn add_output_line(&mut self, line: &str) -> Result<Option<(usize, f64)>> {
+        let (statistic, value, count) = line.split('\t').collect_tuple().ok_or(anyhow!(
+            "Expected three columns in qc output line: {}",
+            line
+        ))?;
+        if statistic == "m6a_acf" {
+            return Ok(Some((value.parse()?, count.parse()?)));
+        }
+        let count: i64 = count.parse()?;
+        fn add<T: std::hash::Hash + Eq>(h: &mut HashMap<T, i64>, k: T, count: i64) {
+            h.entry(k).and_modify(|e| *e += count).or_insert(count);
+        }
+        match statistic {
+            "phased_reads" => add(&mut self.phased_reads, value.to_string(), count),
+            "phased_bp" => add(&mut self.phased_bp, value.to_string(), count),
+            "fiber_length" => {
+                self.fiber_count += count;
+                add(&mut self.fiber_lengths, value.parse()?, count)
+            }
+            "msp_count" => add(&mut self.msp_count, value.parse()?, count),
+            "msp_length" => add(&mut self.msp_lengths, value.parse()?, count),
+            "fire_length" => add(&mut self.fire_lengths, value.parse()?, count),
+            "nuc_count" => add(&mut self.nuc_count, value.parse()?, count),
+            "nuc_length" => add(&mut self.nuc_lengths, value.parse()?, count),
+            "m6a_count" => add(&mut self.m6a_count, value.parse()?, count),
+            "cpg_count" => add(&mut self.cpg_count, value.parse()?, count),
+            "read_length_per_nuc" => add(&mut self.read_length_per_nuc, value.parse()?, count),
+            "ccs_passes" => add(&mut self.ccs_passes, value.parse()?, count),
+            "read_quality" => add(&mut self.rq, value.parse()?, count),
+            "m6a_ratio" => add(&mut self.m6a_ratio, value.parse()?, count),
+            "m6a_per_kb" => add(&mut self.m6a_per_kb, value.parse()?, count),
+            "labelling_efficiency" => add(&mut self.labelling_efficiency, value.parse()?, count),
+            "labelling_bases" => match value {
+                "msp_at" => self.labelling.msp_at += count,
+                "msp_m6a" => self.labelling.msp_m6a += count,
+                "nuc_at" => self.labelling.nuc_at += count,
+                "nuc_m6a" => self.labelling.nuc_m6a += count,
+                _ => return Err(anyhow!("Unknown labelling_bases value: {}", value)),
+            },
+            "m6a_per_msp_size" => add(&mut self.m6a_per_msp_size, value.parse()?, count),
+            _ => return Err(anyhow!("Unknown statistic in qc output: {}", statistic)),
+        }
+        Ok(None)
+    }
+
+    /// Read a previous qc output, returning the stats and the m6A ACF if it has one
+    pub fn from_output(path: &str) -> Result<(Self, Option<Vec<f64>>)> {
+        let mut stats = Self::empty();
+        let mut acf = vec![];
+        for line in buffer_from(path)?.lines() {
+            let line = line?;
+            if line.is_empty() || line.starts_with("statistic\t") {
+                continue;
+            }
+            if let Some((lag, value)) = stats.add_output_line(&line)? {
+                if lag != acf.len() {
+                    return Err(anyhow!("m6a_acf lags are out of order in {}", path));
+                }
+                acf.push(value);
+            }
+        }
+        let acf = if acf.is_empty() { None } else { Some(acf) };
+        Ok((stats, acf))
+    }
+
+    /// Summary statistics derived from the histograms
+    pub fn summary(&self, acf: &Option<Vec<f64>>) -> QcSummary {
+        let int_values = |h: &HashMap<i64, i64>| -> Vec<(f64, i64)> {
+            h.iter().map(|(k, v)| (*k as f64, *v)).collect()
+        };
+        let float_values = |h: &HashMap<OrderedFloat<f32>, i64>| -> Vec<(f64, i64)> {
+            h.iter().map(|(k, v)| (k.into_inner() as f64, *v)).collect()
+        };
+        let fraction = |num: i64, den: i64| -> Option<f64> {
+            if den > 0 {
+                Some(num as f64 / den as f64)
+            } else {
+                None
+            }
+        };
+        // total bases in a histogram of lengths
+        let bases = |h: &HashMap<i64, i64>| -> i64 { h.iter().map(|(k, v)| k * v).sum() };
+        let total_bp = bases(&self.fiber_lengths);
+        let phased_reads = self.phased_reads.iter().filter(|(hp, _)| *hp != "UNK");
+        let phased_bp = self.phased_bp.iter().filter(|(hp, _)| *hp != "UNK");
+        let (m6a_acf_peak_lag, m6a_acf_peak) = match acf
+            .as_deref()
+            .and_then(|acf| acf_peak(acf, MIN_ACF_PEAK_LAG))
+        {
+            Some((lag, val)) => (Some(lag), Some(val)),
+            None => (None, None),
+        };
+        QcSummary {
+            fiber_count: self.fiber_count,
+            total_bp,
+            fiber_length: HistogramSummary::new(int_values(&self.fiber_lengths)),
+            fiber_length_n50: n50(int_values(&self.fiber_lengths)),
+            msp_count: HistogramSummary::new(int_values(&self.msp_count)),
+            msp_length: HistogramSummary::new(int_values(&self.msp_lengths)),
+            nuc_count: HistogramSummary::new(int_values(&self.nuc_count)),
+            nuc_length: HistogramSummary::new(int_values(&self.nuc_lengths)),
+            m6a_count: HistogramSummary::new(int_values(&self.m6a_count)),
+            m6a_per_kb: HistogramSummary::new(float_values(&self.m6a_per_kb)),
+            m6a_ratio: HistogramSummary::new(float_values(&self.m6a_ratio)),
+            cpg_count: HistogramSummary::new(int_values(&self.cpg_count)),
+            ccs_passes: HistogramSummary::new(float_values(&self.ccs_passes)),
+            read_quality: HistogramSummary::new(float_values(&self.rq)),
+            labelling_efficiency: HistogramSummary::new(float_values(&self.labelling_efficiency)),
+            pooled_labelling_efficiency: self.labelling.efficiency(),
+            labelling_background_rate: self.labelling.background_rate(),
+            labelling_bases: self.labelling,
+            fraction_under_labelled: self
+                .qc_opts
+                .and_then(|opts| opts.min_labelling_efficiency)
+                .and_then(|min| {
+                    let under: i64 = self
+                        .labelling_efficiency
+                        .iter()
+                        .filter(|(k, _)| k.into_inner() < min as f32)
+                        .map(|(_, v)| v)
+                        .sum();
+                    fraction(under, self.labelling_efficiency.values().sum())
+                }),
+            fraction_phased_reads: fraction(phased_reads.map(|(_, v)| v).sum(), self.fiber_count),
+            fraction_phased_bp: fraction(phased_bp.map(|(_, v)| v).sum(), total_bp),
+            msp_bp_fraction: fraction(bases(&self.msp_lengths), total_bp),
+            fire_bp_fraction: fraction(bases(&self.fire_lengths), total_bp),
+            fire_msp_bp_fraction: fraction(bases(&self.fire_lengths), bases(&self.msp_lengths)),
+            nuc_bp_fraction: fraction(bases(&self.nuc_lengths), total_bp),
+            m6a_acf_peak_lag,
+            m6a_acf_peak,
+        }
+    }
+
+    /// The full QC report, with the histograms and their summaries, as JSON
+    pub fn json_report(
+        &self,
+        acf: &Option<Vec<f64>>,
+        acf_series: &[AcfSeries],
+    ) -> serde_json::Value {
+        let mut report = serde_json::json!({
+            "schema": "fibertools-qc",
+            "schema_version": QC_JSON_SCHEMA_VERSION,
+            "fibertools_version": env!("CARGO_PKG_VERSION"),
+            "summary": self.summary(acf),
+            "histograms": self.json_histograms(),
+            "m6a_acf": acf,
+        });
+        if !acf_series.is_empty() {
+            report["acf_series"] = serde_json::json!(acf_series);
+        }
+        if self.bed_strata.is_some() || !self.strata_stats.is_empty() {
+            let strata: BTreeMap<&str, serde_json::Value> = self
+                .strata_stats
+                .iter()
+                .map(|(label, stats)| {
+                    (
+                        label.as_str(),
+                        serde_json::json!({
+                            "summary": stats.summary(&None),
+                            "histograms": stats.json_histograms(),
+                        }),
+                    )
+                })
+                .collect();
+            report["strata"] = serde_json::json!(strata);
+        }
+        report
+    }
+
+    fn json_histograms(&self) -> BTreeMap<&str, Vec<serde_json::Value>> {
+        let mut histograms: BTreeMap<&str, Vec<serde_json::Value>> = BTreeMap::new();
+        for (h, name) in [
+            (&self.phased_reads, "phased_reads"),
+            (&self.phased_bp, "phased_bp"),
         ] {
-            out.write_all(Self::hashmap_to_string(f.0, f.1).as_bytes())?;
+            histograms.insert(name, Self::hashmap_to_json(h, |k| k.clone().into()));
         }
-        // write the m6a per msp size
-        out.write_all(
-            Self::hashmap_to_string(&self.m6a_per_msp_size, "m6a_per_msp_size").as_bytes(),
-        )?;
-        Ok(())
+        for (h, name) in self.int_histograms() {
+            histograms.insert(name, Self::hashmap_to_json(h, |k| (*k).into()));
+        }
+        for (h, name) in self.float_histograms() {
+            histograms.insert(
+                name,
+                Self::hashmap_to_json(h, |k| serde_json::json!(k.into_inner())),
+            );
+        }
+        if !self.m6a_per_msp_size.is_empty() {
+            histograms.insert(
+                "m6a_per_msp_size",
+                Self::hashmap_to_json(&self.m6a_per_msp_size, |k| serde_json::json!(k)),
+            );
+        }
+        histograms
+    }
+
+    /// histogram as a sorted list of {"value": value, "count": count}
+    fn hashmap_to_json<T>(
+        hashmap: &HashMap<T, i64>,
+        to_value: impl Fn(&T) -> serde_json::Value,
+    ) -> Vec<serde_json::Value>
+    where
+        T: std::hash::Hash + Eq + std::cmp::Ord,
+    {
+        hashmap
+            .iter()
+            .sorted()
+            .map(|(k, v)| serde_json::json!({"value": to_value(k), "count": v}))
+            .collect()
     }
 
     fn hashmap_to_string<T>(hashmap: &HashMap<T, i64>, name: &str) -> String
@@ -342,20 +1060,297 @@ impl<'a> QcStats<'a> {
     }
 }
 
-pub fn run_qc(opts: &mut QcOpts) -> Result<(), anyhow::Error> {
-    let mut bam = opts.input.bam_reader();
-    let mut stats = QcStats::new(opts);
+/// Summary statistics of a histogram of values
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct HistogramSummary {
+    pub count: i64,
+    pub mean: Option<f64>,
+    pub median: Option<f64>,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
+
+impl HistogramSummary {
+    /// summarize a histogram of (value, count) pairs
+    pub fn new(mut hist: Vec<(f64, i64)>) -> Self {
+        hist.retain(|(v, c)| *c > 0 && v.is_finite());
+        hist.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let count: i64 = hist.iter().map(|(_, c)| c).sum();
+        if count == 0 {
+            return Self {
+                count,
+                mean: None,
+                median: None,
+                min: None,
+                max: None,
+            };
+        }
+        let mean = hist.iter().map(|(v, c)| v * *c as f64).sum::<f64>() / count as f64;
+        // the value at a zero based rank of the sorted observations
+        let value_at = |rank: i64| -> f64 {
+            let mut seen = 0;
+            for (v, c) in hist.iter() {
+                seen += c;
+                if seen > rank {
+                    return *v;
+                }
+            }
+            hist[hist.len() - 1].0
+        };
+        let median = (value_at((count - 1) / 2) + value_at(count / 2)) / 2.0;
+        Self {
+            count,
+            mean: Some(mean),
+            median: Some(median),
+            min: Some(hist[0].0),
+            max: Some(hist[hist.len() - 1].0),
+        }
+    }
+}
 
-    for (idx, fiber) in opts.input.fibers(&mut bam).enumerate() {
-        // break if we have reached the maximum number of reads
-        if idx >= opts.n_reads.unwrap_or(usize::MAX) {
-            break;
+/// The length such that lengths at least this long make up half of the total length
+pub fn n50(mut hist: Vec<(f64, i64)>) -> Option<f64> {
+    hist.sort_by(|a, b| b.0.total_cmp(&a.0));
+    let total: f64 = hist.iter().map(|(v, c)| v * *c as f64).sum();
+    if total <= 0.0 {
+        return None;
+    }
+    let mut cumulative = 0.0;
+    for (v, c) in hist {
+        cumulative += v * c as f64;
+        if cumulative >= total / 2.0 {
+            return Some(v);
         }
-        // add the read to the stats
-        stats.add_read_to_stats(&fiber);
     }
-    let mut out = bio_io::writer(&opts.out)?;
+    None
+}
+
+/// Sample level summary of the QC metrics, reported in the JSON output.
+/// Fields are None when there are no observations.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct QcSummary {
+    pub fiber_count: i64,
+    pub total_bp: i64,
+    pub fiber_length: HistogramSummary,
+    pub fiber_length_n50: Option<f64>,
+    pub msp_count: HistogramSummary,
+    pub msp_length: HistogramSummary,
+    pub nuc_count: HistogramSummary,
+    pub nuc_length: HistogramSummary,
+    pub m6a_count: HistogramSummary,
+    pub m6a_per_kb: HistogramSummary,
+    pub m6a_ratio: HistogramSummary,
+    pub cpg_count: HistogramSummary,
+    pub ccs_passes: HistogramSummary,
+    pub read_quality: HistogramSummary,
+    /// estimated fraction of accessible A/T bases labelled with m6A per read, see `LabellingCounts`
+    pub labelling_efficiency: HistogramSummary,
+    /// labelling efficiency of all reads pooled together, a sample level measure of saturation
+    pub pooled_labelling_efficiency: Option<f64>,
+    /// fraction of the A/T bases in nucleosomes with an m6A call
+    pub labelling_background_rate: Option<f64>,
+    pub labelling_bases: LabellingCounts,
+    /// fraction of reads with a labelling efficiency below --min-labelling-efficiency
+    pub fraction_under_labelled: Option<f64>,
+    /// fraction of reads (or bases of reads) with a haplotype (HP tag)
+    pub fraction_phased_reads: Option<f64>,
+    pub fraction_phased_bp: Option<f64>,
+    /// fraction of the read bases in MSPs, FIREs, and nucleosomes
+    pub msp_bp_fraction: Option<f64>,
+    pub fire_bp_fraction: Option<f64>,
+    pub nuc_bp_fraction: Option<f64>,
+    /// fraction of the MSP bases in FIREs
+    pub fire_msp_bp_fraction: Option<f64>,
+    /// lag and value of the highest peak of the m6A ACF, roughly the nucleosome repeat length
+    pub m6a_acf_peak_lag: Option<usize>,
+    pub m6a_acf_peak: Option<f64>,
+}
+
+/// write the qc table and optionally the JSON report
+fn write_qc(
+    stats: &QcStats,
+    acf: &Option<Vec<f64>>,
+    acf_series: &[AcfSeries],
+    out: &str,
+    json: &Option<String>,
+) -> Result<(), anyhow::Error> {
+    let mut out = bio_io::writer(out)?;
     stats.write(&mut out)?;
-    stats.write_m6a_acf(&mut out)?;
+    stats.write_m6a_acf(&mut out, acf)?;
+    if let Some(json) = json {
+        let mut json_out = bio_io::writer(json)?;
+        serde_json::to_writer_pretty(&mut json_out, &stats.json_report(acf, acf_series))?;
+        json_out.write_all(b"\n")?;
+    }
     Ok(())
 }
+
+pub fn run_qc(opts: &mut QcOpts) -> Result<(), anyhow::Error> {
+    let mut bam = opts.input.bam_reader();
+    let opts: &QcOpts = opts;
+    let bed_strata = match &opts.bed {
+        Some(bed) => Some(BedStrata::from_bed(bed)?),
+        None => None,
+    };
+    let mut stats = QcStats::new(opts).stratified(bed_strata.as_ref());
+
+    let fibers = opts
+        .input
+        .fibers(&mut bam)
+        .take(opts.n_reads.unwrap_or(usize::MAX));
+    for chunk in &fibers.chunks(QC_CHUNK_SIZE) {
+        let fibers: Vec<fiber::FiberseqData> = chunk.collect();
+        // reads are sampled for the ACF in the order of the bam
+        for fiber in fibers.iter() {
+            stats.add_acf_read(fiber);
+        }
+        // every other stat is collected in parallel and merged
+        let chunk_stats = fibers
+            .par_iter()
+            .fold(
+                || QcStats::new(opts).stratified(bed_strata.as_ref()),
+                |mut chunk_stats, fiber| {
+                    chunk_stats.add_fiber_stats(fiber);
+                    chunk_stats
+                },
+            )
+            .reduce(
+                || QcStats::new(opts).stratified(bed_strata.as_ref()),
+                |mut a, b| {
+                    a.merge(b);
+                    a
+                },
+            );
+        stats.merge(chunk_stats);
+    }
+    let acf = stats.m6a_acf()?;
+    let acf_series = if opts.acf_out.is_some() || opts.json.is_some() {
+        stats.acf_series()
+    } else {
+        vec![]
+    };
+    if let Some(acf_out) = &opts.acf_out {
+        QcStats::write_acf_series(&mut bio_io::writer(acf_out)?, &acf_series)?;
+    }
+    if let Some(bed_out) = &opts.bed_out {
+        stats.write_strata(&mut bio_io::writer(bed_out)?)?;
+    }
+    write_qc(&stats, &acf, &acf_series, &opts.out, &opts.json)
+}
+
+/// Merge the outputs of `ft qc` run on parts of the data (e.g. chromosomes or SMRT cells).
+/// The m6A ACF of the inputs cannot be recomputed and is instead averaged, weighted by the number of fibers.
+pub fn run_qc_merge(opts: &QcMergeOpts) -> Result<(), anyhow::Error> {
+    let mut stats = QcStats::empty();
+    let mut acf_sum: Vec<f64> = vec![];
+    let mut acf_weight = 0.0;
+    for input in opts.inputs.iter() {
+        log::info!("Reading qc output {}", input);
+        let (input_stats, input_acf) = QcStats::from_output(input)?;
+        if let Some(input_acf) = input_acf {
+            let weight = input_stats.fiber_count as f64;
+            if acf_sum.is_empty() {
+                acf_sum = vec![0.0; input_acf.len()];
+            } else if acf_sum.len() != input_acf.len() {
+                return Err(anyhow!(
+                    "The m6A ACF of {} has a different max lag than the previous inputs",
+                    input
+                ));
+            }
+            for (sum, val) in acf_sum.iter_mut().zip(input_acf) {
+                *sum += val * weight;
+            }
+            acf_weight += weight;
+        }
+        stats.merge(input_stats);
+    }
+    let acf = if acf_weight > 0.0 {
+        Some(acf_sum.iter().map(|sum| sum / acf_weight).collect())
+    } else {
+        None
+    };
+    write_qc(&stats, &acf, &[], &opts.out, &opts.json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram_summary() {
+        let summary = HistogramSummary::new(vec![(10.0, 1), (20.0, 2), (40.0, 1)]);
+        assert_eq!(summary.count, 4);
+        assert_eq!(summary.mean, Some(22.5));
+        assert_eq!(summary.median, Some(20.0));
+        assert_eq!(summary.min, Some(10.0));
+        assert_eq!(summary.max, Some(40.0));
+        // even number of observations with different middle values
+        let summary = HistogramSummary::new(vec![(1.0, 1), (3.0, 1)]);
+        assert_eq!(summary.median, Some(2.0));
+        assert_eq!(HistogramSummary::new(vec![]).mean, None);
+    }
+
+    #[test]
+    fn test_merge_outputs() {
+        let mut a = QcStats::empty();
+        let mut b = QcStats::empty();
+        for line in [
+            "fiber_length\t1000\t2",
+            "fire_length\t150\t3",
+            "phased_reads\tH1\t2",
+            "m6a_per_msp_size\t10,100,true\t1",
+        ] {
+            assert_eq!(a.add_output_line(line).unwrap(), None);
+        }
+        for line in ["fiber_length\t1000\t1", "fiber_length\t500\t1"] {
+            b.add_output_line(line).unwrap();
+        }
+        assert_eq!(
+            b.add_output_line("m6a_acf\t3\t0.25").unwrap(),
+            Some((3, 0.25))
+        );
+        assert!(b.add_output_line("not_a_statistic\t1\t1").is_err());
+        a.merge(b);
+        assert_eq!(a.fiber_count, 4);
+        assert_eq!(a.fiber_lengths[&1000], 3);
+        assert_eq!(a.fiber_lengths[&500], 1);
+        assert_eq!(a.fire_lengths[&150], 3);
+        let summary = a.summary(&None);
+        assert_eq!(summary.total_bp, 3500);
+        assert_eq!(summary.fraction_phased_reads, Some(0.5));
+    }
+
+    #[test]
+    fn test_bed_strata_overlaps() {
+        let strata = BedStrata {
+            regions: HashMap::from([(
+                "chr1".to_string(),
+                vec![
+                    (0, 1000, 0),
+                    (100, 200, 1),
+                    (1500, 1600, 1),
+                    (5000, 5100, 0),
+                ],
+            )]),
+            max_len: HashMap::from([("chr1".to_string(), 1000)]),
+            labels: vec!["gene".to_string(), "promoter".to_string()],
+        };
+        let overlaps = strata.overlapping("chr1", 150, 1550);
+        assert_eq!(overlaps[&0], vec![(0, 1000)]);
+        assert_eq!(overlaps[&1], vec![(1500, 1600), (100, 200)]);
+        assert!(strata.overlapping("chr1", 1000, 1500).is_empty());
+        assert!(strata.overlapping("chr2", 0, 1000).is_empty());
+        // features are assigned by their midpoint
+        assert!(midpoint_in(&Some((90, 150, 60)), &[(100, 200)]));
+        assert!(!midpoint_in(&Some((0, 150, 150)), &[(100, 200)]));
+        assert!(!midpoint_in(&None, &[(100, 200)]));
+    }
+
+    #[test]
+    fn test_n50() {
+        // 100 bases in reads of 50, 30 bases in reads of 30, 20 in reads of 10
+        assert_eq!(n50(vec![(10.0, 2), (30.0, 1), (50.0, 2)]), Some(50.0));
+        assert_eq!(n50(vec![(10.0, 5), (30.0, 1), (50.0, 1)]), Some(30.0));
+        assert_eq!(n50(vec![]), None);
+    }
+}
@@ -12,11 +12,6 @@ pub fn strip_base_mods(opts: &mut StripBasemodsOptions) {
     // read in bam data
     let bam_chunk_iter = BamChunk::new(bam.records(), None);
 
-    let filter_mod = match &opts.basemod {
-        Some(mod_name) => mod_name,
-        None => "",
-    };
-
     // iterate over chunks
     for mut chunk in bam_chunk_iter {
         // strip
@@ -24,10 +19,8 @@ pub fn strip_base_mods(opts: &mut StripBasemodsOptions) {
             .par_iter_mut()
             .map(|record| {
                 let mut data = basemods::BaseMods::new(record, 0);
-                if (filter_mod == "5mC") || (filter_mod == "CpG") {
-                    data.drop_cpg();
-                } else if (filter_mod == "6mA") || (filter_mod == "m6A") {
-                    data.drop_m6a();
+                if let Some(code) = &opts.basemod {
+                    data.drop_mod(code);
                 }
                 if opts.drop_forward {
                     data.drop_forward();
@@ -41,6 +34,9 @@ pub fn strip_base_mods(opts: &mut StripBasemodsOptions) {
                 if opts.ml_5mc > 0 {
                     data.filter_5mc(opts.ml_5mc);
                 }
+                for (code, ml) in opts.ml_mod.iter() {
+                    data.filter_mod(code, *ml);
+                }
 
                 data.add_mm_and_ml_tags(record);
                 record
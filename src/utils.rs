@@ -2,10 +2,16 @@ pub mod acf;
 pub mod bamlift;
 pub mod bamranges;
 pub mod basemods;
+pub mod bigwig;
 pub mod bio_io;
+pub mod checkpoint;
 pub mod fire;
 pub mod input_bam;
+pub mod labelling;
+pub mod nrl;
 pub mod nucleosome;
+pub mod nucleosome_hmm;
+pub mod stats;
 
 // test modules for expressions
 pub mod ftexpression;
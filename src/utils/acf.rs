@@ -2,7 +2,10 @@
 // https://github.com/krfricke/arima
 use anyhow::Result;
 use num::Float;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use rayon::prelude::*;
+use serde::Serialize;
 use std::cmp;
 use std::convert::From;
 use std::ops::{Add, AddAssign, Div};
@@ -138,3 +141,209 @@ pub fn acf_par<
     }
     Ok(y)
 }
+
+/// Sums over a binary series (e.g. the m6A calls along a fiber) from which the auto-correlation
+/// of many series pooled together is calculated, with one mean shared by all the series.
+/// For a single series the pooled ACF is the same as `acf`, but lags do not span from one series into the next.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AcfSums {
+    len: usize,
+    /// sum of x_i
+    count: f64,
+    /// sum of x_i * x_(i+t) for each lag t
+    lagged: Vec<f64>,
+    /// sum of x_i for i < len - t
+    head: Vec<f64>,
+    /// sum of x_i for i >= t
+    tail: Vec<f64>,
+}
+
+impl AcfSums {
+    /// `positions` are the positions of the ones in a binary series of length `len`
+    pub fn from_positions(positions: &[i64], len: usize, max_lag: usize) -> Self {
+        let mut positions: Vec<i64> = positions
+            .iter()
+            .copied()
+            .filter(|&p| p >= 0 && (p as usize) < len)
+            .collect();
+        positions.sort();
+        positions.dedup();
+
+        let mut lagged = vec![0.0; max_lag + 1];
+        for (i, &p) in positions.iter().enumerate() {
+            for &q in positions[i..].iter() {
+                let lag = (q - p) as usize;
+                if lag > max_lag {
+                    break;
+                }
+                lagged[lag] += 1.0;
+            }
+        }
+        let head = (0..=max_lag)
+            .map(|t| positions.partition_point(|&p| (p as usize) + t < len) as f64)
+            .collect();
+        let tail = (0..=max_lag)
+            .map(|t| (positions.len() - positions.partition_point(|&p| (p as usize) < t)) as f64)
+            .collect();
+        Self {
+            len,
+            count: positions.len() as f64,
+            lagged,
+            head,
+            tail,
+        }
+    }
+
+    /// length of the series
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+/// Auto-correlation of the series pooled together, with series `i` included `weights[i]` times.
+/// None if there is no variation in the series.
+pub fn pooled_acf(sums: &[AcfSums], weights: &[f64], max_lag: usize) -> Option<Vec<f64>> {
+    let n: f64 = sums
+        .iter()
+        .zip(weights)
+        .map(|(s, w)| w * s.len as f64)
+        .sum();
+    if n <= 0.0 {
+        return None;
+    }
+    let mean = sums
+        .iter()
+        .zip(weights)
+        .map(|(s, w)| w * s.count)
+        .sum::<f64>()
+        / n;
+    let mut y = vec![0.0; max_lag + 1];
+    for (s, w) in sums.iter().zip(weights) {
+        if *w == 0.0 {
+            continue;
+        }
+        for (t, y_t) in y.iter_mut().enumerate() {
+            let pairs = s.len.saturating_sub(t) as f64;
+            *y_t += w * (s.lagged[t] - mean * (s.head[t] + s.tail[t]) + mean * mean * pairs);
+        }
+    }
+    if y[0] <= 0.0 {
+        return None;
+    }
+    Some(y.iter().map(|y_t| y_t / y[0]).collect())
+}
+
+/// Find the highest local maximum of an ACF at a lag of at least `min_lag`,
+/// e.g. the nucleosome repeat length in the m6A auto-correlation.
+/// The last lag of the ACF is only used to tell if the lag before it is a local maximum.
+pub fn acf_peak(acf: &[f64], min_lag: usize) -> Option<(usize, f64)> {
+    (min_lag.max(1)..acf.len().saturating_sub(1))
+        .filter(|&lag| acf[lag] > acf[lag - 1] && acf[lag] >= acf[lag + 1])
+        .map(|lag| (lag, acf[lag]))
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+}
+
+/// An ACF with a confidence interval for each lag
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AcfEstimate {
+    pub acf: Vec<f64>,
+    pub lower: Vec<f64>,
+    pub upper: Vec<f64>,
+}
+
+/// Pooled ACF of the series with percentile bootstrap confidence intervals,
+/// made by resampling the series with replacement `n_bootstraps` times.
+pub fn bootstrap_acf(
+    sums: &[AcfSums],
+    max_lag: usize,
+    n_bootstraps: usize,
+    confidence: f64,
+    seed: u64,
+) -> Option<AcfEstimate> {
+    let acf = pooled_acf(sums, &vec![1.0; sums.len()], max_lag)?;
+    let resampled: Vec<Vec<f64>> = (0..n_bootstraps)
+        .into_par_iter()
+        .filter_map(|i| {
+            let mut rng = StdRng::seed_from_u64(seed.wrapping_add(i as u64));
+            let mut weights = vec![0.0; sums.len()];
+            for _ in 0..sums.len() {
+                weights[rng.gen_range(0..sums.len())] += 1.0;
+            }
+            pooled_acf(sums, &weights, max_lag)
+        })
+        .collect();
+    if resampled.is_empty() {
+        return Some(AcfEstimate {
+            lower: acf.clone(),
+            upper: acf.clone(),
+            acf,
+        });
+    }
+    let alpha = (1.0 - confidence) / 2.0;
+    let quantile = |sorted: &[f64], q: f64| -> f64 {
+        sorted[((sorted.len() - 1) as f64 * q).round() as usize]
+    };
+    let (lower, upper) = (0..=max_lag)
+        .map(|t| {
+            let mut values: Vec<f64> = resampled.iter().map(|r| r[t]).collect();
+            values.sort_by(|a, b| a.total_cmp(b));
+            (quantile(&values, alpha), quantile(&values, 1.0 - alpha))
+        })
+        .unzip();
+    Some(AcfEstimate { acf, lower, upper })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pooled_acf_matches_acf() {
+        let positions = [0, 3, 4, 10, 11, 17, 25, 26, 30];
+        let len = 40;
+        let mut x = vec![0.0; len];
+        for &p in positions.iter() {
+            x[p as usize] = 1.0;
+        }
+        let expected = acf(&x, Some(12), false).unwrap();
+        let sums = AcfSums::from_positions(&positions, len, 12);
+        let pooled = pooled_acf(&[sums], &[1.0], 12).unwrap();
+        for (a, b) in expected.iter().zip(pooled.iter()) {
+            assert!((a - b).abs() < 1e-9, "{:?} {:?}", expected, pooled);
+        }
+    }
+
+    #[test]
+    fn test_bootstrap_acf() {
+        // periodic series with a peak every 10 bases
+        let sums: Vec<AcfSums> = (0..20)
+            .map(|i| {
+                let positions: Vec<i64> = (0..50).map(|p| p * 10 + (i % 3)).collect();
+                AcfSums::from_positions(&positions, 500, 20)
+            })
+            .collect();
+        let estimate = bootstrap_acf(&sums, 20, 50, 0.95, 42).unwrap();
+        assert_eq!(estimate.acf[0], 1.0);
+        for t in 0..=20 {
+            assert!(estimate.lower[t] <= estimate.acf[t] + 1e-9);
+            assert!(estimate.upper[t] >= estimate.acf[t] - 1e-9);
+        }
+        assert!(estimate.acf[10] > 0.9);
+        assert!(estimate.acf[5] < 0.0);
+        // no variation
+        assert_eq!(bootstrap_acf(&[], 20, 50, 0.95, 42), None);
+    }
+
+    #[test]
+    fn test_acf_peak() {
+        let acf: Vec<f64> = (0..250)
+            .map(|lag| (lag as f64 * 2.0 * std::f64::consts::PI / 180.0).cos())
+            .collect();
+        assert_eq!(acf_peak(&acf, 100).map(|(lag, _)| lag), Some(180));
+        assert_eq!(acf_peak(&acf[..150], 100), None);
+    }
+}
@@ -11,13 +11,92 @@ use std::collections::HashMap;
 
 use std::convert::TryFrom;
 
+/// Modification codes from the SAM tags spec as (single letter code, ChEBI id, names),
+/// codes that only have a ChEBI id have an empty letter code
+pub static MOD_CODES: [(&str, &str, &[&str]); 11] = [
+    ("a", "28871", &["m6A", "6mA"]),
+    ("m", "27551", &["5mC", "CpG"]),
+    ("h", "76792", &["5hmC"]),
+    ("f", "76794", &["5fC"]),
+    ("c", "76793", &["5caC"]),
+    ("", "21839", &["4mC"]),
+    ("g", "16964", &["5hmU"]),
+    ("e", "80961", &["5fU"]),
+    ("b", "17477", &["5caU"]),
+    ("o", "44605", &["8oxoG"]),
+    ("n", "18107", &["Xao"]),
+];
+
+/// Normalize a modification given as a MM code, ChEBI id, or name (e.g. `h`, `76792`, or `5hmC`)
+/// to the ChEBI id if it is known, otherwise the code is returned as is.
+/// ```
+/// use fibertools_rs::utils::basemods::normalize_mod_code;
+/// assert_eq!(normalize_mod_code("a"), "28871");
+/// assert_eq!(normalize_mod_code("6mA"), "28871");
+/// assert_eq!(normalize_mod_code("5hmc"), "76792");
+/// assert_eq!(normalize_mod_code("z"), "z");
+/// ```
+pub fn normalize_mod_code(code: &str) -> String {
+    for (letter, chebi, names) in MOD_CODES.iter() {
+        if code == *letter || code == *chebi || names.iter().any(|n| n.eq_ignore_ascii_case(code)) {
+            return chebi.to_string();
+        }
+    }
+    code.to_string()
+}
+
+/// A readable name for a modification code, e.g. `5hmC` for `h` or `76792`
+pub fn mod_code_name(code: &str) -> String {
+    let chebi = normalize_mod_code(code);
+    MOD_CODES
+        .iter()
+        .find(|(_, c, _)| *c == chebi)
+        .map(|(_, _, names)| names[0].to_string())
+        .unwrap_or(chebi)
+}
+
+/// clap value parser for modification codes
+pub fn parse_mod_code(code: &str) -> Result<String, String> {
+    let valid = (code.len() == 1 && code.chars().all(|c| c.is_ascii_lowercase()))
+        || code.chars().all(|c| c.is_ascii_digit());
+    if code.len() > 1
+        && code.chars().all(|c| c.is_ascii_lowercase())
+        && normalize_mod_code(code) == code
+    {
+        return Err(format!(
+            "{code} combines several modification codes, which are read as separate modifications. Give one of them instead (e.g. {})",
+            &code[..1]
+        ));
+    }
+    if code.is_empty() || (!valid && normalize_mod_code(code) == code) {
+        return Err(format!(
+            "{code} is not a modification code (e.g. a or m), ChEBI id (e.g. 76792), or known modification name (e.g. 5hmC)"
+        ));
+    }
+    Ok(code.to_string())
+}
+
+/// How bases of the modified type that are not listed in the MM tag should be read, see the SAM tags spec
+#[derive(Eq, PartialEq, Debug, PartialOrd, Ord, Clone, Copy, Default)]
+pub enum SkipMode {
+    /// `.` or no mode given: unlisted bases are not modified
+    #[default]
+    Implicit,
+    /// `?`: unlisted bases were not assessed
+    Explicit,
+}
+
 #[derive(Eq, PartialEq, Debug, PartialOrd, Ord, Clone)]
 pub struct BaseMod {
     pub modified_base: u8,
     pub strand: char,
-    pub modification_type: char,
+    /// single letter code or ChEBI id from the MM tag
+    pub modification_type: String,
     pub ranges: Ranges,
     pub record_is_reverse: bool,
+    pub skip_mode: SkipMode,
+    /// positions in the forward read of bases of the modified type that were not assessed, only possible with `SkipMode::Explicit`
+    pub unassessed_forward: Vec<i64>,
 }
 
 impl BaseMod {
@@ -25,7 +104,7 @@ impl BaseMod {
         record: &bam::Record,
         modified_base: u8,
         strand: char,
-        modification_type: char,
+        modification_type: &str,
         modified_bases_forward: Vec<i64>,
         modified_probabilities_forward: Vec<u8>,
     ) -> Self {
@@ -38,18 +117,37 @@ impl BaseMod {
         Self {
             modified_base,
             strand,
-            modification_type,
+            modification_type: modification_type.to_string(),
             ranges,
             record_is_reverse,
+            skip_mode: SkipMode::Implicit,
+            unassessed_forward: vec![],
         }
     }
 
+    /// positions of bases of the modified type that were not assessed, in the orientation of the aligned sequence
+    pub fn unassessed_positions(&self) -> Vec<i64> {
+        if !self.record_is_reverse {
+            return self.unassessed_forward.clone();
+        }
+        self.unassessed_forward
+            .iter()
+            .rev()
+            .map(|p| self.ranges.seq_len - p - 1)
+            .collect()
+    }
+
+    /// true if this is the modification given as a MM code, ChEBI id, or name, see `normalize_mod_code`
+    pub fn is_mod(&self, code: &str) -> bool {
+        normalize_mod_code(&self.modification_type) == normalize_mod_code(code)
+    }
+
     pub fn is_m6a(&self) -> bool {
-        self.modification_type == 'a'
+        self.modification_type == "a" || self.modification_type == "28871"
     }
 
     pub fn is_cpg(&self) -> bool {
-        self.modification_type == 'm'
+        self.modification_type == "m" || self.modification_type == "27551"
     }
 
     pub fn filter_at_read_ends(&mut self, n_strip: i64) {
@@ -58,6 +156,33 @@ impl BaseMod {
         }
         self.ranges.filter_starts_at_read_ends(n_strip);
     }
+
+    /// positions and ML values to list in the MM and ML tags, in the forward orientation of the read.
+    /// In explicit mode every assessed base is listed so that calls dropped by filtering are
+    /// written with an ML of 0 rather than becoming unassessed.
+    fn forward_mm_and_ml(&self, forward_seq: &[u8]) -> (Vec<i64>, Vec<u8>) {
+        let starts = self.ranges.get_forward_starts();
+        let quals = self.ranges.get_forward_quals();
+        if self.skip_mode == SkipMode::Implicit {
+            return (starts, quals);
+        }
+        let mut calls = starts.into_iter().zip(quals).peekable();
+        let mut unassessed = self.unassessed_forward.iter().peekable();
+        let mut positions = vec![];
+        let mut mls = vec![];
+        for (i, &base) in forward_seq.iter().enumerate() {
+            let i = i as i64;
+            if base != self.modified_base {
+                continue;
+            }
+            if unassessed.next_if(|&&p| p == i).is_some() {
+                continue;
+            }
+            positions.push(i);
+            mls.push(calls.next_if(|&(p, _)| p == i).map_or(0, |(_, ml)| ml));
+        }
+        (positions, mls)
+    }
 }
 
 #[derive(Eq, PartialEq, Debug, Clone)]
@@ -76,11 +201,25 @@ impl BaseMods {
         lazy_static! {
             // MM:Z:([ACGTUN][-+]([a-z]+|[0-9]+)[.?]?(,[0-9]+)*;)*
             static ref MM_RE: Regex =
-                Regex::new(r"((([ACGTUN])([-+])([a-z]+|[0-9]+))[.?]?((,[0-9]+)*;)*)").unwrap();
+                Regex::new(r"((([ACGTUN])([-+])([a-z]+|[0-9]+))([.?]?)((,[0-9]+)*;)*)").unwrap();
         }
         // Array to store all the different modifications within the MM tag
         let mut rtn = vec![];
 
+        // Dorado records the length of the sequence the MM tag was made for in MN,
+        // if the read has since been trimmed or hard clipped the MM tag no longer matches the sequence
+        if let Some(mn) = get_mn_tag(record) {
+            if mn != record.seq_len() {
+                log::warn!(
+                    "Skipping base mods for {} because its MN tag ({}) does not match the sequence length ({}).",
+                    String::from_utf8_lossy(record.qname()),
+                    mn,
+                    record.seq_len()
+                );
+                return BaseMods { base_mods: rtn };
+            }
+        }
+
         let ml_tag = get_u8_tag(record, b"ML");
 
         let mut num_mods_seen = 0;
@@ -91,7 +230,11 @@ impl BaseMods {
                 let mod_base = cap.get(3).map(|m| m.as_str().as_bytes()[0]).unwrap();
                 let mod_strand = cap.get(4).map_or("", |m| m.as_str());
                 let modification_type = cap.get(5).map_or("", |m| m.as_str());
-                let mod_dists_str = cap.get(6).map_or("", |m| m.as_str());
+                let skip_mode = match cap.get(6).map_or("", |m| m.as_str()) {
+                    "?" => SkipMode::Explicit,
+                    _ => SkipMode::Implicit,
+                };
+                let mod_dists_str = cap.get(7).map_or("", |m| m.as_str());
                 // parse the string containing distances between modifications into a vector of i64
                 let mod_dists: Vec<i64> = mod_dists_str
                     .trim_end_matches(';')
@@ -140,13 +283,42 @@ impl BaseMods {
                     record.is_reverse()
                 );
 
+                // in explicit mode the bases of this type that are not listed were not assessed
+                let unassessed_forward = if skip_mode == SkipMode::Explicit {
+                    let mut listed = unfiltered_modified_positions.iter().peekable();
+                    forward_bases
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, &b)| b == mod_base)
+                        .map(|(i, _)| i as i64)
+                        .filter(|i| {
+                            if listed.peek() == Some(&i) {
+                                listed.next();
+                                false
+                            } else {
+                                true
+                            }
+                        })
+                        .collect()
+                } else {
+                    vec![]
+                };
+
+                // combined codes (e.g. C+hm) list one ML value per code for each position, in the order of the codes
+                let codes: Vec<&str> = if modification_type.chars().all(|c| c.is_ascii_digit()) {
+                    vec![modification_type]
+                } else {
+                    (0..modification_type.len())
+                        .map(|i| &modification_type[i..i + 1])
+                        .collect()
+                };
+
                 // check for the probability of modification.
-                let num_mods_cur_end = num_mods_seen + unfiltered_modified_positions.len();
-                let unfiltered_modified_probabilities = if num_mods_cur_end > ml_tag.len() {
-                    let needed_num_of_zeros = num_mods_cur_end - ml_tag.len();
-                    let mut to_add = vec![0; needed_num_of_zeros];
-                    let mut has = ml_tag[num_mods_seen..ml_tag.len()].to_vec();
-                    has.append(&mut to_add);
+                let num_mods_cur_end =
+                    num_mods_seen + unfiltered_modified_positions.len() * codes.len();
+                let all_probabilities = if num_mods_cur_end > ml_tag.len() {
+                    let mut has = ml_tag.get(num_mods_seen..).unwrap_or_default().to_vec();
+                    has.resize(num_mods_cur_end - num_mods_seen, 0);
                     log::warn!(
                         "ML tag is too short for the number of modifications found in the MM tag. Assuming an ML value of 0 after the first {num_mods_cur_end} modifications."
                     );
@@ -156,34 +328,42 @@ impl BaseMods {
                 };
                 num_mods_seen = num_mods_cur_end;
 
-                // must be true for filtering, and at this point
-                assert_eq!(
-                    unfiltered_modified_positions.len(),
-                    unfiltered_modified_probabilities.len()
-                );
-
-                // Filter mods based on probabilities
-                let (modified_probabilities, modified_positions): (Vec<u8>, Vec<i64>) =
-                    unfiltered_modified_probabilities
+                for (code_idx, code) in codes.iter().enumerate() {
+                    let unfiltered_modified_probabilities: Vec<u8> = all_probabilities
                         .iter()
-                        .zip(unfiltered_modified_positions.iter())
-                        .filter(|(&ml, &_mm)| ml >= min_ml_score)
-                        .unzip();
+                        .skip(code_idx)
+                        .step_by(codes.len())
+                        .copied()
+                        .collect();
+
+                    // must be true for filtering, and at this point
+                    assert_eq!(
+                        unfiltered_modified_positions.len(),
+                        unfiltered_modified_probabilities.len()
+                    );
+
+                    // Filter mods based on probabilities
+                    let (modified_probabilities, modified_positions): (Vec<u8>, Vec<i64>) =
+                        unfiltered_modified_probabilities
+                            .iter()
+                            .zip(unfiltered_modified_positions.iter())
+                            .filter(|(&ml, &_mm)| ml >= min_ml_score)
+                            .unzip();
 
-                // don't add empty basemods
-                if modified_positions.is_empty() {
-                    continue;
+                    // empty basemods are kept, since the MM tag still records that the bases were assessed
+                    // add to a struct
+                    let mut mods = BaseMod::new(
+                        record,
+                        mod_base,
+                        mod_strand.chars().next().unwrap(),
+                        code,
+                        modified_positions,
+                        modified_probabilities,
+                    );
+                    mods.skip_mode = skip_mode;
+                    mods.unassessed_forward = unassessed_forward.clone();
+                    rtn.push(mods);
                 }
-                // add to a struct
-                let mods = BaseMod::new(
-                    record,
-                    mod_base,
-                    mod_strand.chars().next().unwrap(),
-                    modification_type.chars().next().unwrap(),
-                    modified_positions,
-                    modified_probabilities,
-                );
-                rtn.push(mods);
             }
         } else {
             log::trace!("No MM tag found");
@@ -208,7 +388,12 @@ impl BaseMods {
         let mut rtn = vec![];
         for (mod_info, mods) in map {
             let mod_base = mod_info.0 as u8;
-            let mod_type = mod_info.1 as u8 as char;
+            // htslib gives ChEBI codes as negative numbers
+            let mod_type = if mod_info.1 < 0 {
+                (-mod_info.1).to_string()
+            } else {
+                (mod_info.1 as u8 as char).to_string()
+            };
             let mod_strand = if mod_info.2 == 0 { '+' } else { '-' };
             let (mut positions, mut qualities): (Vec<i64>, Vec<u8>) = mods.into_iter().unzip();
             if record.is_reverse() {
@@ -220,7 +405,9 @@ impl BaseMods {
                     .collect();
                 qualities.reverse();
             }
-            let mods = BaseMod::new(record, mod_base, mod_strand, mod_type, positions, qualities);
+            let mods = BaseMod::new(
+                record, mod_base, mod_strand, &mod_type, positions, qualities,
+            );
             rtn.push(mods);
         }
         // needed so I can compare methods
@@ -228,6 +415,27 @@ impl BaseMods {
         BaseMods { base_mods: rtn }
     }
 
+    /// true if all m6A calls are on the A bases of the sequenced strand (`A+a`), as in ONT data,
+    /// rather than also on the T bases of the other strand (`T-a`), as in PacBio data
+    pub fn is_single_strand_m6a(&self) -> bool {
+        self.base_mods
+            .iter()
+            .filter(|bm| bm.is_m6a())
+            .all(|bm| bm.modified_base == b'A' && bm.strand == '+')
+    }
+
+    /// positions of A/T bases that were not assessed for m6A, in the orientation of the aligned sequence
+    pub fn unassessed_m6a(&self) -> Vec<i64> {
+        let mut positions: Vec<i64> = self
+            .base_mods
+            .iter()
+            .filter(|bm| bm.is_m6a())
+            .flat_map(|bm| bm.unassessed_positions())
+            .collect();
+        positions.sort();
+        positions
+    }
+
     /// remove m6a base mods from the struct
     pub fn drop_m6a(&mut self) {
         self.base_mods.retain(|bm| !bm.is_m6a());
@@ -238,6 +446,11 @@ impl BaseMods {
         self.base_mods.retain(|bm| !bm.is_cpg());
     }
 
+    /// remove base mods of the modification given as a MM code, ChEBI id, or name
+    pub fn drop_mod(&mut self, code: &str) {
+        self.base_mods.retain(|bm| !bm.is_mod(code));
+    }
+
     /// drop the forward stand of basemod calls
     pub fn drop_forward(&mut self) {
         self.base_mods.retain(|bm| bm.strand == '-');
@@ -264,6 +477,14 @@ impl BaseMods {
             .for_each(|bm| bm.ranges.filter_by_qual(min_ml_score));
     }
 
+    /// drop modifications of the given MM code, ChEBI id, or name with a qual less than the min_ml_score
+    pub fn filter_mod(&mut self, code: &str, min_ml_score: u8) {
+        self.base_mods
+            .iter_mut()
+            .filter(|bm| bm.is_mod(code))
+            .for_each(|bm| bm.ranges.filter_by_qual(min_ml_score));
+    }
+
     /// filter the basemods at the read ends
     pub fn filter_at_read_ends(&mut self, n_strip: i64) {
         if n_strip <= 0 {
@@ -296,6 +517,17 @@ impl BaseMods {
         Ranges::merge_ranges(ranges)
     }
 
+    /// combine the forward and reverse data for the modification given as a MM code, ChEBI id, or name
+    pub fn get_mod(&self, code: &str) -> Ranges {
+        let ranges = self
+            .base_mods
+            .iter()
+            .filter(|x| x.is_mod(code))
+            .map(|x| &x.ranges)
+            .collect();
+        Ranges::merge_ranges(ranges)
+    }
+
     /// Example MM tag: MM:Z:C+m,11,6,10;A+a,0,0,0;
     /// Example ML tag: ML:B:C,157,30,2,164,118,255
     pub fn add_mm_and_ml_tags(&self, record: &mut bam::Record) {
@@ -309,11 +541,11 @@ impl BaseMods {
         }
         // add to the ml and mm tag.
         for basemod in self.base_mods.iter() {
+            let (positions, quals) = basemod.forward_mm_and_ml(&seq);
             // adding quality values (ML)
-            ml_tag.extend(basemod.ranges.get_forward_quals());
+            ml_tag.extend(quals);
             // get MM tag values
             let mut cur_mm = vec![];
-            let positions = basemod.ranges.get_forward_starts();
             let mut last_pos = 0;
             for pos in positions {
                 let u_pos = pos as usize;
@@ -331,7 +563,10 @@ impl BaseMods {
             // Add to the MM string
             mm_tag.push(basemod.modified_base as char);
             mm_tag.push(basemod.strand);
-            mm_tag.push(basemod.modification_type);
+            mm_tag.push_str(&basemod.modification_type);
+            if basemod.skip_mode == SkipMode::Explicit {
+                mm_tag.push('?');
+            }
             for diff in cur_mm {
                 mm_tag.push_str(&format!(",{}", diff));
             }
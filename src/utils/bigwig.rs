@@ -0,0 +1,611 @@
+use anyhow::{bail, Result};
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+
+const BIGWIG_MAGIC: u32 = 0x888F_FC26;
+const CHROM_TREE_MAGIC: u32 = 0x78CA_8C91;
+const INDEX_MAGIC: u32 = 0x2468_ACE0;
+const BIGWIG_VERSION: u16 = 4;
+const BLOCK_SIZE: usize = 256;
+const ITEMS_PER_SLOT: usize = 1024;
+const HEADER_SIZE: u64 = 64;
+const ZOOM_HEADER_SIZE: u64 = 24;
+const SUMMARY_SIZE: u64 = 40;
+const MAX_ZOOM_LEVELS: usize = 10;
+/// bases summarized by each record of the first zoom level
+const FIRST_ZOOM_REDUCTION: u64 = 100;
+/// each zoom level summarizes this many times more bases than the previous one
+const ZOOM_FACTOR: u64 = 4;
+
+/// little endian serialization of the bigWig fields
+trait PutLe {
+    fn put_u16(&mut self, x: u16);
+    fn put_u32(&mut self, x: u32);
+    fn put_u64(&mut self, x: u64);
+    fn put_f32(&mut self, x: f32);
+    fn put_f64(&mut self, x: f64);
+}
+
+impl PutLe for Vec<u8> {
+    fn put_u16(&mut self, x: u16) {
+        self.extend_from_slice(&x.to_le_bytes());
+    }
+    fn put_u32(&mut self, x: u32) {
+        self.extend_from_slice(&x.to_le_bytes());
+    }
+    fn put_u64(&mut self, x: u64) {
+        self.extend_from_slice(&x.to_le_bytes());
+    }
+    fn put_f32(&mut self, x: f32) {
+        self.extend_from_slice(&x.to_le_bytes());
+    }
+    fn put_f64(&mut self, x: f64) {
+        self.extend_from_slice(&x.to_le_bytes());
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Summary {
+    bases: u64,
+    min: f64,
+    max: f64,
+    sum: f64,
+    sum_squares: f64,
+}
+
+impl Summary {
+    fn new() -> Self {
+        Self {
+            bases: 0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            sum: 0.0,
+            sum_squares: 0.0,
+        }
+    }
+
+    fn add(&mut self, bases: u64, value: f64) {
+        self.bases += bases;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.sum += bases as f64 * value;
+        self.sum_squares += bases as f64 * value * value;
+    }
+}
+
+/// A compressed block of data and the genomic span it covers
+#[derive(Debug, Clone, Copy)]
+struct IndexEntry {
+    start_chrom: u32,
+    start: u32,
+    end_chrom: u32,
+    end: u32,
+    offset: u64,
+    size: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ZoomRecord {
+    chrom: u32,
+    start: u32,
+    end: u32,
+    summary: Summary,
+}
+
+struct ZoomLevel {
+    reduction: u64,
+    current: Option<ZoomRecord>,
+    pending: Vec<ZoomRecord>,
+    /// blocks of this zoom level, with offsets into the temporary zoom file
+    blocks: Vec<IndexEntry>,
+    records: u32,
+}
+
+fn compress(data: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+/// Write a bigWig file from sorted, non-overlapping intervals.
+///
+/// Full resolution data is written as it is added, zoom level summaries are kept in a temporary file
+/// until `finish` is called, which writes the indexes and the header.
+pub struct BigWigWriter {
+    out: BufWriter<File>,
+    pos: u64,
+    chrom_ids: HashMap<String, u32>,
+    data_count_offset: u64,
+    pending: Vec<(u32, u32, f32)>,
+    pending_chrom: u32,
+    last: Option<(u32, u32)>,
+    blocks: Vec<IndexEntry>,
+    summary: Summary,
+    zooms: Vec<ZoomLevel>,
+    zoom_file: File,
+    zoom_file_len: u64,
+    max_uncompressed: usize,
+}
+
+impl BigWigWriter {
+    /// Create a bigWig for the chromosomes (name, length), e.g. from a bam header
+    pub fn create(path: &str, chroms: &[(String, u32)]) -> Result<Self> {
+        let chrom_ids: HashMap<String, u32> = chroms
+            .iter()
+            .enumerate()
+            .map(|(i, (name, _))| (name.clone(), i as u32))
+            .collect();
+        let max_chrom_len = chroms.iter().map(|(_, len)| *len as u64).max().unwrap_or(0);
+        let mut zooms = vec![];
+        let mut reduction = FIRST_ZOOM_REDUCTION;
+        while zooms.len() < MAX_ZOOM_LEVELS && reduction <= max_chrom_len {
+            zooms.push(ZoomLevel {
+                reduction,
+                current: None,
+                pending: vec![],
+                blocks: vec![],
+                records: 0,
+            });
+            reduction *= ZOOM_FACTOR;
+        }
+
+        let mut writer = Self {
+            out: BufWriter::new(File::create(path)?),
+            pos: 0,
+            chrom_ids,
+            data_count_offset: 0,
+            pending: vec![],
+            pending_chrom: 0,
+            last: None,
+            blocks: vec![],
+            summary: Summary::new(),
+            zooms,
+            zoom_file: tempfile::tempfile()?,
+            zoom_file_len: 0,
+            max_uncompressed: 0,
+        };
+        // the header, zoom headers, and summary are filled in by finish
+        let reserved = HEADER_SIZE + ZOOM_HEADER_SIZE * MAX_ZOOM_LEVELS as u64 + SUMMARY_SIZE;
+        writer.write(&vec![0; reserved as usize])?;
+        let tree = chrom_tree(chroms, writer.pos);
+        writer.write(&tree)?;
+        writer.data_count_offset = writer.pos;
+        writer.write(&0u64.to_le_bytes())?;
+        Ok(writer)
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<()> {
+        self.out.write_all(bytes)?;
+        self.pos += bytes.len() as u64;
+        Ok(())
+    }
+
+    /// Add the value of [start, end) on a chromosome. Intervals must be added in the order of the
+    /// chromosomes given to `create` and sorted by position without overlaps.
+    pub fn add(&mut self, chrom: &str, start: u32, end: u32, value: f32) -> Result<()> {
+        if start >= end {
+            return Ok(());
+        }
+        let Some(&chrom_id) = self.chrom_ids.get(chrom) else {
+            bail!("{} is not one of the bigWig chromosomes", chrom);
+        };
+        if let Some((last_chrom, last_end)) = self.last {
+            if chrom_id < last_chrom || (chrom_id == last_chrom && start < last_end) {
+                bail!(
+                    "bigWig intervals must be sorted and not overlap: {}:{}-{}",
+                    chrom,
+                    start,
+                    end
+                );
+            }
+        }
+        self.last = Some((chrom_id, end));
+
+        if !self.pending.is_empty()
+            && (self.pending_chrom != chrom_id || self.pending.len() == ITEMS_PER_SLOT)
+        {
+            self.flush_block()?;
+        }
+        self.pending_chrom = chrom_id;
+        self.pending.push((start, end, value));
+        self.summary.add((end - start) as u64, value as f64);
+        self.add_to_zooms(chrom_id, start, end, value)
+    }
+
+    /// write the pending intervals as a compressed bedGraph block
+    fn flush_block(&mut self) -> Result<()> {
+        let mut data = vec![];
+        data.put_u32(self.pending_chrom);
+        data.put_u32(self.pending[0].0);
+        data.put_u32(self.pending[self.pending.len() - 1].1);
+        data.put_u32(0); // item step
+        data.put_u32(0); // item span
+        data.push(1); // bedGraph block
+        data.push(0);
+        data.put_u16(self.pending.len() as u16);
+        for &(start, end, value) in &self.pending {
+            data.put_u32(start);
+            data.put_u32(end);
+            data.put_f32(value);
+        }
+        self.max_uncompressed = self.max_uncompressed.max(data.len());
+        let compressed = compress(&data)?;
+        self.blocks.push(IndexEntry {
+            start_chrom: self.pending_chrom,
+            start: self.pending[0].0,
+            end_chrom: self.pending_chrom,
+            end: self.pending[self.pending.len() - 1].1,
+            offset: self.pos,
+            size: compressed.len() as u64,
+        });
+        self.write(&compressed)?;
+        self.pending.clear();
+        Ok(())
+    }
+
+    fn add_to_zooms(&mut self, chrom: u32, start: u32, end: u32, value: f32) -> Result<()> {
+        for z in 0..self.zooms.len() {
+            let reduction = self.zooms[z].reduction;
+            let mut bin_start = start as u64;
+            while bin_start < end as u64 {
+                let bin = bin_start / reduction;
+                let bin_end = ((bin + 1) * reduction).min(end as u64);
+                let bases = bin_end - bin_start;
+                let level = &mut self.zooms[z];
+                match level.current.as_mut() {
+                    Some(rec) if rec.chrom == chrom && rec.start as u64 / reduction == bin => {
+                        rec.end = bin_end as u32;
+                        rec.summary.add(bases, value as f64);
+                    }
+                    _ => {
+                        if let Some(rec) = level.current.take() {
+                            level.pending.push(rec);
+                        }
+                        let mut summary = Summary::new();
+                        summary.add(bases, value as f64);
+                        level.current = Some(ZoomRecord {
+                            chrom,
+                            start: bin_start as u32,
+                            end: bin_end as u32,
+                            summary,
+                        });
+                    }
+                }
+                if level.pending.len() >= ITEMS_PER_SLOT {
+                    self.flush_zoom(z)?;
+                }
+                bin_start = bin_end;
+            }
+        }
+        Ok(())
+    }
+
+    /// write the pending zoom records of a level to the temporary zoom file
+    fn flush_zoom(&mut self, z: usize) -> Result<()> {
+        let level = &mut self.zooms[z];
+        let mut data = vec![];
+        for rec in &level.pending {
+            data.put_u32(rec.chrom);
+            data.put_u32(rec.start);
+            data.put_u32(rec.end);
+            data.put_u32(rec.summary.bases as u32);
+            data.put_f32(rec.summary.min as f32);
+            data.put_f32(rec.summary.max as f32);
+            data.put_f32(rec.summary.sum as f32);
+            data.put_f32(rec.summary.sum_squares as f32);
+        }
+        let compressed = compress(&data)?;
+        let (first, last) = (level.pending[0], level.pending[level.pending.len() - 1]);
+        level.blocks.push(IndexEntry {
+            start_chrom: first.chrom,
+            start: first.start,
+            end_chrom: last.chrom,
+            end: last.end,
+            offset: self.zoom_file_len,
+            size: compressed.len() as u64,
+        });
+        level.records += level.pending.len() as u32;
+        level.pending.clear();
+        self.max_uncompressed = self.max_uncompressed.max(data.len());
+        self.zoom_file.write_all(&compressed)?;
+        self.zoom_file_len += compressed.len() as u64;
+        Ok(())
+    }
+
+    /// Write the indexes, zoom levels, and header
+    pub fn finish(mut self) -> Result<()> {
+        if !self.pending.is_empty() {
+            self.flush_block()?;
+        }
+        for z in 0..self.zooms.len() {
+            if let Some(rec) = self.zooms[z].current.take() {
+                self.zooms[z].pending.push(rec);
+            }
+            if !self.zooms[z].pending.is_empty() {
+                self.flush_zoom(z)?;
+            }
+        }
+
+        // full resolution index
+        let full_index_offset = self.pos;
+        let index = index_tree(&self.blocks, full_index_offset, full_index_offset);
+        self.write(&index)?;
+
+        // copy the zoom levels out of the temporary file and index them
+        let mut zoom_headers = vec![];
+        let zooms = std::mem::take(&mut self.zooms);
+        for level in zooms.iter().filter(|level| level.records > 0) {
+            let data_offset = self.pos;
+            self.write(&level.records.to_le_bytes())?;
+            let mut blocks = level.blocks.clone();
+            for block in blocks.iter_mut() {
+                let mut compressed = vec![0; block.size as usize];
+                self.zoom_file.seek(SeekFrom::Start(block.offset))?;
+                self.zoom_file.read_exact(&mut compressed)?;
+                block.offset = self.pos;
+                self.write(&compressed)?;
+            }
+            let index_offset = self.pos;
+            let index = index_tree(&blocks, index_offset, index_offset);
+            self.write(&index)?;
+            zoom_headers.put_u32(level.reduction as u32);
+            zoom_headers.put_u32(0);
+            zoom_headers.put_u64(data_offset);
+            zoom_headers.put_u64(index_offset);
+        }
+        self.write(&BIGWIG_MAGIC.to_le_bytes())?;
+
+        let summary_offset = HEADER_SIZE + ZOOM_HEADER_SIZE * MAX_ZOOM_LEVELS as u64;
+        let mut header = vec![];
+        header.put_u32(BIGWIG_MAGIC);
+        header.put_u16(BIGWIG_VERSION);
+        header.put_u16((zoom_headers.len() as u64 / ZOOM_HEADER_SIZE) as u16);
+        header.put_u64(summary_offset + SUMMARY_SIZE); // chromosome tree
+        header.put_u64(self.data_count_offset);
+        header.put_u64(full_index_offset);
+        header.put_u16(0); // field count
+        header.put_u16(0); // defined field count
+        header.put_u64(0); // autoSql
+        header.put_u64(summary_offset);
+        header.put_u32(self.max_uncompressed as u32);
+        header.put_u64(0); // reserved
+        header.extend_from_slice(&zoom_headers);
+
+        let mut summary = vec![];
+        let s = self.summary;
+        summary.put_u64(s.bases);
+        if s.bases > 0 {
+            summary.put_f64(s.min);
+            summary.put_f64(s.max);
+        } else {
+            summary.put_f64(0.0);
+            summary.put_f64(0.0);
+        }
+        summary.put_f64(s.sum);
+        summary.put_f64(s.sum_squares);
+
+        self.out.seek(SeekFrom::Start(0))?;
+        self.out.write_all(&header)?;
+        self.out.seek(SeekFrom::Start(summary_offset))?;
+        self.out.write_all(&summary)?;
+        self.out.seek(SeekFrom::Start(self.data_count_offset))?;
+        self.out
+            .write_all(&(self.blocks.len() as u64).to_le_bytes())?;
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+/// number of levels in a B+ tree with `items` items
+fn tree_levels(block_size: usize, mut items: usize) -> u32 {
+    let mut levels = 1;
+    while items > block_size {
+        items = items.div_ceil(block_size);
+        levels += 1;
+    }
+    levels
+}
+
+/// B+ tree of the chromosome names, ids, and lengths starting at `offset` in the file
+fn chrom_tree(chroms: &[(String, u32)], offset: u64) -> Vec<u8> {
+    let mut items: Vec<(&[u8], u32, u32)> = chroms
+        .iter()
+        .enumerate()
+        .map(|(i, (name, len))| (name.as_bytes(), i as u32, *len))
+        .collect();
+    items.sort();
+    let key_size = items
+        .iter()
+        .map(|(name, _, _)| name.len())
+        .max()
+        .unwrap_or(1)
+        .max(1);
+    let block_size = items.len().clamp(1, BLOCK_SIZE);
+    let val_size = 8;
+
+    let mut buf = vec![];
+    buf.put_u32(CHROM_TREE_MAGIC);
+    buf.put_u32(block_size as u32);
+    buf.put_u32(key_size as u32);
+    buf.put_u32(val_size);
+    buf.put_u64(items.len() as u64);
+    buf.put_u64(0);
+    let put_key = |buf: &mut Vec<u8>, key: &[u8]| {
+        buf.extend_from_slice(key);
+        buf.resize(buf.len() + key_size - key.len(), 0);
+    };
+
+    // index levels, top down
+    let index_block_bytes = 4 + block_size * (key_size + 8);
+    let leaf_block_bytes = 4 + block_size * (key_size + val_size as usize);
+    for level in (1..tree_levels(block_size, items.len())).rev() {
+        let slot_size = block_size.pow(level);
+        let node_size = slot_size * block_size;
+        let node_count = items.len().div_ceil(node_size);
+        let mut next_child = offset + buf.len() as u64 + (node_count * index_block_bytes) as u64;
+        let child_bytes = if level == 1 {
+            leaf_block_bytes
+        } else {
+            index_block_bytes
+        };
+        for node_start in (0..items.len()).step_by(node_size) {
+            let node_end = (node_start + node_size).min(items.len());
+            let count = (node_end - node_start).div_ceil(slot_size);
+            buf.push(0);
+            buf.push(0);
+            buf.put_u16(count as u16);
+            for (key, _, _) in items[node_start..node_end].iter().step_by(slot_size) {
+                put_key(&mut buf, key);
+                buf.put_u64(next_child);
+                next_child += child_bytes as u64;
+            }
+            buf.resize(buf.len() + (block_size - count) * (key_size + 8), 0);
+        }
+    }
+
+    // leaves
+    for chunk in items.chunks(block_size) {
+        buf.push(1);
+        buf.push(0);
+        buf.put_u16(chunk.len() as u16);
+        for (key, id, len) in chunk {
+            put_key(&mut buf, key);
+            buf.put_u32(*id);
+            buf.put_u32(*len);
+        }
+        buf.resize(
+            buf.len() + (block_size - chunk.len()) * (key_size + val_size as usize),
+            0,
+        );
+    }
+    if items.is_empty() {
+        buf.extend_from_slice(&[1, 0, 0, 0]);
+    }
+    buf
+}
+
+/// R tree index of sorted data blocks starting at `offset` in the file
+fn index_tree(entries: &[IndexEntry], offset: u64, end_file_offset: u64) -> Vec<u8> {
+    // each node is (first child, number of children, [start chrom, start, end chrom, end])
+    let bounds = |first: [u32; 4], last: [u32; 4]| [first[0], first[1], last[2], last[3]];
+    let entry_bounds = |e: &IndexEntry| [e.start_chrom, e.start, e.end_chrom, e.end];
+    let mut leaves = vec![];
+    for (i, chunk) in entries.chunks(BLOCK_SIZE).enumerate() {
+        let b = bounds(
+            entry_bounds(&chunk[0]),
+            entry_bounds(&chunk[chunk.len() - 1]),
+        );
+        leaves.push((i * BLOCK_SIZE, chunk.len(), b));
+    }
+    if leaves.is_empty() {
+        leaves.push((0, 0, [0; 4]));
+    }
+    let mut levels = vec![leaves];
+    while levels[levels.len() - 1].len() > 1 {
+        let children = &levels[levels.len() - 1];
+        let parents = children
+            .chunks(BLOCK_SIZE)
+            .enumerate()
+            .map(|(i, chunk)| {
+                let b = bounds(chunk[0].2, chunk[chunk.len() - 1].2);
+                (i * BLOCK_SIZE, chunk.len(), b)
+            })
+            .collect();
+        levels.push(parents);
+    }
+    levels.reverse();
+
+    // node offsets, root first
+    let header_size = 48;
+    let mut node_offsets = vec![];
+    let mut pos = offset + header_size;
+    for (i, level) in levels.iter().enumerate() {
+        let item_size = if i == levels.len() - 1 { 32 } else { 24 };
+        node_offsets.push(
+            level
+                .iter()
+                .map(|(_, count, _)| {
+                    let node_offset = pos;
+                    pos += 4 + (count * item_size) as u64;
+                    node_offset
+                })
+                .collect::<Vec<_>>(),
+        );
+    }
+
+    let root = levels[0][0].2;
+    let mut buf = vec![];
+    buf.put_u32(INDEX_MAGIC);
+    buf.put_u32(BLOCK_SIZE as u32);
+    buf.put_u64(entries.len() as u64);
+    for x in root {
+        buf.put_u32(x);
+    }
+    buf.put_u64(end_file_offset);
+    buf.put_u32(ITEMS_PER_SLOT as u32);
+    buf.put_u32(0);
+    for (i, level) in levels.iter().enumerate() {
+        let is_leaf = i == levels.len() - 1;
+        for &(first, count, _) in level {
+            buf.push(is_leaf as u8);
+            buf.push(0);
+            buf.put_u16(count as u16);
+            for k in first..first + count {
+                if is_leaf {
+                    let e = &entries[k];
+                    for x in entry_bounds(e) {
+                        buf.put_u32(x);
+                    }
+                    buf.put_u64(e.offset);
+                    buf.put_u64(e.size);
+                } else {
+                    for x in levels[i + 1][k].2 {
+                        buf.put_u32(x);
+                    }
+                    buf.put_u64(node_offsets[i + 1][k]);
+                }
+            }
+        }
+    }
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tree_levels() {
+        assert_eq!(tree_levels(256, 1), 1);
+        assert_eq!(tree_levels(256, 256), 1);
+        assert_eq!(tree_levels(256, 257), 2);
+        assert_eq!(tree_levels(256, 256 * 256 + 1), 3);
+    }
+
+    #[test]
+    fn test_bigwig_header() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let path = file.path().to_str().unwrap();
+        let chroms = vec![("chr1".to_string(), 10_000), ("chr2".to_string(), 5_000)];
+        let mut bw = BigWigWriter::create(path, &chroms).unwrap();
+        bw.add("chr1", 0, 100, 1.0).unwrap();
+        bw.add("chr1", 100, 250, 2.0).unwrap();
+        bw.add("chr2", 10, 20, 3.0).unwrap();
+        assert!(bw.add("chr1", 0, 10, 1.0).is_err());
+        bw.finish().unwrap();
+
+        let bytes = std::fs::read(path).unwrap();
+        let u32_at = |i: usize| u32::from_le_bytes(bytes[i..i + 4].try_into().unwrap());
+        let u64_at = |i: usize| u64::from_le_bytes(bytes[i..i + 8].try_into().unwrap());
+        assert_eq!(u32_at(0), BIGWIG_MAGIC);
+        assert_eq!(u32_at(bytes.len() - 4), BIGWIG_MAGIC);
+        assert_eq!(u32_at(u64_at(8) as usize), CHROM_TREE_MAGIC);
+        assert_eq!(u64_at(u64_at(16) as usize), 2); // data blocks
+        assert_eq!(u32_at(u64_at(24) as usize), INDEX_MAGIC);
+        // bases covered in the total summary
+        assert_eq!(u64_at(u64_at(44) as usize), 260);
+    }
+}
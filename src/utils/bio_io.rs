@@ -193,6 +193,8 @@ pub struct BamChunk<'a> {
     pub pre_chunk_done: u64,
     pub bar: ProgressBar,
     pub bit_flag_filter: u16,
+    /// Number of bam records pulled from the input so far, including skipped records
+    pub records_read: u64,
 }
 
 impl<'a> BamChunk<'a> {
@@ -208,6 +210,7 @@ impl<'a> BamChunk<'a> {
             pre_chunk_done: 0,
             bar,
             bit_flag_filter: 0,
+            records_read: 0,
         }
     }
 
@@ -234,6 +237,7 @@ impl<'a> Iterator for BamChunk<'a> {
         let mut cur_vec = vec![];
         for r in self.bam.by_ref().take(self.chunk_size) {
             let r = r.unwrap();
+            self.records_read += 1;
             if r.cigar().leading_hardclips() > 0 || r.cigar().trailing_hardclips() > 0 {
                 log::warn!(
                     "Skipping read ({}) because it has been hard clipped. This read will be excluded from calculations and any output.",
@@ -269,7 +273,7 @@ impl<'a> Iterator for BamChunk<'a> {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum PbChem {
     Two,
     TwoPointTwo,
@@ -277,31 +281,85 @@ pub enum PbChem {
     Revio,
 }
 
-pub fn find_pb_polymerase(header: &bam::Header) -> PbChem {
-    lazy_static! {
-        static ref CHEMISTRY_MAP: HashMap<String, PbChem> = HashMap::from([
-            // regular 2.0
-            ("101-789-500".to_string(), PbChem::Two),
-            // this is actually 2.1 we didn't have training data for it
-            ("101-820-500".to_string(), PbChem::Two),
-            // regular 2.2
-            ("101-894-200".to_string(), PbChem::TwoPointTwo),
-            // is really 3.1 but has polymerase of 2.1, and we need to make that 2.0
-            ("102-194-200".to_string(), PbChem::Two),
-            // regular 3.2
-            ("102-194-100".to_string(), PbChem::ThreePointTwo),
-            // Revio has kinetics most similar to 2.2
-            ("102-739-100".to_string(), PbChem::Revio),
-            // Vega currently using Revio chemistry
-            ("103-426-500".to_string(), PbChem::Revio)
-        ]);
+impl PbChem {
+    pub fn name(&self) -> &'static str {
+        match self {
+            PbChem::Two => "2.0",
+            PbChem::TwoPointTwo => "2.2",
+            PbChem::ThreePointTwo => "3.2",
+            PbChem::Revio => "Revio",
+        }
+    }
+}
+
+/// Parse a chemistry name into a PbChem
+/// ```
+/// use fibertools_rs::utils::bio_io::PbChem;
+/// assert_eq!("2.2".parse::<PbChem>().unwrap(), PbChem::TwoPointTwo);
+/// assert_eq!("revio".parse::<PbChem>().unwrap(), PbChem::Revio);
+/// assert!("4.0".parse::<PbChem>().is_err());
+/// ```
+impl std::str::FromStr for PbChem {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "2.0" => Ok(PbChem::Two),
+            "2.2" => Ok(PbChem::TwoPointTwo),
+            "3.2" => Ok(PbChem::ThreePointTwo),
+            "revio" => Ok(PbChem::Revio),
+            _ => Err(anyhow::anyhow!(
+                "Unknown chemistry '{}'. Expected one of: 2.0, 2.2, 3.2, revio.",
+                s
+            )),
+        }
     }
+}
+
+lazy_static! {
+    static ref CHEMISTRY_MAP: HashMap<String, PbChem> = HashMap::from([
+        // regular 2.0
+        ("101-789-500".to_string(), PbChem::Two),
+        // this is actually 2.1 we didn't have training data for it
+        ("101-820-500".to_string(), PbChem::Two),
+        // regular 2.2
+        ("101-894-200".to_string(), PbChem::TwoPointTwo),
+        // is really 3.1 but has polymerase of 2.1, and we need to make that 2.0
+        ("102-194-200".to_string(), PbChem::Two),
+        // regular 3.2
+        ("102-194-100".to_string(), PbChem::ThreePointTwo),
+        // Revio has kinetics most similar to 2.2
+        ("102-739-100".to_string(), PbChem::Revio),
+        // Vega currently using Revio chemistry
+        ("103-426-500".to_string(), PbChem::Revio)
+    ]);
+}
+
+/// Read a YAML (or JSON) file mapping BINDINGKIT values to chemistries, e.g.
+/// `{"102-739-100": "revio", "101-894-200": "2.2"}`.
+/// Entries are added to, and take precedence over, the built-in binding kits.
+pub fn read_chemistry_registry(path: &str) -> Result<HashMap<String, PbChem>> {
+    let buffer = buffer_from(path)?;
+    let raw: HashMap<String, String> = serde_yaml::from_reader(buffer)
+        .map_err(|e| anyhow::anyhow!("Unable to parse chemistry registry {}: {}", path, e))?;
+    let mut registry = CHEMISTRY_MAP.clone();
+    for (binding_kit, chem) in raw {
+        registry.insert(binding_kit, chem.parse()?);
+    }
+    Ok(registry)
+}
+
+/// Find the PacBio chemistry from the BINDINGKIT in the read group of the bam header.
+/// An optional registry file can add or override binding kits (see `read_chemistry_registry`).
+pub fn find_pb_polymerase(header: &bam::Header, registry: Option<&str>) -> Result<PbChem> {
     lazy_static! {
         static ref MM_DS: regex::Regex =
             regex::Regex::new(r".*READTYPE=([^;]+);.*BINDINGKIT=([^;]+);").unwrap();
     }
     let z = header.to_hashmap();
-    let rg = z.get("RG").expect("RG tag missing from bam file");
+    let rg = z
+        .get("RG")
+        .ok_or_else(|| anyhow::anyhow!("RG tag missing from bam file"))?;
     let mut read_type = "";
     let mut binding_kit = "";
     for tag in rg {
@@ -319,29 +377,44 @@ pub fn find_pb_polymerase(header: &bam::Header) -> PbChem {
         binding_kit = "102-739-100";
     }
     // make sure read-type is CCS
-    assert_eq!(read_type, "CCS");
+    if read_type != "CCS" {
+        return Err(anyhow::anyhow!(
+            "READTYPE in the bam header must be CCS, found '{}'.",
+            read_type
+        ));
+    }
     // grab chemistry
-    let chemistry = CHEMISTRY_MAP.get(binding_kit).unwrap_or_else(|| {
-        log::error!(
-            "Model for BINDINGKIT={} not available. Unable to run predictions.",
+    let chemistry = match registry {
+        Some(path) => read_chemistry_registry(path)?.get(binding_kit).cloned(),
+        None => CHEMISTRY_MAP.get(binding_kit).cloned(),
+    };
+    let chemistry = chemistry.ok_or_else(|| {
+        anyhow::anyhow!(
+            "Model for BINDINGKIT={} not available. Use --chemistry or a --chemistry-registry file to select a model.",
             binding_kit
-        );
-        std::process::exit(1);
-    });
+        )
+    })?;
 
     // log the chem being used
-    let chem = match chemistry {
-        PbChem::Two => "2.0",
-        PbChem::TwoPointTwo => "2.2",
-        PbChem::ThreePointTwo => "3.2",
-        PbChem::Revio => "Revio",
-    };
     log::info!(
         "Bam header implies PacBio chemistry {} binding kit {}.",
-        chem,
+        chemistry.name(),
         binding_kit
     );
-    chemistry.clone()
+    Ok(chemistry)
+}
+
+/// Get the MN tag, the length of the sequence the MM and ML tags were made for
+pub fn get_mn_tag(record: &bam::Record) -> Option<usize> {
+    match record.aux(b"MN") {
+        Ok(Aux::U8(mn)) => Some(mn as usize),
+        Ok(Aux::U16(mn)) => Some(mn as usize),
+        Ok(Aux::U32(mn)) => Some(mn as usize),
+        Ok(Aux::I8(mn)) => Some(mn as usize),
+        Ok(Aux::I16(mn)) => Some(mn as usize),
+        Ok(Aux::I32(mn)) => Some(mn as usize),
+        _ => None,
+    }
 }
 
 pub fn get_u32_tag(record: &bam::Record, tag: &[u8; 2]) -> Vec<i64> {
@@ -0,0 +1,248 @@
+use crate::cli::CheckpointOptions;
+use anyhow::{bail, Context, Result};
+use rust_htslib::bam::{self, Read};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// One line of a checkpoint file: how many input bam records had been consumed and how many
+/// output records had been written at the end of a chunk, and the name of the last output record.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CheckpointEntry {
+    pub input_records: u64,
+    pub output_records: u64,
+    pub last_read: String,
+}
+
+impl CheckpointEntry {
+    fn to_line(&self) -> String {
+        format!(
+            "{}\t{}\t{}\n",
+            self.input_records, self.output_records, self.last_read
+        )
+    }
+
+    /// ```
+    /// use fibertools_rs::utils::checkpoint::CheckpointEntry;
+    /// let entry = CheckpointEntry::from_line("10\t8\tm84008/1/ccs").unwrap();
+    /// assert_eq!(entry.input_records, 10);
+    /// assert_eq!(entry.output_records, 8);
+    /// assert_eq!(entry.last_read, "m84008/1/ccs");
+    /// ```
+    pub fn from_line(line: &str) -> Result<Self> {
+        let mut cols = line.trim_end_matches('\n').splitn(3, '\t');
+        let (Some(input), Some(output), Some(last_read)) = (cols.next(), cols.next(), cols.next())
+        else {
+            bail!("Malformed checkpoint line: {}", line);
+        };
+        Ok(Self {
+            input_records: input.parse()?,
+            output_records: output.parse()?,
+            last_read: last_read.to_string(),
+        })
+    }
+}
+
+/// The checkpoint file for an output bam
+pub fn checkpoint_path(out: &str) -> String {
+    format!("{out}.ckpt")
+}
+
+/// Where the output of an interrupted run is kept while it is copied into the new output
+fn partial_path(out: &str) -> String {
+    format!("{out}.partial")
+}
+
+/// Read the entries of a checkpoint file, ignoring a final line that was only partly written
+pub fn read_checkpoint(path: &str) -> Result<Vec<CheckpointEntry>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut entries = vec![];
+    for line in reader.lines() {
+        let line = line?;
+        match CheckpointEntry::from_line(&line) {
+            Ok(entry) => entries.push(entry),
+            Err(e) => {
+                log::warn!("Ignoring the rest of checkpoint file {}: {}", path, e);
+                break;
+            }
+        }
+    }
+    Ok(entries)
+}
+
+/// Tracks progress of a bam to bam subcommand and periodically records it next to the output.
+///
+/// htslib cannot reopen a BGZF file for appending, so when resuming the output of the interrupted run
+/// is moved aside and the records it holds up to the last usable checkpoint are copied into the new output.
+/// The matching input records are then skipped and processing continues from there.
+pub struct Checkpoint {
+    out: String,
+    file: Option<File>,
+    interval: Duration,
+    last_write: Instant,
+    entries: Vec<CheckpointEntry>,
+    current: CheckpointEntry,
+}
+
+impl Checkpoint {
+    /// Set up checkpointing for `out`. This must be called before the output bam is opened, since if a checkpoint
+    /// already exists the output from the interrupted run is moved out of the way.
+    pub fn new(out: &str, opts: &CheckpointOptions) -> Result<Self> {
+        let mut checkpoint = Self {
+            out: out.to_string(),
+            file: None,
+            interval: Duration::from_secs(opts.checkpoint_interval),
+            last_write: Instant::now(),
+            entries: vec![],
+            current: CheckpointEntry::default(),
+        };
+        if !opts.checkpoint {
+            return Ok(checkpoint);
+        }
+        if out == "-" {
+            bail!("--checkpoint requires the output to be a file, not stdout.");
+        }
+
+        let ckpt = checkpoint_path(out);
+        if Path::new(&ckpt).exists() {
+            let partial = partial_path(out);
+            // a previous resume may have been interrupted while copying, in which case the partial file is still the one to use
+            if !Path::new(&partial).exists() && Path::new(out).exists() {
+                fs::rename(out, &partial)?;
+            }
+            if Path::new(&partial).exists() {
+                checkpoint.entries = read_checkpoint(&ckpt)?;
+                log::info!("Resuming from checkpoint {}", ckpt);
+            } else {
+                log::warn!(
+                    "Checkpoint {} exists but the output {} does not, starting over.",
+                    ckpt,
+                    out
+                );
+            }
+        }
+        checkpoint.file = Some(
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&ckpt)
+                .with_context(|| format!("Unable to open checkpoint file {}", ckpt))?,
+        );
+        Ok(checkpoint)
+    }
+
+    /// Copy the records of the interrupted run into `out` and skip the matching records in `bam`.
+    /// Does nothing if there is no checkpoint to resume from.
+    pub fn resume(&mut self, bam: &mut bam::Reader, out: &mut bam::Writer) -> Result<()> {
+        let partial = partial_path(&self.out);
+        if self.file.is_none() || !Path::new(&partial).exists() {
+            return Ok(());
+        }
+
+        // find how many records of the partial output can be read, the end of it may not have been written
+        let max_output = self
+            .entries
+            .iter()
+            .map(|e| e.output_records)
+            .max()
+            .unwrap_or(0);
+        let mut readable = 0;
+        if let Ok(mut partial_bam) = bam::Reader::from_path(&partial) {
+            for rec in partial_bam.records() {
+                if rec.is_err() || readable == max_output {
+                    break;
+                }
+                readable += 1;
+            }
+        }
+
+        // use the furthest checkpoint that is entirely within the readable records
+        let entry = self
+            .entries
+            .iter()
+            .filter(|e| e.output_records <= readable)
+            .max_by_key(|e| e.input_records)
+            .cloned()
+            .unwrap_or_default();
+
+        // copy the records from the partial output
+        if entry.output_records > 0 {
+            let mut partial_bam = bam::Reader::from_path(&partial)?;
+            let mut last_read = vec![];
+            for rec in partial_bam.records().take(entry.output_records as usize) {
+                let rec = rec?;
+                out.write(&rec)?;
+                last_read = rec.qname().to_vec();
+            }
+            if last_read != entry.last_read.as_bytes() {
+                bail!(
+                    "The output of the interrupted run does not match its checkpoint: expected {} but found {} as output record {}.",
+                    entry.last_read,
+                    String::from_utf8_lossy(&last_read),
+                    entry.output_records
+                );
+            }
+        }
+
+        // skip the input that was already processed
+        for rec in bam.records().take(entry.input_records as usize) {
+            rec?;
+        }
+        log::info!(
+            "Resumed after {} input records and {} output records (last read: {}).",
+            entry.input_records,
+            entry.output_records,
+            if entry.last_read.is_empty() {
+                "none"
+            } else {
+                &entry.last_read
+            }
+        );
+
+        // start the checkpoint file over from where we resumed, the copied records now live in the new output
+        self.entries.clear();
+        if let Some(file) = self.file.as_mut() {
+            file.set_len(0)?;
+            file.write_all(entry.to_line().as_bytes())?;
+            file.sync_data()?;
+        }
+        fs::remove_file(&partial)?;
+        self.current = entry;
+        Ok(())
+    }
+
+    /// Record a finished chunk: the number of input records it consumed and the records that were written for it.
+    /// A line is added to the checkpoint file if the checkpoint interval has passed.
+    pub fn add_chunk(
+        &mut self,
+        input_records: u64,
+        output_records: u64,
+        last_read: Option<&[u8]>,
+    ) -> Result<()> {
+        self.current.input_records += input_records;
+        self.current.output_records += output_records;
+        if let Some(last_read) = last_read {
+            self.current.last_read = String::from_utf8_lossy(last_read).to_string();
+        }
+        if let Some(file) = self.file.as_mut() {
+            if self.last_write.elapsed() >= self.interval {
+                file.write_all(self.current.to_line().as_bytes())?;
+                file.sync_data()?;
+                self.last_write = Instant::now();
+                log::debug!("Checkpoint: {:?}", self.current);
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove the checkpoint file. Call once the output bam has been closed.
+    pub fn finish(self) -> Result<()> {
+        if self.file.is_none() {
+            return Ok(());
+        }
+        drop(self.file);
+        fs::remove_file(checkpoint_path(&self.out))?;
+        Ok(())
+    }
+}
@@ -162,6 +162,8 @@ pub struct FireFeats<'a> {
     //frac_m6a_in_msps: f32,
     fire_opts: &'a FireOptions,
     seq: Vec<u8>,
+    /// sorted positions of A/T bases that were not assessed for m6A (MM tags in explicit `?` mode)
+    unassessed_m6a: Vec<i64>,
     fire_feats: Vec<(i64, i64, Vec<f32>)>,
 }
 
@@ -178,6 +180,7 @@ impl<'a> FireFeats<'a> {
             //frac_m6a_in_msps,
             fire_opts,
             seq,
+            unassessed_m6a: rec.base_mods.unassessed_m6a(),
             fire_feats: vec![],
         };
 
@@ -198,6 +201,12 @@ impl<'a> FireFeats<'a> {
     }
 
     fn validate_that_ont_is_single_strand(&self) {
+        if !self.rec.base_mods.is_single_strand_m6a() {
+            log::warn!(
+                "{} has m6A calls on both strands, which is not expected for ONT data.",
+                self.rec.get_qname()
+            );
+        }
         let sequenced_bp = if self.rec.record.is_reverse() {
             b'T'
         } else {
@@ -220,8 +229,23 @@ impl<'a> FireFeats<'a> {
         subseq.iter().filter(|&&b| b == bp).count()
     }
 
+    /// like get_bp_count, but without bases that were not assessed for m6A
+    fn get_assessed_bp_count(&self, start: i64, end: i64, bp: u8) -> usize {
+        let count = self.get_bp_count(start, end, bp);
+        if self.unassessed_m6a.is_empty() {
+            return count;
+        }
+        let st = self.unassessed_m6a.partition_point(|&p| p < start);
+        let en = self.unassessed_m6a.partition_point(|&p| p < end);
+        let unassessed = self.unassessed_m6a[st..en]
+            .iter()
+            .filter(|&&p| self.seq[p as usize] == bp)
+            .count();
+        count - unassessed
+    }
+
     fn get_at_count(&self, start: i64, end: i64) -> usize {
-        self.get_bp_count(start, end, b'A') + self.get_bp_count(start, end, b'T')
+        self.get_assessed_bp_count(start, end, b'A') + self.get_assessed_bp_count(start, end, b'T')
     }
 
     fn get_5mc_count(&self, start: i64, end: i64) -> usize {
@@ -246,12 +270,17 @@ impl<'a> FireFeats<'a> {
 
         // estimate what the count would be if we sequenced the other strand
         if self.fire_opts.ont {
-            let mut sequenced_bp = self.get_bp_count(start, end, b'A');
-            let mut un_sequenced_bp = self.get_bp_count(start, end, b'T');
-            if self.rec.record.is_reverse() {
-                // swap the counts
-                std::mem::swap(&mut sequenced_bp, &mut un_sequenced_bp);
-            }
+            let (sequenced_bp, un_sequenced_bp) = if self.rec.record.is_reverse() {
+                (
+                    self.get_assessed_bp_count(start, end, b'T'),
+                    self.get_bp_count(start, end, b'A'),
+                )
+            } else {
+                (
+                    self.get_assessed_bp_count(start, end, b'A'),
+                    self.get_bp_count(start, end, b'T'),
+                )
+            };
             let m6a_frac = if sequenced_bp > 0 {
                 m6a_count as f32 / sequenced_bp as f32
             } else {
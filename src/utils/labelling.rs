@@ -0,0 +1,127 @@
+use crate::fiber::FiberseqData;
+use serde::Serialize;
+
+/// Counts of the A/T bases assessed for m6A and the m6A calls within the MSPs and nucleosomes of one or more fibers,
+/// from which the m6A labelling efficiency of the MTase is estimated.
+///
+/// A/T bases within MSPs are accessible and are labelled with probability `e` (the efficiency),
+/// while A/T bases within nucleosomes are only called as m6A at a background rate `b`
+/// (false positive calls and breathing of the nucleosome). The m6A rate observed in MSPs
+/// is then `e + (1 - e) * b`, which is solved for `e` using the rate in nucleosomes as `b`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct LabellingCounts {
+    pub msp_at: i64,
+    pub msp_m6a: i64,
+    pub nuc_at: i64,
+    pub nuc_m6a: i64,
+}
+
+impl LabellingCounts {
+    /// Count the bases assessed for m6A and the m6A calls within the MSPs and nucleosomes, all in molecular coordinates.
+    /// `assessed` is whether each base of the read was assessed for m6A, see `FiberseqData::m6a_assessed`.
+    pub fn new(assessed: &[bool], m6a: &[i64], msps: &[(i64, i64)], nucs: &[(i64, i64)]) -> Self {
+        let mut is_m6a = vec![false; assessed.len()];
+        for &pos in m6a {
+            if pos >= 0 && (pos as usize) < assessed.len() {
+                is_m6a[pos as usize] = true;
+            }
+        }
+        let count = |ranges: &[(i64, i64)]| -> (i64, i64) {
+            let mut at = 0;
+            let mut m6a = 0;
+            for &(st, en) in ranges {
+                let st = st.clamp(0, assessed.len() as i64) as usize;
+                let en = en.clamp(0, assessed.len() as i64) as usize;
+                for i in st..en {
+                    if assessed[i] {
+                        at += 1;
+                        if is_m6a[i] {
+                            m6a += 1;
+                        }
+                    }
+                }
+            }
+            (at, m6a)
+        };
+        let (msp_at, msp_m6a) = count(msps);
+        let (nuc_at, nuc_m6a) = count(nucs);
+        Self {
+            msp_at,
+            msp_m6a,
+            nuc_at,
+            nuc_m6a,
+        }
+    }
+
+    pub fn from_fiber(fiber: &FiberseqData) -> Self {
+        let assessed = fiber.m6a_assessed();
+        let m6a: Vec<i64> = fiber.m6a.starts.iter().flatten().copied().collect();
+        let msps: Vec<(i64, i64)> = fiber.msp.into_iter().map(|r| (r.0, r.1)).collect();
+        let nucs: Vec<(i64, i64)> = fiber.nuc.into_iter().map(|r| (r.0, r.1)).collect();
+        Self::new(&assessed, &m6a, &msps, &nucs)
+    }
+
+    pub fn add(&mut self, other: &Self) {
+        self.msp_at += other.msp_at;
+        self.msp_m6a += other.msp_m6a;
+        self.nuc_at += other.nuc_at;
+        self.nuc_m6a += other.nuc_m6a;
+    }
+
+    /// fraction of the A/T bases in MSPs with an m6A call
+    pub fn msp_rate(&self) -> Option<f64> {
+        if self.msp_at == 0 {
+            return None;
+        }
+        Some(self.msp_m6a as f64 / self.msp_at as f64)
+    }
+
+    /// fraction of the A/T bases in nucleosomes with an m6A call
+    pub fn background_rate(&self) -> Option<f64> {
+        if self.nuc_at == 0 {
+            return None;
+        }
+        Some(self.nuc_m6a as f64 / self.nuc_at as f64)
+    }
+
+    /// Estimated fraction of accessible A/T bases labelled by the MTase, None without MSPs and nucleosomes
+    pub fn efficiency(&self) -> Option<f64> {
+        let msp_rate = self.msp_rate()?;
+        let background = self.background_rate()?;
+        if background >= 1.0 {
+            return None;
+        }
+        Some(((msp_rate - background) / (1.0 - background)).clamp(0.0, 1.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_labelling_efficiency() {
+        // 10 A/T in the MSP with 5 m6A, 10 A/T in the nucleosome with 1 m6A
+        let assessed: Vec<bool> = b"AAAAATTTTTGGGGGAAAAATTTTT"
+            .iter()
+            .map(|&bp| bp == b'A' || bp == b'T')
+            .collect();
+        let counts =
+            LabellingCounts::new(&assessed, &[0, 2, 4, 6, 8, 15, 30], &[(0, 10)], &[(15, 25)]);
+        assert_eq!(
+            counts,
+            LabellingCounts {
+                msp_at: 10,
+                msp_m6a: 5,
+                nuc_at: 10,
+                nuc_m6a: 1,
+            }
+        );
+        assert_eq!(counts.msp_rate(), Some(0.5));
+        assert_eq!(counts.background_rate(), Some(0.1));
+        assert!((counts.efficiency().unwrap() - 4.0 / 9.0).abs() < 1e-9);
+        // no nucleosomes
+        let counts = LabellingCounts::new(&assessed, &[0], &[(0, 10)], &[]);
+        assert_eq!(counts.efficiency(), None);
+    }
+}
@@ -0,0 +1,198 @@
+use crate::cli::NrlParameters;
+use crate::utils::acf;
+
+/// Nucleosome positions and m6A calls collected over one fiber, or over many fibers within a region,
+/// from which a nucleosome repeat length (NRL) and the regularity of the nucleosome array are estimated.
+#[derive(Debug, Clone, Default)]
+pub struct NrlObservations {
+    pub fibers: usize,
+    pub nucs: usize,
+    /// distances between the centers of adjacent nucleosomes
+    pub spacings: Vec<i64>,
+    /// sums for the m6A auto-correlation of each fiber, so lags never span two fibers
+    m6a_acf_sums: Vec<acf::AcfSums>,
+}
+
+/// Summary of a set of `NrlObservations`
+#[derive(Debug, Clone, PartialEq)]
+pub struct NrlSummary {
+    pub fibers: usize,
+    pub nucs: usize,
+    pub spacings: usize,
+    /// median distance between adjacent nucleosome centers
+    pub median_spacing: Option<f64>,
+    /// coefficient of variation of the distances between adjacent nucleosome centers
+    pub spacing_cv: Option<f64>,
+    /// lag of the m6A auto-correlation peak
+    pub acf_nrl: Option<usize>,
+    /// m6A auto-correlation at `acf_nrl`, higher values indicate a more regular array
+    pub acf_peak: Option<f64>,
+}
+
+impl NrlObservations {
+    /// Add the nucleosomes (start, end) and m6A positions of a fiber observed between `start` and `end`.
+    /// Positions can be in any coordinate system (molecular or reference) as long as it is the same for all inputs.
+    pub fn add_fiber(
+        &mut self,
+        nucs: &[(i64, i64)],
+        m6a: &[i64],
+        start: i64,
+        end: i64,
+        opts: &NrlParameters,
+    ) {
+        if end <= start {
+            return;
+        }
+        self.fibers += 1;
+        let mut centers: Vec<i64> = nucs
+            .iter()
+            .filter(|(st, en)| *st >= start && *en <= end)
+            .map(|(st, en)| (st + en) / 2)
+            .collect();
+        centers.sort();
+        self.nucs += centers.len();
+        self.spacings.extend(
+            centers
+                .windows(2)
+                .map(|w| w[1] - w[0])
+                .filter(|&d| d <= opts.max_nuc_spacing),
+        );
+
+        let m6a: Vec<i64> = m6a.iter().map(|p| p - start).collect();
+        self.m6a_acf_sums.push(acf::AcfSums::from_positions(
+            &m6a,
+            (end - start) as usize,
+            opts.max_nrl + 1,
+        ));
+    }
+
+    pub fn summary(&self, opts: &NrlParameters) -> NrlSummary {
+        let (median_spacing, spacing_cv) = if self.spacings.is_empty() {
+            (None, None)
+        } else {
+            let mut sorted = self.spacings.clone();
+            sorted.sort();
+            // the middle value, or the mean of the two middle values
+            let median = (sorted[(sorted.len() - 1) / 2] + sorted[sorted.len() / 2]) as f64 / 2.0;
+            let n = sorted.len() as f64;
+            let mean = sorted.iter().sum::<i64>() as f64 / n;
+            let var = sorted
+                .iter()
+                .map(|&d| (d as f64 - mean).powi(2))
+                .sum::<f64>()
+                / n;
+            (Some(median), Some(var.sqrt() / mean))
+        };
+        let (acf_nrl, acf_peak) = match self.acf_peak(opts) {
+            Some((lag, val)) => (Some(lag), Some(val)),
+            None => (None, None),
+        };
+        NrlSummary {
+            fibers: self.fibers,
+            nucs: self.nucs,
+            spacings: self.spacings.len(),
+            median_spacing,
+            spacing_cv,
+            acf_nrl,
+            acf_peak,
+        }
+    }
+
+    /// Find the highest local maximum of the m6A auto-correlation between min_nrl and max_nrl
+    fn acf_peak(&self, opts: &NrlParameters) -> Option<(usize, f64)> {
+        // need one lag past the max to know if it is a local maximum
+        let max_lag = opts.max_nrl + 1;
+        if self.m6a_acf_sums.iter().all(|s| s.len() <= max_lag) {
+            return None;
+        }
+        let weights = vec![1.0; self.m6a_acf_sums.len()];
+        let acf = acf::pooled_acf(&self.m6a_acf_sums, &weights, max_lag)?;
+        acf::acf_peak(&acf, opts.min_nrl)
+    }
+}
+
+fn format_option<T: std::fmt::Display>(x: Option<T>) -> String {
+    match x {
+        Some(x) => format!("{}", x),
+        None => ".".to_string(),
+    }
+}
+
+impl NrlSummary {
+    pub fn header() -> String {
+        format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            "nuc_count",
+            "spacing_count",
+            "median_spacing",
+            "spacing_cv",
+            "acf_nrl",
+            "acf_peak",
+            "fiber_count"
+        )
+    }
+}
+
+impl std::fmt::Display for NrlSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            self.nucs,
+            self.spacings,
+            format_option(self.median_spacing),
+            format_option(self.spacing_cv.map(|x| format!("{:.4}", x))),
+            format_option(self.acf_nrl),
+            format_option(self.acf_peak.map(|x| format!("{:.4}", x))),
+            self.fibers
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_regular_array() {
+        let opts = NrlParameters::default();
+        // nucleosomes every 190 bp with m6A in the 43 bp linkers
+        let nucs: Vec<(i64, i64)> = (0..20).map(|i| (i * 190, i * 190 + 147)).collect();
+        let m6a: Vec<i64> = nucs
+            .iter()
+            .flat_map(|(_, en)| (*en..en + 43).step_by(3))
+            .collect();
+        let mut obs = NrlObservations::default();
+        obs.add_fiber(&nucs, &m6a, 0, 20 * 190, &opts);
+        let summary = obs.summary(&opts);
+        assert_eq!(summary.nucs, 20);
+        assert_eq!(summary.spacings, 19);
+        assert_eq!(summary.median_spacing, Some(190.0));
+        assert_eq!(summary.spacing_cv, Some(0.0));
+        let acf_nrl = summary.acf_nrl.unwrap() as i64;
+        assert!((acf_nrl - 190).abs() <= 3, "{:?}", summary);
+    }
+
+    #[test]
+    fn test_many_fibers() {
+        let opts = NrlParameters::default();
+        // fibers with arrays out of phase with each other, offset in a shared coordinate system
+        let mut obs = NrlObservations::default();
+        for f in 0..10 {
+            let offset = f * 10_000 + f * 37;
+            let nucs: Vec<(i64, i64)> = (0..10)
+                .map(|i| (offset + i * 190, offset + i * 190 + 147))
+                .collect();
+            let m6a: Vec<i64> = nucs
+                .iter()
+                .flat_map(|(_, en)| (*en..en + 43).step_by(3))
+                .collect();
+            obs.add_fiber(&nucs, &m6a, offset, offset + 10 * 190, &opts);
+        }
+        let summary = obs.summary(&opts);
+        assert_eq!(summary.fibers, 10);
+        assert_eq!(summary.nucs, 100);
+        let acf_nrl = summary.acf_nrl.unwrap() as i64;
+        assert!((acf_nrl - 190).abs() <= 3, "{:?}", summary);
+    }
+}
@@ -1,4 +1,6 @@
 use crate::cli::NucleosomeParameters;
+use crate::utils::nucleosome_hmm::hmm_nucleosomes;
+use bio::alphabets::dna::revcomp;
 use rust_htslib::{
     bam,
     bam::record::{Aux, AuxArray},
@@ -172,15 +174,19 @@ pub fn find_msps(nucs: &[(i64, i64)], m6a: &[i64]) -> Vec<(i64, i64)> {
     msps
 }
 
+/// whether a span is at least `distance_from_end` from both ends of the fiber
+fn far_from_end(record: &bam::Record, start: i64, length: i64, distance_from_end: i64) -> bool {
+    start >= distance_from_end && start + length <= record.seq_len() as i64 - distance_from_end
+}
+
 pub fn filter_for_end(
     record: &bam::Record,
     spans: &[(i64, i64)],
     distance_from_end: i64,
 ) -> (Vec<u32>, Vec<u32>) {
-    let length = record.seq_len() as i64;
     spans
         .iter()
-        .filter(|(s, l)| *s >= distance_from_end && s + l <= length - distance_from_end)
+        .filter(|(s, l)| far_from_end(record, *s, *l, distance_from_end))
         .map(|(s, l)| (*s as u32, *l as u32))
         .unzip()
 }
@@ -192,11 +198,25 @@ pub fn add_nucleosomes_to_record(
 ) {
     record.remove_aux(b"ns").unwrap_or(());
     record.remove_aux(b"nl").unwrap_or(());
+    record.remove_aux(b"nq").unwrap_or(());
     record.remove_aux(b"as").unwrap_or(());
     record.remove_aux(b"al").unwrap_or(());
     record.remove_aux(b"aq").unwrap_or(());
 
-    let nucs = if options.allowed_m6a_skips < 0 {
+    let mut nuc_quals = vec![];
+    let nucs = if options.nucleosome_caller == "hmm" {
+        let mut forward_seq = record.seq().as_bytes();
+        if record.is_reverse() {
+            forward_seq = revcomp(forward_seq);
+        }
+        let hmm_nucs = hmm_nucleosomes(&forward_seq, m6a, options);
+        nuc_quals = hmm_nucs
+            .iter()
+            .filter(|(s, l, _)| far_from_end(record, *s, *l, options.distance_from_end))
+            .map(|(_, _, q)| *q)
+            .collect();
+        hmm_nucs.into_iter().map(|(s, l, _)| (s, l)).collect()
+    } else if options.allowed_m6a_skips < 0 {
         find_nucleosomes(m6a, options)
     } else {
         d_segment_nucleosomes(m6a, options)
@@ -217,6 +237,10 @@ pub fn add_nucleosomes_to_record(
         let aux_array_field = Aux::ArrayU32(aux_array);
         record.push_aux(tag, aux_array_field).unwrap();
     }
+    if !nuc_quals.is_empty() {
+        let aux_array: AuxArray<u8> = (&nuc_quals).into();
+        record.push_aux(b"nq", Aux::ArrayU8(aux_array)).unwrap();
+    }
 }
 
 #[cfg(test)]
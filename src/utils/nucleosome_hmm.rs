@@ -0,0 +1,303 @@
+use crate::cli::NucleosomeParameters;
+
+/// rounds of decoding and re-estimating the parameters for each fiber
+const FIT_ITERATIONS: usize = 3;
+const MIN_P: f64 = 1e-4;
+/// expected linker and nucleosome lengths used to initialize the transitions
+const INIT_LINKER_LEN: f64 = 50.0;
+const INIT_NUC_LEN: f64 = 147.0;
+
+/// Parameters of a nucleosome/linker HMM over the m6A state of each A/T base.
+///
+/// Nucleosomes are a chain of `min_nuc_len` states, so every nucleosome is at least that long,
+/// ending in a state that can repeat. Linkers are a single repeating state.
+/// Bases that were not assessed for m6A (e.g. G/C) are uninformative.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NucHmm {
+    pub min_nuc_len: usize,
+    /// probability an assessed A/T base in a nucleosome has an m6A call
+    pub p_m6a_nuc: f64,
+    /// probability an assessed A/T base in a linker has an m6A call
+    pub p_m6a_linker: f64,
+    /// probability of starting a nucleosome at the next base while in a linker
+    pub p_nuc_start: f64,
+    /// probability of ending a nucleosome at the next base once it is `min_nuc_len` long
+    pub p_nuc_end: f64,
+}
+
+impl NucHmm {
+    /// Initialize the parameters from the overall m6A rate of a fiber
+    pub fn new(obs: &[Option<bool>], min_nuc_len: usize) -> Self {
+        let (m6a, assessed) = obs.iter().flatten().fold((0.0f64, 0.0f64), |(m, a), &o| {
+            (m + if o { 1.0 } else { 0.0 }, a + 1.0)
+        });
+        let frac = if assessed > 0.0 { m6a / assessed } else { 0.0 };
+        let min_nuc_len = min_nuc_len.max(2);
+        Self {
+            min_nuc_len,
+            p_m6a_nuc: (frac / 10.0).clamp(MIN_P, 0.2),
+            p_m6a_linker: (frac * 3.0).clamp(0.05, 1.0 - MIN_P),
+            p_nuc_start: 1.0 / INIT_LINKER_LEN,
+            p_nuc_end: 1.0 / (INIT_NUC_LEN - min_nuc_len as f64).max(2.0),
+        }
+    }
+
+    /// emission probabilities for (nucleosome, linker) states
+    #[inline]
+    fn emission(&self, obs: Option<bool>) -> (f64, f64) {
+        match obs {
+            Some(true) => (self.p_m6a_nuc, self.p_m6a_linker),
+            Some(false) => (1.0 - self.p_m6a_nuc, 1.0 - self.p_m6a_linker),
+            None => (1.0, 1.0),
+        }
+    }
+
+    /// Most likely nucleosome (true) or linker (false) state of each base
+    pub fn viterbi(&self, obs: &[Option<bool>]) -> Vec<bool> {
+        if obs.is_empty() {
+            return vec![];
+        }
+        let k = self.min_nuc_len;
+        let (ln_start, ln_stay_linker) = (self.p_nuc_start.ln(), (1.0 - self.p_nuc_start).ln());
+        let (ln_end, ln_stay_nuc) = (self.p_nuc_end.ln(), (1.0 - self.p_nuc_end).ln());
+        // back pointers for the only two states with more than one way in
+        let mut tail_from_tail = vec![false; obs.len()];
+        let mut linker_from_tail = vec![false; obs.len()];
+
+        let (em_n, em_l) = self.emission(obs[0]);
+        let mut nuc = vec![(0.5 / k as f64).ln() + em_n.ln(); k];
+        let mut linker = 0.5f64.ln() + em_l.ln();
+        for (t, &o) in obs.iter().enumerate().skip(1) {
+            let (em_n, em_l) = self.emission(o);
+            let (ln_em_n, ln_em_l) = (em_n.ln(), em_l.ln());
+            let stay_tail = nuc[k - 1] + ln_stay_nuc;
+            tail_from_tail[t] = stay_tail > nuc[k - 2];
+            let new_tail = stay_tail.max(nuc[k - 2]);
+            let end_nuc = nuc[k - 1] + ln_end;
+            let stay_linker = linker + ln_stay_linker;
+            linker_from_tail[t] = end_nuc > stay_linker;
+            let new_linker = end_nuc.max(stay_linker);
+            let new_start = linker + ln_start;
+
+            nuc.copy_within(0..k - 2, 1);
+            nuc[0] = new_start;
+            nuc[k - 1] = new_tail;
+            nuc.iter_mut().for_each(|v| *v += ln_em_n);
+            linker = new_linker + ln_em_l;
+        }
+
+        // trace back, states 0..k are the nucleosome chain and k is the linker
+        let (mut state, best_nuc) =
+            nuc.iter()
+                .enumerate()
+                .fold((0, f64::NEG_INFINITY), |(bi, bv), (i, &v)| {
+                    if v > bv {
+                        (i, v)
+                    } else {
+                        (bi, bv)
+                    }
+                });
+        if linker >= best_nuc {
+            state = k;
+        }
+        let mut path = vec![false; obs.len()];
+        for t in (0..obs.len()).rev() {
+            path[t] = state != k;
+            if t == 0 {
+                break;
+            }
+            state = if state == k {
+                if linker_from_tail[t] {
+                    k - 1
+                } else {
+                    k
+                }
+            } else if state == 0 {
+                k
+            } else if state == k - 1 && tail_from_tail[t] {
+                k - 1
+            } else {
+                state - 1
+            };
+        }
+        path
+    }
+
+    /// Posterior probability that each base is in a nucleosome, using the forward-backward algorithm
+    pub fn posterior(&self, obs: &[Option<bool>]) -> Vec<f64> {
+        if obs.is_empty() {
+            return vec![];
+        }
+        let k = self.min_nuc_len;
+        let (s, e) = (self.p_nuc_start, self.p_nuc_end);
+
+        // forward pass, keeping only the linker column and the scaling factors
+        let mut scales = vec![0.0; obs.len()];
+        let mut f_linker = vec![0.0; obs.len()];
+        let (em_n, em_l) = self.emission(obs[0]);
+        let mut nuc = vec![0.5 / k as f64 * em_n; k];
+        let mut linker = 0.5 * em_l;
+        for (t, &o) in obs.iter().enumerate() {
+            if t > 0 {
+                let (em_n, em_l) = self.emission(o);
+                let new_tail = nuc[k - 2] + nuc[k - 1] * (1.0 - e);
+                let new_linker = linker * (1.0 - s) + nuc[k - 1] * e;
+                let new_start = linker * s;
+                nuc.copy_within(0..k - 2, 1);
+                nuc[0] = new_start;
+                nuc[k - 1] = new_tail;
+                nuc.iter_mut().for_each(|v| *v *= em_n);
+                linker = new_linker * em_l;
+            }
+            let scale = nuc.iter().sum::<f64>() + linker;
+            nuc.iter_mut().for_each(|v| *v /= scale);
+            linker /= scale;
+            scales[t] = scale;
+            f_linker[t] = linker;
+        }
+
+        // backward pass
+        let mut posterior = vec![0.0; obs.len()];
+        let mut nuc = vec![1.0; k];
+        let mut linker = 1.0;
+        for t in (0..obs.len()).rev() {
+            if t < obs.len() - 1 {
+                let (em_n, em_l) = self.emission(obs[t + 1]);
+                let scale = scales[t + 1];
+                let new_linker = ((1.0 - s) * em_l * linker + s * em_n * nuc[0]) / scale;
+                let new_tail = ((1.0 - e) * em_n * nuc[k - 1] + e * em_l * linker) / scale;
+                nuc.copy_within(1..k, 0);
+                nuc[..k - 1].iter_mut().for_each(|v| *v *= em_n / scale);
+                nuc[k - 1] = new_tail;
+                linker = new_linker;
+            }
+            posterior[t] = (1.0 - f_linker[t] * linker).clamp(0.0, 1.0);
+        }
+        posterior
+    }
+
+    /// Re-estimate the parameters from a decoded path
+    fn refit(&mut self, obs: &[Option<bool>], path: &[bool]) {
+        let (mut nuc_at, mut nuc_m6a, mut linker_at, mut linker_m6a) = (0.0f64, 0.0, 0.0, 0.0);
+        for (o, &is_nuc) in obs.iter().zip(path) {
+            if let Some(m6a) = o {
+                let m6a = if *m6a { 1.0 } else { 0.0 };
+                if is_nuc {
+                    nuc_at += 1.0;
+                    nuc_m6a += m6a;
+                } else {
+                    linker_at += 1.0;
+                    linker_m6a += m6a;
+                }
+            }
+        }
+        let p_m6a_nuc = ((nuc_m6a + 0.5) / (nuc_at + 1.0)).clamp(MIN_P, 0.5);
+        let p_m6a_linker = ((linker_m6a + 0.5) / (linker_at + 1.0)).clamp(MIN_P, 1.0 - MIN_P);
+        // keep the previous emissions if the fit does not separate the states
+        if p_m6a_linker > p_m6a_nuc {
+            self.p_m6a_nuc = p_m6a_nuc;
+            self.p_m6a_linker = p_m6a_linker;
+        }
+
+        let nucs = segments(path);
+        if nucs.is_empty() {
+            return;
+        }
+        let nuc_bp: usize = nucs.iter().map(|(_, l)| *l as usize).sum();
+        let linker_bp = path.len() - nuc_bp;
+        let n = nucs.len() as f64;
+        self.p_nuc_start = (n / (linker_bp as f64 + 1.0)).clamp(MIN_P, 0.5);
+        let extra_bp = nuc_bp.saturating_sub(nucs.len() * self.min_nuc_len) as f64;
+        self.p_nuc_end = (n / (extra_bp + n)).clamp(MIN_P, 1.0 - MIN_P);
+    }
+}
+
+/// (start, length) of the runs of true values
+fn segments(path: &[bool]) -> Vec<(i64, i64)> {
+    let mut rtn = vec![];
+    let mut start = None;
+    for (i, &is_nuc) in path.iter().chain([false].iter()).enumerate() {
+        match (is_nuc, start) {
+            (true, None) => start = Some(i),
+            (false, Some(st)) => {
+                rtn.push((st as i64, (i - st) as i64));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    rtn
+}
+
+/// Per base m6A observations of a fiber. Only bases of the same type as the m6A calls are assessed,
+/// so ONT data with calls on only the A bases does not treat the T bases as unmethylated.
+pub fn m6a_observations(forward_seq: &[u8], m6a: &[i64]) -> Vec<Option<bool>> {
+    let called_a = m6a.iter().any(|&p| forward_seq[p as usize] == b'A');
+    let called_t = m6a.iter().any(|&p| forward_seq[p as usize] == b'T');
+    let mut obs: Vec<Option<bool>> = forward_seq
+        .iter()
+        .map(|&b| match b {
+            b'A' if called_a => Some(false),
+            b'T' if called_t => Some(false),
+            _ => None,
+        })
+        .collect();
+    for &p in m6a {
+        obs[p as usize] = Some(true);
+    }
+    obs
+}
+
+/// Call nucleosomes with a HMM fit to each fiber.
+/// Returns the (start, length, quality) of each nucleosome in the forward orientation of the read,
+/// where quality is the mean posterior probability of being in a nucleosome scaled to 0-255.
+pub fn hmm_nucleosomes(
+    forward_seq: &[u8],
+    m6a: &[i64],
+    options: &NucleosomeParameters,
+) -> Vec<(i64, i64, u8)> {
+    if m6a.is_empty() {
+        return vec![];
+    }
+    let obs = m6a_observations(forward_seq, m6a);
+    let mut hmm = NucHmm::new(&obs, options.nucleosome_length.max(0) as usize);
+    let mut path = hmm.viterbi(&obs);
+    for _ in 0..FIT_ITERATIONS {
+        hmm.refit(&obs, &path);
+        path = hmm.viterbi(&obs);
+    }
+    log::trace!("{:?}", hmm);
+    let posterior = hmm.posterior(&obs);
+    segments(&path)
+        .into_iter()
+        .map(|(st, ln)| {
+            let mean = posterior[st as usize..(st + ln) as usize]
+                .iter()
+                .sum::<f64>()
+                / ln as f64;
+            (st, ln, (mean * 255.0).round() as u8)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hmm_nucleosomes() {
+        let seq = vec![b'A'; 700];
+        // m6A every 4 bp outside of two nucleosome sized gaps
+        let m6a: Vec<i64> = (0..700)
+            .step_by(4)
+            .filter(|p| !(100..250).contains(p) && !(400..550).contains(p))
+            .collect();
+        let nucs = hmm_nucleosomes(&seq, &m6a, &NucleosomeParameters::default());
+        assert_eq!(nucs.len(), 2);
+        for ((st, ln, q), (exp_st, exp_ln)) in nucs.iter().zip([(97, 155), (397, 155)]) {
+            assert!((st - exp_st).abs() <= 3, "{:?}", nucs);
+            assert!((ln - exp_ln).abs() <= 6, "{:?}", nucs);
+            assert!(*q > 200, "{:?}", nucs);
+        }
+    }
+}
@@ -0,0 +1,99 @@
+/// Two-sided Fisher's exact test of 2x2 tables, with a cache of log factorials so many tables can be tested quickly
+#[derive(Debug, Clone)]
+pub struct FisherExact {
+    ln_factorials: Vec<f64>,
+}
+
+impl FisherExact {
+    pub fn new() -> Self {
+        Self {
+            ln_factorials: vec![0.0],
+        }
+    }
+
+    fn ln_factorial(&mut self, n: u64) -> f64 {
+        while self.ln_factorials.len() <= n as usize {
+            let i = self.ln_factorials.len();
+            self.ln_factorials
+                .push(self.ln_factorials[i - 1] + (i as f64).ln());
+        }
+        self.ln_factorials[n as usize]
+    }
+
+    /// log probability of a table with `a` in the top left cell given the margins
+    fn ln_hypergeometric(&mut self, a: u64, row1: u64, row2: u64, col1: u64) -> f64 {
+        let n = row1 + row2;
+        let (b, c) = (row1 - a, col1 - a);
+        let d = row2 - c;
+        self.ln_factorial(row1)
+            + self.ln_factorial(row2)
+            + self.ln_factorial(col1)
+            + self.ln_factorial(n - col1)
+            - self.ln_factorial(n)
+            - self.ln_factorial(a)
+            - self.ln_factorial(b)
+            - self.ln_factorial(c)
+            - self.ln_factorial(d)
+    }
+
+    /// p-value of the table [[a, b], [c, d]], the sum of the probabilities of the tables
+    /// with the same margins that are no more likely than the observed table
+    pub fn two_sided(&mut self, a: u64, b: u64, c: u64, d: u64) -> f64 {
+        let (row1, row2, col1) = (a + b, c + d, a + c);
+        let min_a = col1.saturating_sub(row2);
+        let max_a = row1.min(col1);
+        let observed = self.ln_hypergeometric(a, row1, row2, col1);
+        let p: f64 = (min_a..=max_a)
+            .map(|x| self.ln_hypergeometric(x, row1, row2, col1))
+            .filter(|&ln_p| ln_p <= observed + 1e-7)
+            .map(f64::exp)
+            .sum();
+        p.min(1.0)
+    }
+}
+
+impl Default for FisherExact {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Benjamini-Hochberg adjusted p-values (q-values) in the order of `p_values`.
+/// `n_tests` can be larger than the number of p-values when only the smallest p-values of the tests were kept.
+pub fn benjamini_hochberg(p_values: &[f64], n_tests: usize) -> Vec<f64> {
+    let n_tests = n_tests.max(p_values.len()) as f64;
+    let mut order: Vec<usize> = (0..p_values.len()).collect();
+    order.sort_by(|&i, &j| p_values[i].total_cmp(&p_values[j]));
+    let mut q_values = vec![1.0; p_values.len()];
+    let mut min_q: f64 = 1.0;
+    for (rank, &i) in order.iter().enumerate().rev() {
+        min_q = min_q.min(p_values[i] * n_tests / (rank + 1) as f64);
+        q_values[i] = min_q;
+    }
+    q_values
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fisher_exact() {
+        let mut fisher = FisherExact::new();
+        let p = fisher.two_sided(1, 9, 11, 3);
+        assert!((p - 0.002759).abs() < 1e-6, "{}", p);
+        assert!((fisher.two_sided(5, 5, 5, 5) - 1.0).abs() < 1e-9);
+        assert!((fisher.two_sided(0, 0, 0, 0) - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_benjamini_hochberg() {
+        let q = benjamini_hochberg(&[0.01, 0.04, 0.03, 0.005], 4);
+        let expected = [0.02, 0.04, 0.04, 0.02];
+        for (q, e) in q.iter().zip(expected.iter()) {
+            assert!((q - e).abs() < 1e-12, "{:?}", q);
+        }
+        // only the smallest p-value of 10 tests was kept
+        assert_eq!(benjamini_hochberg(&[0.001], 10), vec![0.01]);
+    }
+}
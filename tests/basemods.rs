@@ -19,3 +19,124 @@ fn test_mods_do_not_change() {
         assert_eq!(mods, mods_2);
     }
 }
+
+#[test]
+/// ONT (Dorado) m6A calls are only on the sequenced strand while PacBio calls are on both strands
+fn test_single_strand_m6a() {
+    let mut bam = bam::Reader::from_path("tests/data/ONT.NAPA.bam").unwrap();
+    for rec in bam.records() {
+        let rec = rec.unwrap();
+        assert!(BaseMods::new(&rec, 0).is_single_strand_m6a());
+    }
+    let mut bam = bam::Reader::from_path("tests/data/all.bam").unwrap();
+    let any_double_strand = bam
+        .records()
+        .map(|rec| BaseMods::new(&rec.unwrap(), 0))
+        .any(|mods| !mods.is_single_strand_m6a());
+    assert!(any_double_strand);
+}
+
+#[test]
+/// bases left out of an MM tag in explicit mode (`?`) were not assessed, and the mode is kept when writing
+fn test_explicit_skip_mode() {
+    let mut rec = bam::Record::new();
+    rec.set(b"read", None, b"AAAATTTT", &[30; 8]);
+    rec.push_aux(b"MM", bam::record::Aux::String("A+a?,1;"))
+        .unwrap();
+    rec.push_aux(b"ML", bam::record::Aux::ArrayU8((&vec![200u8]).into()))
+        .unwrap();
+    let mods = BaseMods::new(&rec, 0);
+    assert_eq!(mods.base_mods.len(), 1);
+    assert_eq!(mods.base_mods[0].skip_mode, SkipMode::Explicit);
+    assert_eq!(mods.base_mods[0].ranges.get_starts(), vec![1]);
+    assert_eq!(mods.unassessed_m6a(), vec![0, 2, 3]);
+
+    mods.add_mm_and_ml_tags(&mut rec);
+    if let Ok(bam::record::Aux::String(mm)) = rec.aux(b"MM") {
+        assert_eq!(mm, "A+a?,1;");
+    } else {
+        panic!("MM tag missing");
+    }
+    assert_eq!(mods, BaseMods::new(&rec, 0));
+}
+
+#[test]
+/// modifications can be selected by MM code, ChEBI id, or name
+fn test_generic_mod_codes() {
+    let mut rec = bam::Record::new();
+    rec.set(b"read", None, b"ACGTACGT", &[30; 8]);
+    rec.push_aux(b"MM", bam::record::Aux::String("C+h,1;A+28871,0;"))
+        .unwrap();
+    rec.push_aux(b"ML", bam::record::Aux::ArrayU8((&vec![200u8, 220]).into()))
+        .unwrap();
+    let mods = BaseMods::new(&rec, 0);
+    assert_eq!(mods.get_mod("5hmC").get_starts(), vec![5]);
+    assert_eq!(mods.get_mod("h").get_starts(), vec![5]);
+    assert_eq!(mods.get_mod("76792").get_starts(), vec![5]);
+    assert_eq!(mods.m6a().get_starts(), vec![0]);
+    assert_eq!(mods.get_mod("a").get_starts(), vec![0]);
+    assert!(mods.cpg().get_starts().is_empty());
+}
+
+#[test]
+/// combined codes (e.g. C+hm) are split into one modification per code with their interleaved ML values
+fn test_combined_mod_codes() {
+    let mut rec = bam::Record::new();
+    rec.set(b"read", None, b"ACGTACGT", &[30; 8]);
+    rec.push_aux(b"MM", bam::record::Aux::String("C+hm,0,0;A+a,1;"))
+        .unwrap();
+    rec.push_aux(
+        b"ML",
+        bam::record::Aux::ArrayU8((&vec![10u8, 200, 20, 210, 150]).into()),
+    )
+    .unwrap();
+    let mods = BaseMods::new(&rec, 0);
+    assert_eq!(mods.get_mod("h").get_starts(), vec![1, 5]);
+    assert_eq!(mods.get_mod("h").qual, vec![10, 20]);
+    assert_eq!(mods.cpg().qual, vec![200, 210]);
+    assert_eq!(mods.m6a().get_starts(), vec![4]);
+    assert_eq!(mods.m6a().qual, vec![150]);
+    assert!(parse_mod_code("hm").is_err());
+    assert!(parse_mod_code("5hmC").is_ok());
+}
+
+#[test]
+/// filtering calls in explicit mode keeps the filtered bases assessed when the tags are written again
+fn test_filter_explicit_skip_mode() {
+    let mut rec = bam::Record::new();
+    rec.set(b"read", None, b"AAAATTTT", &[30; 8]);
+    rec.push_aux(b"MM", bam::record::Aux::String("A+a?,0,1;"))
+        .unwrap();
+    rec.push_aux(b"ML", bam::record::Aux::ArrayU8((&vec![200u8, 50]).into()))
+        .unwrap();
+    let mut mods = BaseMods::new(&rec, 0);
+    assert_eq!(mods.unassessed_m6a(), vec![1, 3]);
+    mods.filter_m6a(100);
+    assert_eq!(mods.m6a().get_starts(), vec![0]);
+
+    mods.add_mm_and_ml_tags(&mut rec);
+    let reparsed = BaseMods::new(&rec, 0);
+    assert_eq!(reparsed.unassessed_m6a(), mods.unassessed_m6a());
+    assert_eq!(BaseMods::new(&rec, 100).m6a().get_starts(), vec![0]);
+
+    // every call filtered, the unassessed bases are still kept
+    mods.filter_m6a(255);
+    mods.add_mm_and_ml_tags(&mut rec);
+    assert_eq!(BaseMods::new(&rec, 100).unassessed_m6a(), vec![1, 3]);
+}
+
+#[test]
+/// an m6A code without calls, or whose calls are all below the ML threshold, is kept as assessed
+fn test_m6a_code_without_calls() {
+    let mut rec = bam::Record::new();
+    rec.set(b"read", None, b"AAAATTTT", &[30; 8]);
+    rec.push_aux(b"MM", bam::record::Aux::String("A+a.;T-a,0;"))
+        .unwrap();
+    rec.push_aux(b"ML", bam::record::Aux::ArrayU8((&vec![50u8]).into()))
+        .unwrap();
+    let mods = BaseMods::new(&rec, 100);
+    assert_eq!(mods.base_mods.len(), 2);
+    assert!(mods.base_mods.iter().all(|bm| bm.is_m6a()));
+    assert!(mods.m6a().get_starts().is_empty());
+    assert!(mods.unassessed_m6a().is_empty());
+}
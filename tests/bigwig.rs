@@ -0,0 +1,279 @@
+use assert_cmd::prelude::*;
+use fibertools_rs::utils::bigwig::BigWigWriter;
+use flate2::read::ZlibDecoder;
+use std::collections::HashMap;
+use std::io::Read;
+use std::process::Command;
+
+/// A bigWig reader written from the file format spec (Kent et al. 2010), independently of `BigWigWriter`
+struct BigWigFile {
+    bytes: Vec<u8>,
+}
+
+impl BigWigFile {
+    fn open(path: &str) -> Self {
+        let bytes = std::fs::read(path).unwrap();
+        assert_eq!(
+            u32::from_le_bytes(bytes[0..4].try_into().unwrap()),
+            0x888F_FC26
+        );
+        Self { bytes }
+    }
+
+    fn u16_at(&self, i: u64) -> u16 {
+        let i = i as usize;
+        u16::from_le_bytes(self.bytes[i..i + 2].try_into().unwrap())
+    }
+
+    fn u32_at(&self, i: u64) -> u32 {
+        let i = i as usize;
+        u32::from_le_bytes(self.bytes[i..i + 4].try_into().unwrap())
+    }
+
+    fn u64_at(&self, i: u64) -> u64 {
+        let i = i as usize;
+        u64::from_le_bytes(self.bytes[i..i + 8].try_into().unwrap())
+    }
+
+    fn f32_at(data: &[u8], i: usize) -> f32 {
+        f32::from_le_bytes(data[i..i + 4].try_into().unwrap())
+    }
+
+    fn block(&self, offset: u64, size: u64) -> Vec<u8> {
+        let compressed = &self.bytes[offset as usize..(offset + size) as usize];
+        let mut data = vec![];
+        ZlibDecoder::new(compressed).read_to_end(&mut data).unwrap();
+        data
+    }
+
+    /// chromosome id -> (name, length) from the chromosome B+ tree
+    fn chroms(&self) -> HashMap<u32, (String, u32)> {
+        let offset = self.u64_at(8);
+        assert_eq!(self.u32_at(offset), 0x78CA_8C91);
+        let key_size = self.u32_at(offset + 8) as u64;
+        let mut chroms = HashMap::new();
+        let mut nodes = vec![offset + 32];
+        while let Some(node) = nodes.pop() {
+            let is_leaf = self.bytes[node as usize] == 1;
+            let count = self.u16_at(node + 2) as u64;
+            for i in 0..count {
+                let item = node + 4 + i * (key_size + 8);
+                if is_leaf {
+                    let key = &self.bytes[item as usize..(item + key_size) as usize];
+                    let name =
+                        String::from_utf8(key.iter().take_while(|&&b| b != 0).copied().collect())
+                            .unwrap();
+                    chroms.insert(
+                        self.u32_at(item + key_size),
+                        (name, self.u32_at(item + key_size + 4)),
+                    );
+                } else {
+                    nodes.push(self.u64_at(item + key_size));
+                }
+            }
+        }
+        chroms
+    }
+
+    /// (offset, size) of every data block in an R tree index
+    fn index_blocks(&self, offset: u64) -> Vec<(u64, u64)> {
+        assert_eq!(self.u32_at(offset), 0x2468_ACE0);
+        let mut blocks = vec![];
+        let mut nodes = vec![offset + 48];
+        while let Some(node) = nodes.pop() {
+            let is_leaf = self.bytes[node as usize] == 1;
+            let count = self.u16_at(node + 2) as u64;
+            for i in 0..count {
+                if is_leaf {
+                    let item = node + 4 + i * 32;
+                    blocks.push((self.u64_at(item + 16), self.u64_at(item + 24)));
+                } else {
+                    let item = node + 4 + i * 24;
+                    nodes.push(self.u64_at(item + 16));
+                }
+            }
+        }
+        blocks.sort();
+        blocks
+    }
+
+    /// the full resolution intervals (chrom, start, end, value)
+    fn intervals(&self) -> Vec<(String, u32, u32, f32)> {
+        let chroms = self.chroms();
+        let mut intervals = vec![];
+        for (offset, size) in self.index_blocks(self.u64_at(24)) {
+            let data = self.block(offset, size);
+            let chrom = u32::from_le_bytes(data[0..4].try_into().unwrap());
+            // bedGraph section
+            assert_eq!(data[20], 1);
+            let count = u16::from_le_bytes(data[22..24].try_into().unwrap()) as usize;
+            assert_eq!(data.len(), 24 + count * 12);
+            for i in 0..count {
+                let item = 24 + i * 12;
+                intervals.push((
+                    chroms[&chrom].0.clone(),
+                    u32::from_le_bytes(data[item..item + 4].try_into().unwrap()),
+                    u32::from_le_bytes(data[item + 4..item + 8].try_into().unwrap()),
+                    Self::f32_at(&data, item + 8),
+                ));
+            }
+        }
+        intervals
+    }
+
+    /// bases covered and sum of the values over the records of the first zoom level
+    fn first_zoom_totals(&self) -> Option<(u64, f64)> {
+        if self.u16_at(6) == 0 {
+            return None;
+        }
+        let (mut bases, mut sum) = (0, 0.0);
+        for (offset, size) in self.index_blocks(self.u64_at(64 + 16)) {
+            let data = self.block(offset, size);
+            for rec in data.chunks(32) {
+                bases += u32::from_le_bytes(rec[12..16].try_into().unwrap()) as u64;
+                sum += Self::f32_at(rec, 24) as f64;
+            }
+        }
+        Some((bases, sum))
+    }
+
+    /// bases covered by the total summary
+    fn summary_bases(&self) -> u64 {
+        self.u64_at(self.u64_at(44))
+    }
+
+    /// min, max, sum, and sum of squares of the total summary
+    fn summary_values(&self) -> [f64; 4] {
+        let offset = self.u64_at(44) + 8;
+        [0, 8, 16, 24].map(|i| {
+            f64::from_le_bytes(
+                self.bytes[(offset + i) as usize..(offset + i + 8) as usize]
+                    .try_into()
+                    .unwrap(),
+            )
+        })
+    }
+}
+
+fn read_bedgraph(path: &str) -> Vec<(String, u32, u32, f32)> {
+    std::fs::read_to_string(path)
+        .unwrap()
+        .lines()
+        .map(|line| {
+            let t: Vec<&str> = line.split('\t').collect();
+            (
+                t[0].to_string(),
+                t[1].parse().unwrap(),
+                t[2].parse().unwrap(),
+                t[3].parse().unwrap(),
+            )
+        })
+        .collect()
+}
+
+#[test]
+/// many chromosomes and intervals so the chromosome and index trees have more than one level
+fn test_bigwig_round_trip() {
+    let file = tempfile::NamedTempFile::new().unwrap();
+    let path = file.path().to_str().unwrap();
+    let chroms: Vec<(String, u32)> = (0..300)
+        .map(|i| (format!("chr{}", i), 10_000_000))
+        .collect();
+    let mut expected = vec![];
+    for (chrom, _) in chroms.iter().take(2) {
+        for i in 0..140_000u32 {
+            expected.push((chrom.clone(), i * 10, i * 10 + 5, (i % 17) as f32 / 4.0));
+        }
+    }
+    expected.push(("chr299".to_string(), 0, 10_000_000, 1.5));
+    let mut bw = BigWigWriter::create(path, &chroms).unwrap();
+    for (chrom, start, end, value) in expected.iter() {
+        bw.add(chrom, *start, *end, *value).unwrap();
+    }
+    bw.finish().unwrap();
+
+    let bw = BigWigFile::open(path);
+    assert_eq!(bw.chroms().len(), 300);
+    assert_eq!(bw.chroms()[&299], ("chr299".to_string(), 10_000_000));
+    let intervals = bw.intervals();
+    assert_eq!(intervals.len(), expected.len());
+    assert_eq!(intervals, expected);
+    let bases: u64 = expected.iter().map(|(_, st, en, _)| (en - st) as u64).sum();
+    assert_eq!(bw.summary_bases(), bases);
+    let (zoom_bases, zoom_sum) = bw.first_zoom_totals().unwrap();
+    assert_eq!(zoom_bases, bases);
+    let sum: f64 = expected
+        .iter()
+        .map(|(_, st, en, v)| (en - st) as f64 * *v as f64)
+        .sum();
+    assert!((zoom_sum - sum).abs() / sum < 1e-4);
+}
+
+#[test]
+/// the bigWig tracks of a pileup have the same intervals and values as the bedGraph tracks
+fn test_pileup_bigwig_matches_bedgraph() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let prefix = dir.path().join("pileup");
+    let prefix = prefix.to_str().unwrap();
+    let mut cmd = Command::cargo_bin("ft")?;
+    cmd.arg("pileup")
+        .arg("tests/data/all.bam")
+        .arg("-o")
+        .arg("/dev/null")
+        .arg("--bedgraph")
+        .arg(prefix)
+        .arg("--bigwig")
+        .arg(prefix)
+        .arg("--m6a-fraction");
+    cmd.assert().success();
+    for column in ["coverage", "fire_coverage", "score", "m6a_fraction"] {
+        let bedgraph = read_bedgraph(&format!("{}.{}.bedGraph", prefix, column));
+        assert!(!bedgraph.is_empty(), "{} is empty", column);
+        let bw = BigWigFile::open(&format!("{}.{}.bw", prefix, column));
+        assert_eq!(bw.intervals(), bedgraph, "{}", column);
+    }
+    Ok(())
+}
+
+#[test]
+#[ignore = "needs tests/data/bigwig_fixture.bw from the UCSC bedGraphToBigWig"]
+/// a bigWig made by the UCSC tools from the same bedGraph decodes to the same intervals and summary as ours.
+/// Make the fixture with:
+/// bedGraphToBigWig tests/data/bigwig_fixture.bedGraph tests/data/bigwig_fixture.chrom.sizes tests/data/bigwig_fixture.bw
+fn test_bigwig_matches_ucsc() {
+    let bedgraph = read_bedgraph("tests/data/bigwig_fixture.bedGraph");
+    let chroms: Vec<(String, u32)> =
+        std::fs::read_to_string("tests/data/bigwig_fixture.chrom.sizes")
+            .unwrap()
+            .lines()
+            .map(|line| {
+                let (name, len) = line.split_once('\t').unwrap();
+                (name.to_string(), len.parse().unwrap())
+            })
+            .collect();
+    let file = tempfile::NamedTempFile::new().unwrap();
+    let path = file.path().to_str().unwrap();
+    let mut bw = BigWigWriter::create(path, &chroms).unwrap();
+    for (chrom, start, end, value) in bedgraph.iter() {
+        bw.add(chrom, *start, *end, *value).unwrap();
+    }
+    bw.finish().unwrap();
+
+    let ucsc = BigWigFile::open("tests/data/bigwig_fixture.bw");
+    let ours = BigWigFile::open(path);
+    assert_eq!(ucsc.intervals(), bedgraph);
+    assert_eq!(ours.intervals(), ucsc.intervals());
+    let mut ucsc_chroms: Vec<_> = ucsc.chroms().into_values().collect();
+    let mut our_chroms: Vec<_> = ours.chroms().into_values().collect();
+    ucsc_chroms.sort();
+    our_chroms.sort();
+    assert_eq!(our_chroms, ucsc_chroms);
+    assert_eq!(ours.summary_bases(), ucsc.summary_bases());
+    for (a, b) in ours.summary_values().iter().zip(ucsc.summary_values()) {
+        assert!((a - b).abs() <= 1e-6 * b.abs().max(1.0), "{} != {}", a, b);
+    }
+    let (our_bases, our_sum) = ours.first_zoom_totals().unwrap();
+    let (ucsc_bases, ucsc_sum) = ucsc.first_zoom_totals().unwrap();
+    assert_eq!(our_bases, ucsc_bases);
+    assert!((our_sum - ucsc_sum).abs() / ucsc_sum < 1e-4);
+}
@@ -13,3 +13,18 @@ pub fn test_msp_nuc_tags() {
         log::debug!("{:?}", a_s);
     }
 }
+
+#[test]
+pub fn test_find_pb_polymerase() {
+    let bam = bam::Reader::from_path("tests/data/all.bam").unwrap();
+    let header = bam::Header::from_template(bam.header());
+    assert!(bio_io::find_pb_polymerase(&header, None).is_ok());
+    // unknown binding kits are an error rather than an exit
+    let mut unknown = bam::Header::new();
+    let mut rg = bam::header::HeaderRecord::new(b"RG");
+    rg.push_tag(b"ID", "unknown");
+    rg.push_tag(b"DS", "READTYPE=CCS;BINDINGKIT=999-999-999;");
+    unknown.push_record(&rg);
+    let err = bio_io::find_pb_polymerase(&unknown, None).unwrap_err();
+    assert!(err.to_string().contains("BINDINGKIT=999-999-999"));
+}
@@ -0,0 +1,217 @@
+use assert_cmd::prelude::*;
+use rust_htslib::bam;
+use rust_htslib::bam::record::{Aux, Cigar, CigarString};
+use std::process::Command;
+
+/// the sequence of chrT in tests/data/cpg_test.fa, with CpGs at 2, 6, 12, and 18
+static REFERENCE: &[u8] = b"TTCGAACGTTTTCGAATTCGAA";
+
+/// A read aligned to the whole of chrT
+struct TestRead {
+    name: String,
+    /// the aligned sequence, in the orientation of the reference
+    seq: Vec<u8>,
+    reverse: bool,
+    mm: &'static str,
+    ml: Vec<u8>,
+    hp: Option<u8>,
+}
+
+impl TestRead {
+    fn new(name: &str, mm: &'static str, ml: &[u8]) -> Self {
+        Self {
+            name: name.to_string(),
+            seq: REFERENCE.to_vec(),
+            reverse: false,
+            mm,
+            ml: ml.to_vec(),
+            hp: None,
+        }
+    }
+}
+
+/// Write an indexed bam of reads aligned to chrT
+fn write_bam(path: &str, reads: &[TestRead]) {
+    let mut header = bam::Header::new();
+    let mut sq = bam::header::HeaderRecord::new(b"SQ");
+    sq.push_tag(b"SN", "chrT");
+    sq.push_tag(b"LN", REFERENCE.len());
+    header.push_record(&sq);
+    let mut writer = bam::Writer::from_path(path, &header, bam::Format::Bam).unwrap();
+    for read in reads {
+        let mut rec = bam::Record::new();
+        let cigar = CigarString(vec![Cigar::Match(read.seq.len() as u32)]);
+        rec.set(
+            read.name.as_bytes(),
+            Some(&cigar),
+            &read.seq,
+            &vec![30; read.seq.len()],
+        );
+        if read.reverse {
+            rec.set_reverse();
+        }
+        rec.set_tid(0);
+        rec.set_pos(0);
+        rec.set_mapq(60);
+        rec.set_bin(4681);
+        rec.set_mtid(-1);
+        rec.set_mpos(-1);
+        rec.push_aux(b"MM", Aux::String(read.mm)).unwrap();
+        rec.push_aux(b"ML", Aux::ArrayU8((&read.ml).into()))
+            .unwrap();
+        if let Some(hp) = read.hp {
+            rec.push_aux(b"HP", Aux::U8(hp)).unwrap();
+        }
+        writer.write(&rec).unwrap();
+    }
+    drop(writer);
+    bam::index::build(path, None, bam::index::Type::Bai, 1).unwrap();
+}
+
+/// the records of `ft cpg-pileup` run on the bam with the extra arguments
+fn cpg_pileup(
+    bam_path: &str,
+    dir: &tempfile::TempDir,
+    args: &[&str],
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let out = dir.path().join("cpg.bed");
+    let mut cmd = Command::cargo_bin("ft")?;
+    cmd.arg("cpg-pileup")
+        .arg("--reference")
+        .arg("tests/data/cpg_test.fa")
+        .arg("--out")
+        .arg(&out)
+        .args(args)
+        .arg(bam_path);
+    cmd.assert().success();
+    Ok(std::fs::read_to_string(&out)?
+        .lines()
+        .skip(1)
+        .map(|l| l.to_string())
+        .collect())
+}
+
+#[test]
+/// unlisted Cs at CpGs are unmodified in implicit mode and not assessed in explicit mode
+fn test_cpg_pileup_skip_modes() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let bam_path = dir.path().join("cpg.bam");
+    let bam_path = bam_path.to_str().unwrap();
+    // every read has a 5mC call on the CpG at 6
+    let mut reads = vec![];
+    for i in 0..4 {
+        reads.push(TestRead::new(&format!("implicit{}", i), "C+m,1;", &[200]));
+        reads.push(TestRead::new(&format!("explicit{}", i), "C+m?,1;", &[200]));
+    }
+    write_bam(bam_path, &reads);
+
+    let lines = cpg_pileup(bam_path, &dir, &[])?;
+    assert_eq!(
+        lines,
+        vec![
+            "chrT\t2\t3\t+\tTotal\t4\t0\t4\t0.0000\t0.0",
+            "chrT\t6\t7\t+\tTotal\t8\t8\t0\t1.0000\t200.0",
+            "chrT\t12\t13\t+\tTotal\t4\t0\t4\t0.0000\t0.0",
+            "chrT\t18\t19\t+\tTotal\t4\t0\t4\t0.0000\t0.0",
+        ]
+    );
+    Ok(())
+}
+
+/// forward reads on haplotype 1, reverse reads on haplotype 2, and a forward read with a C in place of
+/// the G of the CpG at 6, whose implicit unmodified call on that C must not be counted on the - strand
+fn strand_test_bam(dir: &tempfile::TempDir) -> String {
+    let bam_path = dir.path().join("strands.bam");
+    let bam_path = bam_path.to_str().unwrap().to_string();
+    let mut reads = vec![];
+    for i in 0..4 {
+        // 5mC on the C at 6
+        let mut read = TestRead::new(&format!("fwd{}", i), "C+m,1;", &[200]);
+        read.hp = Some(1);
+        reads.push(read);
+    }
+    for i in 0..2 {
+        // the second C of the original read is the G at 13, so 5mC on the - strand of the CpG at 12
+        let mut read = TestRead::new(&format!("rev{}", i), "C+m,1;", &[200]);
+        read.reverse = true;
+        read.hp = Some(2);
+        reads.push(read);
+    }
+    let mut snp = TestRead::new("snp", "C+m;", &[]);
+    snp.seq[7] = b'C';
+    reads.push(snp);
+    write_bam(&bam_path, &reads);
+    bam_path
+}
+
+#[test]
+/// calls are counted on the strand of the read that assessed them
+fn test_cpg_pileup_strands() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let bam_path = strand_test_bam(&dir);
+    let lines = cpg_pileup(&bam_path, &dir, &["--min-coverage", "1"])?;
+    assert_eq!(
+        lines,
+        vec![
+            "chrT\t2\t3\t+\tTotal\t5\t0\t5\t0.0000\t0.0",
+            "chrT\t3\t4\t-\tTotal\t2\t0\t2\t0.0000\t0.0",
+            "chrT\t6\t7\t+\tTotal\t5\t4\t1\t0.8000\t160.0",
+            "chrT\t7\t8\t-\tTotal\t2\t0\t2\t0.0000\t0.0",
+            "chrT\t12\t13\t+\tTotal\t5\t0\t5\t0.0000\t0.0",
+            "chrT\t13\t14\t-\tTotal\t2\t2\t0\t1.0000\t200.0",
+            "chrT\t18\t19\t+\tTotal\t5\t0\t5\t0.0000\t0.0",
+            "chrT\t19\t20\t-\tTotal\t2\t0\t2\t0.0000\t0.0",
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_cpg_pileup_combine_strands() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let bam_path = strand_test_bam(&dir);
+    let lines = cpg_pileup(
+        &bam_path,
+        &dir,
+        &["--min-coverage", "1", "--combine-strands"],
+    )?;
+    assert_eq!(
+        lines,
+        vec![
+            "chrT\t2\t4\t.\tTotal\t7\t0\t7\t0.0000\t0.0",
+            "chrT\t6\t8\t.\tTotal\t7\t4\t3\t0.5714\t114.3",
+            "chrT\t12\t14\t.\tTotal\t7\t2\t5\t0.2857\t57.1",
+            "chrT\t18\t20\t.\tTotal\t7\t0\t7\t0.0000\t0.0",
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_cpg_pileup_haps() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let bam_path = strand_test_bam(&dir);
+    let lines = cpg_pileup(&bam_path, &dir, &["--min-coverage", "1", "--haps"])?;
+    assert_eq!(
+        lines,
+        vec![
+            "chrT\t2\t3\t+\tTotal\t5\t0\t5\t0.0000\t0.0",
+            "chrT\t2\t3\t+\thap1\t4\t0\t4\t0.0000\t0.0",
+            "chrT\t3\t4\t-\tTotal\t2\t0\t2\t0.0000\t0.0",
+            "chrT\t3\t4\t-\thap2\t2\t0\t2\t0.0000\t0.0",
+            "chrT\t6\t7\t+\tTotal\t5\t4\t1\t0.8000\t160.0",
+            "chrT\t6\t7\t+\thap1\t4\t4\t0\t1.0000\t200.0",
+            "chrT\t7\t8\t-\tTotal\t2\t0\t2\t0.0000\t0.0",
+            "chrT\t7\t8\t-\thap2\t2\t0\t2\t0.0000\t0.0",
+            "chrT\t12\t13\t+\tTotal\t5\t0\t5\t0.0000\t0.0",
+            "chrT\t12\t13\t+\thap1\t4\t0\t4\t0.0000\t0.0",
+            "chrT\t13\t14\t-\tTotal\t2\t2\t0\t1.0000\t200.0",
+            "chrT\t13\t14\t-\thap2\t2\t2\t0\t1.0000\t200.0",
+            "chrT\t18\t19\t+\tTotal\t5\t0\t5\t0.0000\t0.0",
+            "chrT\t18\t19\t+\thap1\t4\t0\t4\t0.0000\t0.0",
+            "chrT\t19\t20\t-\tTotal\t2\t0\t2\t0.0000\t0.0",
+            "chrT\t19\t20\t-\thap2\t2\t0\t2\t0.0000\t0.0",
+        ]
+    );
+    Ok(())
+}
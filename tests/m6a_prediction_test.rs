@@ -1,7 +1,7 @@
 use fibertools_rs::subcommands::predict_m6a::WINDOW;
 use fibertools_rs::utils::bio_io;
 use fibertools_rs::utils::input_bam::FiberFilters;
-use rust_htslib::bam::Reader;
+use rust_htslib::bam::{Read, Reader};
 use tempfile::NamedTempFile;
 
 fn sum_qual(bam: &mut Reader) -> usize {
@@ -48,7 +48,8 @@ fn run_prediction_and_count_qual(inbam: String) -> usize {
 
         {
             // run prediction
-            fibertools_rs::subcommands::predict_m6a::read_bam_into_fiberdata(&mut predict_options);
+            fibertools_rs::subcommands::predict_m6a::read_bam_into_fiberdata(&mut predict_options)
+                .unwrap();
         }
 
         // read in the output bam and check the sum of the quality scores
@@ -92,3 +93,93 @@ fn test_three_two_m6a_prediction() {
 fn test_revio_m6a_prediction() {
     run_comparison("tests/data/revio.bam");
 }
+
+#[test]
+fn test_raw_probabilities() {
+    let named_tmp_bam_out = NamedTempFile::new().unwrap();
+    let out_str = named_tmp_bam_out.path().to_str().unwrap();
+    let mut predict_options = fibertools_rs::cli::PredictM6AOptions::default();
+    predict_options.input.bam = "tests/data/revio.bam".to_string();
+    predict_options.input.global.threads = 1;
+    predict_options.out = out_str.to_string();
+    predict_options.raw_probabilities = true;
+    fibertools_rs::subcommands::predict_m6a::read_bam_into_fiberdata(&mut predict_options).unwrap();
+
+    let mut predicted_bam = bio_io::bam_reader(out_str);
+    let mut n_with_probabilities = 0;
+    for record in predicted_bam.records() {
+        let record = record.unwrap();
+        let probabilities = bio_io::get_f32_tag(&record, b"mp");
+        // reads without kinetics are not predicted on
+        if probabilities.is_empty() {
+            continue;
+        }
+        n_with_probabilities += 1;
+        let at_count = record
+            .seq()
+            .as_bytes()
+            .iter()
+            .filter(|&b| *b == b'A' || *b == b'T')
+            .count();
+        assert_eq!(probabilities.len(), at_count);
+        assert!(probabilities.iter().all(|p| (0.0..=1.0).contains(p)));
+    }
+    assert!(n_with_probabilities > 0);
+}
+
+#[test]
+fn test_resume_from_checkpoint() {
+    let dir = tempfile::tempdir().unwrap();
+    let full_out = dir.path().join("full.bam").to_str().unwrap().to_string();
+    let resumed_out = dir.path().join("resumed.bam").to_str().unwrap().to_string();
+    let run = |out: &str| {
+        let mut predict_options = fibertools_rs::cli::PredictM6AOptions::default();
+        predict_options.input.bam = "tests/data/revio.bam".to_string();
+        predict_options.input.global.threads = 1;
+        predict_options.out = out.to_string();
+        predict_options.checkpoint.checkpoint = true;
+        fibertools_rs::subcommands::predict_m6a::read_bam_into_fiberdata(&mut predict_options)
+            .unwrap();
+    };
+    run(&full_out);
+    let full: Vec<_> = bio_io::bam_reader(&full_out)
+        .records()
+        .map(|r| r.unwrap())
+        .collect();
+    assert!(full.len() > 2);
+
+    // fake an interrupted run that made it through half of the reads
+    let n = full.len() / 2;
+    {
+        let template = bio_io::bam_reader(&full_out);
+        let header = rust_htslib::bam::Header::from_template(template.header());
+        let mut partial = rust_htslib::bam::Writer::from_path(
+            &resumed_out,
+            &header,
+            rust_htslib::bam::Format::Bam,
+        )
+        .unwrap();
+        for rec in full.iter().take(n) {
+            partial.write(rec).unwrap();
+        }
+    }
+    let ckpt = fibertools_rs::utils::checkpoint::checkpoint_path(&resumed_out);
+    std::fs::write(
+        &ckpt,
+        format!(
+            "{}\t{}\t{}\n",
+            n,
+            n,
+            String::from_utf8_lossy(full[n - 1].qname())
+        ),
+    )
+    .unwrap();
+
+    run(&resumed_out);
+    let resumed: Vec<_> = bio_io::bam_reader(&resumed_out)
+        .records()
+        .map(|r| r.unwrap())
+        .collect();
+    assert_eq!(full, resumed);
+    assert!(!std::path::Path::new(&ckpt).exists());
+}
@@ -0,0 +1,131 @@
+use assert_cmd::prelude::*;
+use rust_htslib::{bam, bam::Read};
+use std::collections::HashMap;
+use std::io::Write;
+use std::process::Command;
+
+/// header and rows of a pileup output
+fn read_pileup(path: &std::path::Path) -> (Vec<String>, Vec<Vec<String>>) {
+    let text = std::fs::read_to_string(path).unwrap();
+    let mut lines = text.lines();
+    let header = lines
+        .next()
+        .unwrap()
+        .split('\t')
+        .map(|s| s.to_string())
+        .collect();
+    let rows = lines
+        .map(|line| line.split('\t').map(|s| s.to_string()).collect())
+        .collect();
+    (header, rows)
+}
+
+#[test]
+/// every fiber is put in one of two groups, so the coverage of the groups adds up to the total coverage
+fn test_pileup_group_tsv() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let tsv = dir.path().join("groups.tsv");
+    let out = dir.path().join("pileup.bed");
+
+    let mut groups = HashMap::new();
+    let mut bam = bam::Reader::from_path("tests/data/all.bam")?;
+    for rec in bam.records() {
+        let name = String::from_utf8(rec?.qname().to_vec())?;
+        let group = if groups.len() % 2 == 0 { "g1" } else { "g2" };
+        groups.entry(name).or_insert(group);
+    }
+    let mut f = std::fs::File::create(&tsv)?;
+    for (name, group) in groups.iter() {
+        writeln!(f, "{}\t{}", name, group)?;
+    }
+
+    let mut cmd = Command::cargo_bin("ft")?;
+    cmd.arg("pileup")
+        .arg("tests/data/all.bam")
+        .arg("--group-tsv")
+        .arg(&tsv)
+        .arg("--groups")
+        .arg("g1,g2")
+        .arg("-o")
+        .arg(&out);
+    cmd.assert().success();
+
+    let (header, rows) = read_pileup(&out);
+    let base_columns = ["coverage", "fire_coverage", "score", "nuc_coverage"];
+    for column in base_columns {
+        assert!(header.contains(&column.to_string()), "{}", column);
+        for group in ["g1", "g2"] {
+            let name = format!("{}_{}", group, column);
+            assert!(header.contains(&name), "{}", name);
+        }
+    }
+    assert!(!header
+        .iter()
+        .any(|c| c.starts_with("H1") || c.ends_with("_H1")));
+
+    let col = |name: &str| header.iter().position(|c| c == name).unwrap();
+    let (total, g1, g2) = (col("coverage"), col("g1_coverage"), col("g2_coverage"));
+    assert!(!rows.is_empty());
+    for row in rows.iter() {
+        let cov = |i: usize| row[i].parse::<i32>().unwrap();
+        assert_eq!(cov(total), cov(g1) + cov(g2), "{:?}", row);
+    }
+    Ok(())
+}
+
+#[test]
+/// groups of tags other than RG are not searched for in the bam
+fn test_pileup_group_by_requires_groups() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("ft")?;
+    cmd.arg("pileup")
+        .arg("tests/data/all.bam")
+        .arg("--group-by")
+        .arg("XX")
+        .arg("-o")
+        .arg("/dev/null");
+    cmd.assert().failure();
+    Ok(())
+}
+
+#[test]
+/// the pileup of the regions of a bed file is the same as the pileups of each (merged) region in the order of the bam header
+fn test_pileup_bed_matches_regions() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let bed = dir.path().join("regions.bed");
+    std::fs::write(
+        &bed,
+        "chr19\t45681000\t45682500\nchr11\t5204000\t5206000\nchr4\t3009800\t3011000\nchr4\t3009000\t3010000\nchrUn\t0\t100\n",
+    )?;
+    let bed_out = dir.path().join("bed.pileup");
+    let mut cmd = Command::cargo_bin("ft")?;
+    cmd.arg("pileup")
+        .arg("tests/data/ctcf.bam")
+        .arg("--bed")
+        .arg(&bed)
+        .arg("-o")
+        .arg(&bed_out);
+    cmd.assert().success();
+    let (bed_header, bed_rows) = read_pileup(&bed_out);
+
+    let mut rows = vec![];
+    for rgn in [
+        "chr4:3009000-3011000",
+        "chr11:5204000-5206000",
+        "chr19:45681000-45682500",
+    ] {
+        let out = dir.path().join("rgn.pileup");
+        let mut cmd = Command::cargo_bin("ft")?;
+        cmd.arg("pileup")
+            .arg("tests/data/ctcf.bam")
+            .arg(rgn)
+            .arg("-o")
+            .arg(&out);
+        cmd.assert().success();
+        let (header, rgn_rows) = read_pileup(&out);
+        assert_eq!(header, bed_header);
+        assert!(!rgn_rows.is_empty(), "{}", rgn);
+        rows.extend(rgn_rows);
+    }
+    assert_eq!(bed_rows, rows);
+    Ok(())
+}
@@ -114,6 +114,52 @@ fn test_pileup() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+#[test]
+fn test_pileup_bed() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("ft")?;
+    cmd.arg("pileup")
+        .arg("-v")
+        .arg("tests/data/ctcf.bam")
+        .arg("--bed")
+        .arg("tests/data/ctcf.bed.gz")
+        .arg("-o")
+        .arg("/dev/null");
+    cmd.assert()
+        .success()
+        .stderr(predicate::str::contains("done! Time elapsed:"));
+    Ok(())
+}
+
+#[test]
+fn test_pileup_tracks() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let prefix = dir.path().join("pileup");
+    let prefix = prefix.to_str().unwrap();
+    let mut cmd = Command::cargo_bin("ft")?;
+    cmd.arg("pileup")
+        .arg("tests/data/all.bam")
+        .arg("-o")
+        .arg("/dev/null")
+        .arg("--bedgraph")
+        .arg(prefix)
+        .arg("--bigwig")
+        .arg(prefix)
+        .arg("--m6a-fraction");
+    cmd.assert().success();
+    for column in [
+        "coverage",
+        "fire_coverage",
+        "score",
+        "nuc_coverage",
+        "at_coverage",
+        "m6a_fraction",
+    ] {
+        assert!(std::path::Path::new(&format!("{}.{}.bedGraph", prefix, column)).exists());
+        assert!(std::path::Path::new(&format!("{}.{}.bw", prefix, column)).exists());
+    }
+    Ok(())
+}
+
 #[test]
 fn test_qc() -> Result<(), Box<dyn std::error::Error>> {
     let mut cmd = Command::cargo_bin("ft")?;
@@ -128,3 +174,101 @@ fn test_qc() -> Result<(), Box<dyn std::error::Error>> {
         .stderr(predicate::str::contains("done! Time elapsed:"));
     Ok(())
 }
+
+#[test]
+fn test_qc_merge() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let qc = dir.path().join("qc.tsv");
+    let mut cmd = Command::cargo_bin("ft")?;
+    cmd.arg("qc")
+        .arg("--acf")
+        .arg("tests/data/all.bam")
+        .arg(&qc);
+    cmd.assert().success();
+    let mut cmd = Command::cargo_bin("ft")?;
+    cmd.arg("qc-merge")
+        .arg(&qc)
+        .arg(&qc)
+        .arg("-o")
+        .arg("/dev/null")
+        .arg("--json")
+        .arg(dir.path().join("merged.json"));
+    cmd.assert().success();
+    Ok(())
+}
+
+#[test]
+fn test_qc_bed_strata() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let bed = dir.path().join("strata.bed");
+    std::fs::write(&bed, "chr1\t0\t248956422\tchr1\nchr2\t0\t242193529\tchr2\n")?;
+    let mut cmd = Command::cargo_bin("ft")?;
+    cmd.arg("qc")
+        .arg("tests/data/all.bam")
+        .arg("/dev/null")
+        .arg("--bed")
+        .arg(&bed)
+        .arg("--bed-out")
+        .arg(dir.path().join("strata.tsv"));
+    cmd.assert().success();
+    Ok(())
+}
+
+#[test]
+fn test_qc_acf_series() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let acf_out = dir.path().join("acf.tsv");
+    let mut cmd = Command::cargo_bin("ft")?;
+    cmd.arg("qc")
+        .arg("--acf")
+        .arg("--acf-group-by")
+        .arg("HP")
+        .arg("--acf-bootstraps")
+        .arg("10")
+        .arg("--acf-out")
+        .arg(&acf_out)
+        .arg("tests/data/all.bam")
+        .arg("/dev/null");
+    cmd.assert().success();
+    let acf = std::fs::read_to_string(&acf_out)?;
+    assert!(acf.starts_with("feature\tgroup\tfibers\tlag\tacf\tlower\tupper\n"));
+    assert!(acf.contains("\nnuc\tall\t"));
+    assert!(acf.lines().skip(1).all(|line| {
+        let group = line.split('\t').nth(1).unwrap();
+        group == "all" || group.starts_with("HP=")
+    }));
+    Ok(())
+}
+
+#[test]
+fn test_qc_acf_confidence_out_of_range() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("ft")?;
+    cmd.arg("qc")
+        .arg("--acf")
+        .arg("--acf-confidence")
+        .arg("95")
+        .arg("tests/data/all.bam")
+        .arg("/dev/null");
+    cmd.assert().failure();
+    Ok(())
+}
+
+#[test]
+fn test_qc_json() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let json = dir.path().join("qc.json");
+    let mut cmd = Command::cargo_bin("ft")?;
+    cmd.arg("qc")
+        .arg("--acf")
+        .arg("tests/data/all.bam")
+        .arg("/dev/null")
+        .arg("--json")
+        .arg(&json);
+    cmd.assert().success();
+    let report: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&json)?)?;
+    assert_eq!(report["schema_version"], 1);
+    assert!(report["summary"]["fiber_length_n50"].is_number());
+    assert!(report["histograms"]["fiber_length"].is_array());
+    assert!(report["summary"]["labelling_bases"]["msp_at"].is_number());
+    Ok(())
+}